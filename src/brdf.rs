@@ -0,0 +1,377 @@
+use crate::color::Color;
+use crate::random::Random;
+use crate::shapes::{Material, ShadingModel};
+use crate::tuple::{dot, orthonormal_basis, reflect, Tuple};
+use std::f64::consts::PI;
+
+// Module with the physically based metallic-roughness material model, the model used
+// by glTF and most real-time engines.
+//
+// The surface is made of tiny mirrors (microfacets) pointing in different directions.
+// The reflection is the product of three terms, all functions of the half vector h
+// between the light and the eye:
+//
+//   D  the GGX distribution: how many of the microfacets point along h
+//   G  the Smith geometry term: how many of them are not hidden by the others
+//   F  the Schlick approximation of the Fresnel reflection, which gets stronger at
+//      grazing angles
+//
+// The specular part of the BRDF is D G F / (4 n.l n.v). The light that is not
+// reflected by a dielectric enters the surface and comes out as diffuse light, a
+// metal has no diffuse part.
+//
+// The functions give the BRDF per steradian. The light samples are scaled so that a
+// white Lambert surface (BRDF 1/π) lit head on reflects the intensity of the light,
+// like the Phong diffuse term, so the reflected light is π BRDF n.l intensity.
+
+// The reflectance of a dielectric seen head on, for example plastic or glass
+const DIELECTRIC_REFLECTANCE: f64 = 0.04;
+
+// A smaller roughness gives highlights that are too small to be hit by a point light
+// and too narrow for the numbers
+const MIN_ROUGHNESS: f64 = 0.03;
+
+// The alpha parameter of the GGX distribution. Squaring the roughness makes it
+// perceptually linear.
+fn alpha(roughness: f64) -> f64 {
+    let roughness = roughness.clamp(MIN_ROUGHNESS, 1.0);
+    return roughness * roughness;
+}
+
+// The density of the microfacets with the normal h, given the cosine of the angle
+// between h and the normal of the surface
+pub fn ggx_distribution(n_dot_h: f64, alpha: f64) -> f64 {
+    let alpha2 = alpha * alpha;
+    let d = n_dot_h * n_dot_h * (alpha2 - 1.0) + 1.0;
+    return alpha2 / (PI * d * d);
+}
+
+// The fraction of the microfacets that is seen both from the light and from the eye
+pub fn smith_geometry(n_dot_l: f64, n_dot_v: f64, alpha: f64) -> f64 {
+    let alpha2 = alpha * alpha;
+    let g1 = |cos: f64| 2.0 * cos / (cos + (alpha2 + (1.0 - alpha2) * cos * cos).sqrt());
+    return g1(n_dot_l) * g1(n_dot_v);
+}
+
+// The fraction of the light that is reflected, given the reflectance head on (f0) and
+// the cosine of the angle of incidence
+pub fn schlick_fresnel(f0: Color, cos: f64) -> Color {
+    let factor = (1.0 - cos.clamp(0.0, 1.0)).powi(5);
+    return f0 + (Color::color(1.0, 1.0, 1.0) - f0) * factor;
+}
+
+// The reflectance head on: a metal reflects its own color, a dielectric a little white
+fn base_reflectance(color: Color, metallic: f64) -> Color {
+    let dielectric = Color::color(
+        DIELECTRIC_REFLECTANCE,
+        DIELECTRIC_REFLECTANCE,
+        DIELECTRIC_REFLECTANCE,
+    );
+    return dielectric * (1.0 - metallic) + color * metallic;
+}
+
+fn average(color: Color) -> f64 {
+    return (color.red + color.green + color.blue) / 3.0;
+}
+
+// The BRDF of a metallic-roughness surface with the base color, for light coming from
+// the direction light and leaving towards the eye. All vectors are normalized.
+pub fn metallic_roughness(
+    color: Color,
+    metallic: f64,
+    roughness: f64,
+    light: &Tuple,
+    eye: &Tuple,
+    normal: &Tuple,
+) -> Color {
+    let black = Color::color(0.0, 0.0, 0.0);
+    let n_dot_l = dot(normal, light);
+    let n_dot_v = dot(normal, eye);
+    if n_dot_l <= 0.0 || n_dot_v <= 0.0 {
+        return black;
+    }
+    let metallic = metallic.clamp(0.0, 1.0);
+    let alpha = alpha(roughness);
+    let half = (*light + *eye).normalize();
+    let n_dot_h = dot(normal, &half).max(0.0);
+    let v_dot_h = dot(eye, &half).max(0.0);
+
+    let fresnel = schlick_fresnel(base_reflectance(color, metallic), v_dot_h);
+    let specular = fresnel
+        * (ggx_distribution(n_dot_h, alpha) * smith_geometry(n_dot_l, n_dot_v, alpha)
+            / (4.0 * n_dot_l * n_dot_v));
+    let diffuse = (Color::color(1.0, 1.0, 1.0) - fresnel) * color * ((1.0 - metallic) / PI);
+    return diffuse + specular;
+}
+
+// The probability that the specular lobe is sampled instead of the diffuse one, from
+// how much light each of them reflects towards the eye
+fn specular_probability(color: Color, metallic: f64, n_dot_v: f64) -> f64 {
+    let specular = average(schlick_fresnel(base_reflectance(color, metallic), n_dot_v));
+    let diffuse = (1.0 - metallic) * average(color) * (1.0 - specular);
+    if specular + diffuse <= 0.0 {
+        return 1.0;
+    }
+    return specular / (specular + diffuse);
+}
+
+// The probability density of the direction light when it is sampled with
+// sample_metallic_roughness
+pub fn metallic_roughness_pdf(
+    color: Color,
+    metallic: f64,
+    roughness: f64,
+    light: &Tuple,
+    eye: &Tuple,
+    normal: &Tuple,
+) -> f64 {
+    let n_dot_l = dot(normal, light);
+    if n_dot_l <= 0.0 {
+        return 0.0;
+    }
+    let metallic = metallic.clamp(0.0, 1.0);
+    let half = (*light + *eye).normalize();
+    let n_dot_h = dot(normal, &half).max(0.0);
+    let v_dot_h = dot(eye, &half);
+    let specular = if v_dot_h > 0.0 {
+        ggx_distribution(n_dot_h, alpha(roughness)) * n_dot_h / (4.0 * v_dot_h)
+    } else {
+        0.0
+    };
+    let diffuse = n_dot_l / PI;
+    let probability = specular_probability(color, metallic, dot(normal, eye).max(0.0));
+    return probability * specular + (1.0 - probability) * diffuse;
+}
+
+// A random direction for the light that is reflected towards the eye, picked with
+// importance sampling: either the normal of a microfacet is picked from the GGX
+// distribution and the eye is reflected in it, or a cosine weighted direction is
+// picked for the diffuse part. Returns the direction and the BRDF times the cosine
+// divided by the probability density, the fraction of the light from the direction
+// that reaches the eye. Returns None when the direction is below the surface.
+pub fn sample_metallic_roughness(
+    color: Color,
+    metallic: f64,
+    roughness: f64,
+    eye: &Tuple,
+    normal: &Tuple,
+    random: &mut Random,
+) -> Option<(Tuple, Color)> {
+    let metallic = metallic.clamp(0.0, 1.0);
+    let n_dot_v = dot(normal, eye);
+    if n_dot_v <= 0.0 {
+        return None;
+    }
+    let light = if random.next_f64() < specular_probability(color, metallic, n_dot_v) {
+        let alpha = alpha(roughness);
+        let u = random.next_f64();
+        let cos_theta = ((1.0 - u) / (1.0 + (alpha * alpha - 1.0) * u)).sqrt();
+        let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+        let phi = 2.0 * PI * random.next_f64();
+        let (tangent, bitangent) = orthonormal_basis(normal);
+        let half = tangent * (sin_theta * phi.cos())
+            + bitangent * (sin_theta * phi.sin())
+            + *normal * cos_theta;
+        reflect(&-*eye, &half).normalize()
+    } else {
+        random.cosine_hemisphere(normal)
+    };
+
+    let n_dot_l = dot(normal, &light);
+    let pdf = metallic_roughness_pdf(color, metallic, roughness, &light, eye, normal);
+    if n_dot_l <= 0.0 || pdf <= 0.0 {
+        return None;
+    }
+    let brdf = metallic_roughness(color, metallic, roughness, &light, eye, normal);
+    return Some((light, brdf * (n_dot_l / pdf)));
+}
+
+// The Oren-Nayar reflection of a rough diffuse surface relative to Lambert, in the
+// qualitative form without interreflections. The roughness is the standard deviation
+// of the angle of the facets in radians.
+pub fn oren_nayar(roughness: f64, light: &Tuple, eye: &Tuple, normal: &Tuple) -> f64 {
+    let sigma2 = roughness * roughness;
+    let a = 1.0 - 0.5 * sigma2 / (sigma2 + 0.33);
+    let b = 0.45 * sigma2 / (sigma2 + 0.09);
+
+    let cos_light = dot(normal, light).clamp(0.0, 1.0);
+    let cos_eye = dot(normal, eye).clamp(0.0, 1.0);
+    // The cosine of the angle between the light and the eye around the normal, from
+    // their projections on the surface
+    let light_on_surface = *light - *normal * cos_light;
+    let eye_on_surface = *eye - *normal * cos_eye;
+    let lengths = light_on_surface.magnitude() * eye_on_surface.magnitude();
+    let cos_azimuth = if lengths > 0.0 {
+        (dot(&light_on_surface, &eye_on_surface) / lengths).max(0.0)
+    } else {
+        0.0
+    };
+
+    // With alpha the larger and beta the smaller of the two angles to the normal
+    let sin_alpha = (1.0 - cos_light.min(cos_eye).powi(2)).sqrt();
+    let cos_beta = cos_light.max(cos_eye);
+    let tan_beta = (1.0 - cos_beta * cos_beta).sqrt() / cos_beta.max(f64::EPSILON);
+    return a + b * cos_azimuth * sin_alpha * tan_beta;
+}
+
+// The direction of the next bounce of a path at a surface with the material, and the
+// fraction of the light from that direction that is reflected towards the eye. The
+// Phong models only bounce the diffuse light, their highlights come from the lights.
+pub fn scatter(
+    material: &Material,
+    eye: &Tuple,
+    normal: &Tuple,
+    random: &mut Random,
+) -> Option<(Tuple, Color)> {
+    match material.model {
+        ShadingModel::Phong | ShadingModel::BlinnPhong | ShadingModel::Lambert => Some((
+            random.cosine_hemisphere(normal),
+            material.color * material.diffuse,
+        )),
+        ShadingModel::OrenNayar { roughness } => {
+            let direction = random.cosine_hemisphere(normal);
+            let factor = oren_nayar(roughness, &direction, eye, normal);
+            Some((direction, material.color * (material.diffuse * factor)))
+        }
+        ShadingModel::MetallicRoughness {
+            metallic,
+            roughness,
+        } => sample_metallic_roughness(material.color, metallic, roughness, eye, normal, random),
+    }
+}
+
+// The probability density of the direction when it is picked with scatter
+pub fn scatter_pdf(material: &Material, direction: &Tuple, eye: &Tuple, normal: &Tuple) -> f64 {
+    match material.model {
+        ShadingModel::MetallicRoughness {
+            metallic,
+            roughness,
+        } => metallic_roughness_pdf(material.color, metallic, roughness, direction, eye, normal),
+        _ => dot(direction, normal).max(0.0) / PI,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    const EPSILON: f64 = 0.00001;
+
+    fn approx_eq(lhs: Color, rhs: Color, epsilon: f64) -> bool {
+        return (lhs.red - rhs.red).abs() < epsilon
+            && (lhs.green - rhs.green).abs() < epsilon
+            && (lhs.blue - rhs.blue).abs() < epsilon;
+    }
+
+    #[test]
+    fn ggx_distribution_peaks_at_the_normal() {
+        assert!((ggx_distribution(1.0, 0.5) - 1.0 / (PI * 0.25)).abs() < EPSILON);
+        assert!(ggx_distribution(1.0, 0.1) > ggx_distribution(0.9, 0.1));
+        // A rough surface spreads the microfacets more evenly
+        assert!(ggx_distribution(1.0, 1.0) - ggx_distribution(0.5, 1.0) < EPSILON);
+    }
+
+    #[test]
+    fn smith_geometry_hides_microfacets_at_grazing_angles() {
+        assert!((smith_geometry(1.0, 1.0, 0.5) - 1.0).abs() < EPSILON);
+        assert!(smith_geometry(0.1, 1.0, 0.5) < smith_geometry(0.5, 1.0, 0.5));
+    }
+
+    #[test]
+    fn schlick_fresnel_goes_to_white_at_grazing_angles() {
+        let f0 = Color::color(0.9, 0.6, 0.2);
+        assert!(approx_eq(schlick_fresnel(f0, 1.0), f0, EPSILON));
+        assert!(approx_eq(
+            schlick_fresnel(f0, 0.0),
+            Color::color(1.0, 1.0, 1.0),
+            EPSILON
+        ));
+    }
+
+    #[test]
+    fn metal_has_no_diffuse_reflection() {
+        let normal = Tuple::vector(0.0, 1.0, 0.0);
+        let eye = Tuple::vector(0.0, 1.0, 0.0);
+        let color = Color::color(1.0, 0.8, 0.4);
+        // The light is far from the mirror direction, so only diffuse light is left
+        let light = Tuple::vector(1.0, 0.2, 0.0).normalize();
+        let metal = metallic_roughness(color, 1.0, 0.1, &light, &eye, &normal);
+        assert!(approx_eq(metal, Color::color(0.0, 0.0, 0.0), 0.001));
+        let plastic = metallic_roughness(color, 0.0, 0.1, &light, &eye, &normal);
+        assert!(plastic.red > 0.2 && plastic.blue > 0.1, "{:?}", plastic);
+        // In the mirror direction the metal reflects its color
+        let mirror = metallic_roughness(color, 1.0, 0.1, &eye, &eye, &normal);
+        assert!(mirror.red > mirror.green && mirror.green > mirror.blue);
+    }
+
+    #[test]
+    fn importance_sampling_matches_uniform_sampling() {
+        // Both estimate the fraction of the light from all directions that is
+        // reflected towards the eye
+        let normal = Tuple::vector(0.0, 0.0, 1.0);
+        let eye = Tuple::vector(0.6, 0.0, 0.8);
+        let color = Color::color(0.9, 0.5, 0.2);
+        for (metallic, roughness) in [(1.0, 0.3), (0.0, 0.5), (0.5, 0.8)] {
+            let count = 40000;
+            let mut random = Random::new(17);
+            let mut importance = 0.0;
+            let mut uniform = 0.0;
+            for _ in 0..count {
+                if let Some((light, weight)) = sample_metallic_roughness(
+                    color,
+                    metallic,
+                    roughness,
+                    &eye,
+                    &normal,
+                    &mut random,
+                ) {
+                    assert!(dot(&light, &normal) > 0.0);
+                    importance += weight.red / count as f64;
+                }
+                let light = random.uniform_hemisphere(&normal);
+                let brdf = metallic_roughness(color, metallic, roughness, &light, &eye, &normal);
+                uniform += brdf.red * dot(&light, &normal) * 2.0 * PI / count as f64;
+            }
+            assert!(importance <= 1.0, "{}", importance);
+            assert!(
+                (importance - uniform).abs() < 0.03,
+                "metallic {} roughness {}: {} and {}",
+                metallic,
+                roughness,
+                importance,
+                uniform
+            );
+        }
+    }
+
+    #[test]
+    fn oren_nayar_reflects_back_towards_the_light() {
+        let normal = Tuple::vector(0.0, 1.0, 0.0);
+        let grazing = Tuple::vector(0.866, 0.5, 0.0).normalize();
+        let opposite = Tuple::vector(-0.866, 0.5, 0.0).normalize();
+        assert_eq!(oren_nayar(0.0, &grazing, &grazing, &normal), 1.0);
+        let back = oren_nayar(0.5, &grazing, &grazing, &normal);
+        let head_on = oren_nayar(0.5, &normal, &normal, &normal);
+        assert!(back > 1.0 && head_on < 1.0);
+        // The azimuth term is zero when the eye is on the other side
+        assert!((oren_nayar(0.5, &grazing, &opposite, &normal) - head_on).abs() < EPSILON);
+    }
+
+    #[test]
+    fn phong_material_scatters_the_diffuse_color() {
+        let material = Material {
+            color: Color::color(1.0, 0.5, 0.0),
+            diffuse: 0.8,
+            ..Material::new()
+        };
+        let normal = Tuple::vector(0.0, 1.0, 0.0);
+        let (direction, weight) =
+            scatter(&material, &normal, &normal, &mut Random::new(3)).unwrap();
+        assert!(dot(&direction, &normal) >= 0.0);
+        assert!(approx_eq(weight, Color::color(0.8, 0.4, 0.0), EPSILON));
+        assert!(
+            (scatter_pdf(&material, &direction, &normal, &normal) - dot(&direction, &normal) / PI)
+                .abs()
+                < EPSILON
+        );
+    }
+}
@@ -0,0 +1,227 @@
+use crate::matrices::{to_matrix, to_tuple};
+use crate::shapes::{Normal, Sphere};
+use crate::texture::Texture;
+use crate::tuple::{cross, dot, Tuple};
+
+// Bump mapping changes the normal used for shading, so a smooth surface looks rough or
+// detailed without changing its shape. The shadows and the silhouette of the shape
+// are not changed.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Bump {
+    // Random bumps from gradient noise in object space. The scale is the size of the
+    // bumps and the strength how steep they are.
+    Noise { scale: f64, strength: f64 },
+    // A normal map in tangent space: the red, green and blue of the texture are the
+    // components of the normal along the tangent (increasing u), up in the image
+    // (decreasing v) and along the normal, from -1.0 at 0.0 to 1.0 at 1.0, so a flat
+    // surface is (0.5, 0.5, 1.0). The strength scales the tilt of the normals.
+    NormalMap { texture: Texture, strength: f64 },
+}
+
+impl Bump {
+    pub const DEFAULT_NOISE_SCALE: f64 = 0.1;
+    pub const DEFAULT_NOISE_STRENGTH: f64 = 0.5;
+
+    // The normal used for shading at a point of the object in world space. The normal
+    // is the geometric normal at the point, turned towards the eye.
+    pub fn perturb(&self, object: &Sphere, point: &Tuple, normal: &Tuple) -> Tuple {
+        let object_point = object.object_point(point);
+        match self {
+            Bump::Noise { scale, strength } => {
+                // The surface is moved along the normal by the noise, which tilts the
+                // normal against the gradient of the noise. The gradient is taken into
+                // world space like a normal.
+                let p = object_point * (1.0 / scale);
+                let gradient = noise_gradient(&Tuple::point(p.x, p.y, p.z)) * *strength;
+                let inverse = object.transformation.invert().unwrap().transpose();
+                let mut gradient = to_tuple(&(&inverse * &to_matrix(&gradient)));
+                gradient.w = 0.0;
+                let along_surface = gradient - *normal * dot(&gradient, normal);
+                return (*normal - along_surface).normalize();
+            }
+            Bump::NormalMap { texture, strength } => {
+                let color = texture.color_at(&object_point);
+                let tangent = object.tangent_at(point, texture.mapping);
+                let bitangent = cross(&tangent, normal);
+                let x = (color.red * 2.0 - 1.0) * strength;
+                let y = (color.green * 2.0 - 1.0) * strength;
+                let z = color.blue * 2.0 - 1.0;
+                let perturbed = tangent * x + bitangent * y + *normal * z;
+                if perturbed.magnitude() < 1e-9 {
+                    return *normal;
+                }
+                return perturbed.normalize();
+            }
+        }
+    }
+}
+
+// Gradient noise, as in Ken Perlin's improved noise: a smooth random function that is
+// 0.0 at the points with integer coordinates and between -1.0 and 1.0 elsewhere
+pub fn noise(point: &Tuple) -> f64 {
+    let (x0, y0, z0) = (point.x.floor(), point.y.floor(), point.z.floor());
+    let (dx, dy, dz) = (point.x - x0, point.y - y0, point.z - z0);
+    let (x0, y0, z0) = (x0 as i64, y0 as i64, z0 as i64);
+    let corner = |i: i64, j: i64, k: i64| {
+        return gradient(
+            x0 + i,
+            y0 + j,
+            z0 + k,
+            dx - i as f64,
+            dy - j as f64,
+            dz - k as f64,
+        );
+    };
+    let (u, v, w) = (fade(dx), fade(dy), fade(dz));
+    let lerp = |t: f64, a: f64, b: f64| a + t * (b - a);
+    return lerp(
+        w,
+        lerp(
+            v,
+            lerp(u, corner(0, 0, 0), corner(1, 0, 0)),
+            lerp(u, corner(0, 1, 0), corner(1, 1, 0)),
+        ),
+        lerp(
+            v,
+            lerp(u, corner(0, 0, 1), corner(1, 0, 1)),
+            lerp(u, corner(0, 1, 1), corner(1, 1, 1)),
+        ),
+    );
+}
+
+// The gradient of the noise, from central differences
+fn noise_gradient(point: &Tuple) -> Tuple {
+    const STEP: f64 = 1e-4;
+    let difference = |offset: Tuple| {
+        return (noise(&(*point + offset)) - noise(&(*point - offset))) / (2.0 * STEP);
+    };
+    return Tuple::vector(
+        difference(Tuple::vector(STEP, 0.0, 0.0)),
+        difference(Tuple::vector(0.0, STEP, 0.0)),
+        difference(Tuple::vector(0.0, 0.0, STEP)),
+    );
+}
+
+// Smooths the interpolation between the corners, so the noise has no visible grid
+fn fade(t: f64) -> f64 {
+    return t * t * t * (t * (t * 6.0 - 15.0) + 10.0);
+}
+
+// The dot product of the offset from a corner with the gradient of the corner, one of
+// the twelve directions to the edges of a cube picked by a hash of the corner
+fn gradient(x: i64, y: i64, z: i64, dx: f64, dy: f64, dz: f64) -> f64 {
+    match hash(x, y, z) % 12 {
+        0 => dx + dy,
+        1 => -dx + dy,
+        2 => dx - dy,
+        3 => -dx - dy,
+        4 => dx + dz,
+        5 => -dx + dz,
+        6 => dx - dz,
+        7 => -dx - dz,
+        8 => dy + dz,
+        9 => -dy + dz,
+        10 => dy - dz,
+        _ => -dy - dz,
+    }
+}
+
+fn hash(x: i64, y: i64, z: i64) -> u64 {
+    let mut h = (x as u64).wrapping_mul(0x9E37_79B9_7F4A_7C15)
+        ^ (y as u64).wrapping_mul(0xC2B2_AE3D_27D4_EB4F)
+        ^ (z as u64).wrapping_mul(0x1656_67B1_9E37_79F9);
+    h ^= h >> 31;
+    h = h.wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    h ^= h >> 29;
+    return h;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::canvas::Canvas;
+    use crate::color::Color;
+    use crate::matrices::Matrix;
+    use crate::texture::{Filter, Mapping};
+
+    #[test]
+    fn noise_is_smooth_and_bounded() {
+        assert_eq!(noise(&Tuple::point(1.0, -2.0, 3.0)), 0.0);
+        let mut values = Vec::new();
+        for i in 0..1000 {
+            let point = Tuple::point(i as f64 * 0.173, i as f64 * 0.071, i as f64 * -0.113);
+            let value = noise(&point);
+            assert!((-1.0..=1.0).contains(&value));
+            // Close points have close values
+            let near = noise(&(point + Tuple::vector(1e-3, 1e-3, 1e-3)));
+            assert!((near - value).abs() < 0.01);
+            values.push(value);
+        }
+        assert!(values.iter().any(|value| *value > 0.2));
+        assert!(values.iter().any(|value| *value < -0.2));
+    }
+
+    #[test]
+    fn noise_bumps_tilt_the_normal() {
+        let s = Sphere::new();
+        let point = Tuple::point(0.0, 0.6, -0.8);
+        let normal = s.normal_at(&point);
+        let flat = Bump::Noise {
+            scale: 0.1,
+            strength: 0.0,
+        };
+        assert_eq!(flat.perturb(&s, &point, &normal), normal);
+
+        let bumpy = Bump::Noise {
+            scale: 0.1,
+            strength: 1.0,
+        };
+        let perturbed = bumpy.perturb(&s, &point, &normal);
+        assert!((perturbed.magnitude() - 1.0).abs() < 1e-9);
+        assert!((perturbed - normal).magnitude() > 0.01);
+        assert!(dot(&perturbed, &normal) > 0.0);
+        // The same point gives the same normal
+        assert_eq!(bumpy.perturb(&s, &point, &normal), perturbed);
+    }
+
+    #[test]
+    fn normal_map_in_tangent_space() {
+        let mut s = Sphere::new();
+        s.transformation = Matrix::new_identity().translate(0.0, 0.0, 5.0);
+        let point = Tuple::point(0.0, 0.0, 4.0);
+        let normal = s.normal_at(&point);
+        let map = |color: Color| {
+            let mut image = Canvas::new(1, 1);
+            image.write_pixel(0, 0, color);
+            let texture = Texture::new(image, Mapping::Spherical, Filter::Nearest).unwrap();
+            return Bump::NormalMap {
+                texture,
+                strength: 1.0,
+            };
+        };
+        let flat = map(Color::color(0.5, 0.5, 1.0));
+        assert_eq!(flat.perturb(&s, &point, &normal), normal);
+
+        // Tilted towards the tangent, which is +x in the middle of the image
+        let half = 0.5_f64.sqrt();
+        let right = map(Color::color(1.0, 0.5, 1.0));
+        assert_eq!(
+            right.perturb(&s, &point, &normal),
+            Tuple::vector(half, 0.0, -half)
+        );
+        // Tilted up in the image
+        let up = map(Color::color(0.5, 1.0, 1.0));
+        assert_eq!(
+            up.perturb(&s, &point, &normal),
+            Tuple::vector(0.0, half, -half)
+        );
+        let Bump::NormalMap { texture, .. } = up else {
+            unreachable!()
+        };
+        let weak = Bump::NormalMap {
+            texture,
+            strength: 0.0,
+        };
+        assert_eq!(weak.perturb(&s, &point, &normal), normal);
+    }
+}
@@ -1,9 +1,14 @@
 use crate::matrices::{to_tuple, Matrix};
+use crate::pathtracer::{self, Integrator};
+use crate::random::Random;
 use crate::rays::Ray;
 use crate::tuple::{cross, Tuple};
 use crate::world::World;
 use crate::Canvas;
+use crate::Color;
 use std::f64;
+use std::sync::Mutex;
+use std::thread;
 
 #[derive(Debug)]
 pub struct Camera {
@@ -14,6 +19,13 @@ pub struct Camera {
     pub pixel_size: f64,
     pub field_of_view: f64,
     pub transform: Matrix<4, 4>,
+    // The number of rays per pixel. With more than one ray the rays are spread over
+    // the pixel and their colors are averaged, which smooths the edges.
+    pub samples: usize,
+    // The number of threads that render the rows of the image
+    pub threads: usize,
+    // With path tracing the samples are also the number of paths per pixel
+    pub integrator: Integrator,
 }
 
 impl Camera {
@@ -38,6 +50,9 @@ impl Camera {
             pixel_size: (half_width * 2.) / hsize as f64,
             field_of_view,
             transform: Matrix::new_identity(),
+            samples: 1,
+            threads: 1,
+            integrator: Integrator::Whitted,
         }
     }
 
@@ -59,21 +74,85 @@ impl Camera {
 
     pub fn render(&self, world: &World) -> Canvas {
         let mut image = Canvas::new(self.hsize, self.vsize);
+        let inverse = self.transform.invert().unwrap();
 
-        for y in 0..self.vsize - 1 {
-            for x in 0..self.hsize - 1 {
-                let ray = self.ray_for_pixel(x, y);
-                let color = world.color_at(&ray);
-                image.write_pixel(x, y, color);
+        // The rows are handed out one at a time, so all threads stay busy until the
+        // last rows are rendered
+        let rows = Mutex::new(image.rows_with_alpha_mut().enumerate());
+        thread::scope(|scope| {
+            for _ in 0..self.threads.max(1) {
+                scope.spawn(|| loop {
+                    let next = rows.lock().unwrap().next();
+                    let Some((y, (row, alpha_row))) = next else {
+                        break;
+                    };
+                    for (x, (pixel, alpha)) in row.iter_mut().zip(alpha_row).enumerate() {
+                        (*pixel, *alpha) = self.render_pixel(world, &inverse, x, y);
+                    }
+                });
             }
-        }
+        });
 
         image
     }
 
+    // The average color and alpha of the samples in the pixel
+    fn render_pixel(
+        &self,
+        world: &World,
+        inverse: &Matrix<4, 4>,
+        x: usize,
+        y: usize,
+    ) -> (Color, f64) {
+        if self.samples <= 1 {
+            let ray = self.ray_through(inverse, x as f64 + 0.5, y as f64 + 0.5);
+            return self.color_with_alpha_at(world, &ray, (y * self.hsize + x) as u64);
+        }
+
+        let mut color = Color::color(0., 0., 0.);
+        let mut alpha = 0.;
+        for (i, (dx, dy)) in sample_offsets(self.samples).enumerate() {
+            let ray = self.ray_through(inverse, x as f64 + dx, y as f64 + dy);
+            let seed = ((y * self.hsize + x) * self.samples + i) as u64;
+            let (sample_color, sample_alpha) = self.color_with_alpha_at(world, &ray, seed);
+            // Weight the color by the alpha so a transparent sample doesn't darken
+            // the edge of an object
+            color = color + sample_color * sample_alpha;
+            alpha += sample_alpha;
+        }
+        if alpha > 0. {
+            color = color * (1. / alpha);
+        }
+        return (color, alpha / self.samples as f64);
+    }
+
+    // The color of the ray with the integrator of the camera. The random numbers of a
+    // path tracer are seeded from the pixel and the sample, so an image is the same
+    // every time it is rendered.
+    fn color_with_alpha_at(&self, world: &World, ray: &Ray, seed: u64) -> (Color, f64) {
+        match self.integrator {
+            Integrator::Whitted => world.color_with_alpha_at(ray),
+            Integrator::PathTracing { max_depth } => {
+                pathtracer::trace(world, ray, max_depth, &mut Random::new(seed))
+            }
+            Integrator::AmbientOcclusion => world.occlusion_with_alpha_at(ray),
+        }
+    }
+
     pub fn ray_for_pixel(&self, x: usize, y: usize) -> Ray {
-        let x_offset = (x as f64 + 0.5) * self.pixel_size;
-        let y_offset = (y as f64 + 0.5) * self.pixel_size;
+        return self.ray_through(
+            &self.transform.invert().unwrap(),
+            x as f64 + 0.5,
+            y as f64 + 0.5,
+        );
+    }
+
+    // The ray through a point on the canvas, given in pixels from the top left corner.
+    // The inverse of the camera transform is passed in so it is only calculated once
+    // per image.
+    fn ray_through(&self, inverse: &Matrix<4, 4>, x: f64, y: f64) -> Ray {
+        let x_offset = x * self.pixel_size;
+        let y_offset = y * self.pixel_size;
 
         // The untransformed pixel coordinates in the world space
         // Note that the camera looks toward -z, so +x is to the *left*
@@ -82,10 +161,8 @@ impl Camera {
 
         // Transform the canvas point and the origin point using the camera's transform
         // matrix and then compute the direction vector. Note that the canvas is at z = -1
-        let pixel: Tuple =
-            to_tuple(&(&self.transform.invert().unwrap() * &Tuple::point(world_x, world_y, -1.)));
-        let origin: Tuple =
-            to_tuple(&(&self.transform.invert().unwrap() * &Tuple::point(0., 0., 0.)));
+        let pixel: Tuple = to_tuple(&(inverse * &Tuple::point(world_x, world_y, -1.)));
+        let origin: Tuple = to_tuple(&(inverse * &Tuple::point(0., 0., 0.)));
 
         Ray {
             origin,
@@ -94,10 +171,21 @@ impl Camera {
     }
 }
 
+// The positions of the samples within a pixel. The points form a Hammersley set: the
+// x offsets are evenly spaced and the y offsets are the bit-reversed sample numbers,
+// which spreads the samples evenly over the pixel for any number of samples.
+fn sample_offsets(samples: usize) -> impl Iterator<Item = (f64, f64)> {
+    let n = samples as f64;
+    return (0..samples).map(move |i| {
+        let x = (i as f64 + 0.5) / n;
+        let y = (i as u32).reverse_bits() as f64 / 2f64.powi(32);
+        (x, (y + 0.5 / n) % 1.)
+    });
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::Color;
 
     #[test]
     fn new_camera() {
@@ -220,5 +308,79 @@ mod tests {
             image.read_pixel(5, 5),
             Some(Color::color(0.38066, 0.47583, 0.2855))
         );
+        assert_eq!(image.read_alpha(5, 5), Some(1.0));
+        // The rays through the corners miss both spheres
+        assert_eq!(image.read_alpha(0, 0), Some(0.0));
+    }
+
+    #[test]
+    fn render_with_several_threads_gives_the_same_image() {
+        let world = World::default_world();
+        let mut camera = Camera::new(21, 13, f64::consts::PI / 2.);
+        camera.set_view_transformation(
+            &Tuple::point(0., 0., -5.),
+            &Tuple::point(0., 0., 0.),
+            &Tuple::vector(0., 1., 0.),
+        );
+        let single = camera.render(&world);
+        camera.threads = 4;
+        let threaded = camera.render(&world);
+        assert!(single.pixels().eq(threaded.pixels()));
+        assert!(single.alpha().eq(threaded.alpha()));
+    }
+
+    #[test]
+    fn sample_offsets_are_spread_over_the_pixel() {
+        let offsets: Vec<(f64, f64)> = sample_offsets(4).collect();
+        assert_eq!(
+            offsets,
+            vec![
+                (0.125, 0.125),
+                (0.375, 0.625),
+                (0.625, 0.375),
+                (0.875, 0.875)
+            ]
+        );
+        assert!(sample_offsets(7).all(|(x, y)| (0. ..1.).contains(&x) && (0. ..1.).contains(&y)));
+    }
+
+    #[test]
+    fn multiple_samples_smooth_the_edges() {
+        let world = World::default_world();
+        let mut camera = Camera::new(11, 11, f64::consts::PI / 2.);
+        camera.set_view_transformation(
+            &Tuple::point(0., 0., -5.),
+            &Tuple::point(0., 0., 0.),
+            &Tuple::vector(0., 1., 0.),
+        );
+        camera.samples = 16;
+        let image = camera.render(&world);
+        // Fully inside the sphere and fully outside the pixels are opaque and
+        // transparent, on the edge they are partly covered
+        assert_eq!(image.read_alpha(5, 5), Some(1.0));
+        assert_eq!(image.read_alpha(0, 0), Some(0.0));
+        assert!((0..11).any(|x| {
+            let alpha = image.read_alpha(x, 5).unwrap();
+            alpha > 0. && alpha < 1.
+        }));
+    }
+
+    #[test]
+    fn path_traced_render_is_the_same_with_several_threads() {
+        let world = World::default_world();
+        let mut camera = Camera::new(9, 7, f64::consts::PI / 2.);
+        camera.set_view_transformation(
+            &Tuple::point(0., 0., -5.),
+            &Tuple::point(0., 0., 0.),
+            &Tuple::vector(0., 1., 0.),
+        );
+        camera.integrator = Integrator::PathTracing { max_depth: 4 };
+        camera.samples = 4;
+        let single = camera.render(&world);
+        camera.threads = 3;
+        let threaded = camera.render(&world);
+        assert!(single.pixels().eq(threaded.pixels()));
+        assert_eq!(single.read_alpha(4, 3), Some(1.0));
+        assert_eq!(single.read_alpha(0, 0), Some(0.0));
     }
 }
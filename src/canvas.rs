@@ -1,78 +1,213 @@
 use crate::color::Color;
-use std::vec;
+use std::iter::Zip;
+use std::slice::{ChunksExact, ChunksExactMut, Iter, IterMut};
+use std::{error, fmt, io};
 
+// The pixels are stored row by row in one contiguous buffer, so the pixel at (x, y)
+// is at index y * width + x. The alpha (coverage) of each pixel is kept in a separate
+// buffer with the same layout: 0.0 is fully transparent and 1.0 fully opaque. The
+// colors are not premultiplied by the alpha.
+#[derive(Debug, Clone)]
 pub struct Canvas {
     pub width: usize,
     pub height: usize,
-    pixels: Vec<Vec<Color>>,
-    _x: usize,
-    _y: usize,
+    pixels: Vec<Color>,
+    alpha: Vec<f64>,
 }
 
-pub struct Coordinate {
+// Returned when a pixel outside the canvas is accessed
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct OutOfBounds {
     pub x: usize,
     pub y: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+impl fmt::Display for OutOfBounds {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "pixel ({}, {}) is outside the {}x{} canvas",
+            self.x, self.y, self.width, self.height
+        )
+    }
+}
+
+impl error::Error for OutOfBounds {}
+
+// Errors that can occur when an image file is decoded into a canvas
+#[derive(Debug)]
+pub enum DecodeError {
+    Io(io::Error),
+    Malformed(String),
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecodeError::Io(err) => write!(f, "{}", err),
+            DecodeError::Malformed(reason) => write!(f, "malformed image: {}", reason),
+        }
+    }
+}
+
+impl error::Error for DecodeError {}
+
+impl From<io::Error> for DecodeError {
+    fn from(err: io::Error) -> DecodeError {
+        DecodeError::Io(err)
+    }
 }
 
 impl Canvas {
     pub fn new(x: usize, y: usize) -> Canvas {
-        let mut c = Canvas {
+        Canvas {
             width: x,
             height: y,
-            pixels: vec![],
-            _x: 0,
-            _y: 0,
-        };
-
-        for _ in 0..y {
-            let mut row: Vec<Color> = vec![];
-            for _ in 0..x {
-                let black_pixel = Color::color(0.0, 0.0, 0.0);
-                row.push(black_pixel);
-            }
-            c.pixels.push(row);
+            pixels: vec![Color::color(0.0, 0.0, 0.0); x * y],
+            alpha: vec![1.0; x * y],
         }
-        return c;
     }
 
-    pub fn write_pixel(&mut self, x: usize, y: usize, color: Color) {
+    // A canvas where every pixel is transparent
+    pub fn new_transparent(x: usize, y: usize) -> Canvas {
+        let mut canvas = Canvas::new(x, y);
+        canvas.alpha.fill(0.0);
+        return canvas;
+    }
+
+    fn index(&self, x: usize, y: usize) -> Result<usize, OutOfBounds> {
         if x < self.width && y < self.height {
-            let row: &mut Vec<Color> = &mut self.pixels[y];
-            row[x] = color;
+            return Ok(y * self.width + x);
         }
+        return Err(OutOfBounds {
+            x,
+            y,
+            width: self.width,
+            height: self.height,
+        });
     }
 
-    pub fn read_pixel(&self, x: usize, y: usize) -> Option<Color> {
-        if x < self.width && y < self.height {
-            let row = &self.pixels[y];
-            return Some(row[x]);
-        } else {
-            return None;
+    // Pixels outside the canvas are ignored. The pixel becomes fully opaque.
+    pub fn write_pixel(&mut self, x: usize, y: usize, color: Color) {
+        self.write_pixel_alpha(x, y, color, 1.0);
+    }
+
+    pub fn write_pixel_alpha(&mut self, x: usize, y: usize, color: Color, alpha: f64) {
+        if let Ok(index) = self.index(x, y) {
+            self.pixels[index] = color;
+            self.alpha[index] = alpha.clamp(0.0, 1.0);
         }
     }
-}
 
-impl Iterator for Canvas {
-    type Item = crate::color::Color;
+    pub fn read_alpha(&self, x: usize, y: usize) -> Option<f64> {
+        return self.index(x, y).ok().map(|index| self.alpha[index]);
+    }
+
+    // The alpha of all pixels, row by row
+    pub fn alpha(&self) -> Iter<'_, f64> {
+        return self.alpha.iter();
+    }
+
+    // True if any pixel is not fully opaque
+    pub fn has_transparency(&self) -> bool {
+        return self.alpha.iter().any(|alpha| *alpha < 1.0);
+    }
+
+    pub fn read_pixel(&self, x: usize, y: usize) -> Option<Color> {
+        return self.pixel(x, y).ok().copied();
+    }
+
+    pub fn pixel(&self, x: usize, y: usize) -> Result<&Color, OutOfBounds> {
+        let index = self.index(x, y)?;
+        return Ok(&self.pixels[index]);
+    }
+
+    pub fn pixel_mut(&mut self, x: usize, y: usize) -> Result<&mut Color, OutOfBounds> {
+        let index = self.index(x, y)?;
+        return Ok(&mut self.pixels[index]);
+    }
+
+    // All pixels, row by row
+    pub fn pixels(&self) -> Iter<'_, Color> {
+        return self.pixels.iter();
+    }
+
+    pub fn pixels_mut(&mut self) -> IterMut<'_, Color> {
+        return self.pixels.iter_mut();
+    }
 
-    fn next(&mut self) -> Option<Self::Item> {
-        let x = self._x;
-        let y = self._y;
+    pub fn row(&self, y: usize) -> Option<&[Color]> {
+        if y < self.height {
+            return Some(&self.pixels[y * self.width..(y + 1) * self.width]);
+        }
+        return None;
+    }
 
-        if self._y == self.height {
-            self._y = 0;
-            return None;
+    pub fn row_mut(&mut self, y: usize) -> Option<&mut [Color]> {
+        if y < self.height {
+            return Some(&mut self.pixels[y * self.width..(y + 1) * self.width]);
         }
+        return None;
+    }
 
-        self._x = self._x + 1;
-        if self._x == self.width {
-            self._x = 0;
-            self._y = self._y + 1;
+    // The rows from the top to the bottom of the canvas
+    pub fn rows(&self) -> ChunksExact<'_, Color> {
+        return self.pixels.chunks_exact(self.width.max(1));
+    }
+
+    // The rows as separate mutable slices, so different rows can be written at the
+    // same time, for example by different threads
+    pub fn rows_mut(&mut self) -> ChunksExactMut<'_, Color> {
+        return self.pixels.chunks_exact_mut(self.width.max(1));
+    }
+
+    // The rows together with the alpha of their pixels
+    pub fn rows_with_alpha_mut(
+        &mut self,
+    ) -> Zip<ChunksExactMut<'_, Color>, ChunksExactMut<'_, f64>> {
+        let width = self.width.max(1);
+        return self
+            .pixels
+            .chunks_exact_mut(width)
+            .zip(self.alpha.chunks_exact_mut(width));
+    }
+
+    // Composite the source canvas over this canvas with the Porter-Duff "over" operator.
+    // The top left corner of the source is placed at (x, y); the parts of the source
+    // that fall outside this canvas are clipped.
+    pub fn composite_over(&mut self, source: &Canvas, x: usize, y: usize) {
+        let height = source.height.min(self.height.saturating_sub(y));
+        let width = source.width.min(self.width.saturating_sub(x));
+        for source_y in 0..height {
+            for source_x in 0..width {
+                let index = (y + source_y) * self.width + x + source_x;
+                let source_index = source_y * source.width + source_x;
+                let (color, alpha) = over(
+                    source.pixels[source_index],
+                    source.alpha[source_index],
+                    self.pixels[index],
+                    self.alpha[index],
+                );
+                self.pixels[index] = color;
+                self.alpha[index] = alpha;
+            }
         }
-        return self.read_pixel(x, y);
     }
 }
 
+// The Porter-Duff "over" operator for colors that are not premultiplied
+pub fn over(source: Color, source_alpha: f64, dest: Color, dest_alpha: f64) -> (Color, f64) {
+    let alpha = source_alpha + dest_alpha * (1.0 - source_alpha);
+    if alpha <= 0.0 {
+        return (Color::color(0.0, 0.0, 0.0), 0.0);
+    }
+    let color =
+        (source * source_alpha + dest * (dest_alpha * (1.0 - source_alpha))) * (1.0 / alpha);
+    return (color, alpha);
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -105,8 +240,8 @@ mod tests {
         let red = Color::color(1.0, 0.0, 0.0);
         c.write_pixel(21, 3, red);
         c.write_pixel(2, 13, red);
-        assert!(c.read_pixel(20, 3) == None);
-        assert!(c.read_pixel(2, 13) == None);
+        assert!(c.read_pixel(20, 3).is_none());
+        assert!(c.read_pixel(2, 13).is_none());
     }
 
     #[test]
@@ -116,10 +251,129 @@ mod tests {
         c.write_pixel(10, 3, red);
         c.write_pixel(2, 5, red);
 
+        let mut pixels = c.pixels();
         for y in 0..ROWS {
             for x in 0..COLS {
-                assert!(c.next() == c.read_pixel(x, y));
+                assert!(pixels.next().copied() == c.read_pixel(x, y));
             }
         }
+        assert!(pixels.next().is_none());
+
+        // The canvas is only borrowed, so it can be iterated again
+        assert_eq!(c.pixels().filter(|p| **p == red).count(), 2);
+    }
+
+    #[test]
+    fn fallible_pixel_access() {
+        let mut c = Canvas::new(COLS, ROWS);
+        *c.pixel_mut(3, 4).unwrap() = Color::color(0.0, 1.0, 0.0);
+        assert_eq!(c.pixel(3, 4), Ok(&Color::color(0.0, 1.0, 0.0)));
+        assert_eq!(
+            c.pixel(COLS, 0),
+            Err(OutOfBounds {
+                x: COLS,
+                y: 0,
+                width: COLS,
+                height: ROWS
+            })
+        );
+        assert!(c.pixel_mut(0, ROWS).is_err());
+    }
+
+    #[test]
+    fn canvas_rows() {
+        let mut c = Canvas::new(COLS, ROWS);
+        c.row_mut(2).unwrap()[5] = Color::color(0.0, 0.0, 1.0);
+        assert_eq!(c.read_pixel(5, 2), Some(Color::color(0.0, 0.0, 1.0)));
+        assert_eq!(c.row(2).unwrap().len(), COLS);
+        assert!(c.row(ROWS).is_none());
+        assert_eq!(c.rows().count(), ROWS);
+        assert_eq!(c.rows().nth(2).unwrap()[5], Color::color(0.0, 0.0, 1.0));
+    }
+
+    #[test]
+    fn rows_can_be_written_from_different_threads() {
+        let mut c = Canvas::new(COLS, ROWS);
+        std::thread::scope(|scope| {
+            for (y, row) in c.rows_mut().enumerate() {
+                scope.spawn(move || {
+                    for pixel in row.iter_mut() {
+                        *pixel = Color::color(y as f64, 0.0, 0.0);
+                    }
+                });
+            }
+        });
+        assert_eq!(c.read_pixel(COLS - 1, 7), Some(Color::color(7.0, 0.0, 0.0)));
+    }
+
+    #[test]
+    fn canvas_alpha() {
+        let mut c = Canvas::new(COLS, ROWS);
+        assert_eq!(c.read_alpha(0, 0), Some(1.0));
+        assert!(!c.has_transparency());
+        c.write_pixel_alpha(1, 2, Color::color(1.0, 0.0, 0.0), 0.25);
+        assert_eq!(c.read_alpha(1, 2), Some(0.25));
+        assert!(c.has_transparency());
+        assert!(c.read_alpha(COLS, 0).is_none());
+
+        let t = Canvas::new_transparent(2, 2);
+        assert!(t.alpha().all(|alpha| *alpha == 0.0));
+    }
+
+    #[test]
+    fn porter_duff_over() {
+        let red = Color::color(1.0, 0.0, 0.0);
+        let blue = Color::color(0.0, 0.0, 1.0);
+        assert_eq!(over(red, 1.0, blue, 1.0), (red, 1.0));
+        assert_eq!(over(red, 0.0, blue, 1.0), (blue, 1.0));
+        assert_eq!(
+            over(red, 0.5, blue, 1.0),
+            (Color::color(0.5, 0.0, 0.5), 1.0)
+        );
+        let (color, alpha) = over(red, 0.5, blue, 0.5);
+        assert_eq!(alpha, 0.75);
+        assert_eq!(color, Color::color(2.0 / 3.0, 0.0, 1.0 / 3.0));
+        assert_eq!(over(red, 0.0, blue, 0.0).1, 0.0);
+    }
+
+    #[test]
+    fn composite_canvas_over_canvas() {
+        let mut background = Canvas::new(3, 3);
+        let mut layer = Canvas::new_transparent(2, 2);
+        layer.write_pixel(0, 0, Color::color(1.0, 1.0, 1.0));
+        layer.write_pixel_alpha(1, 1, Color::color(1.0, 1.0, 1.0), 0.5);
+        background.composite_over(&layer, 1, 1);
+
+        assert_eq!(
+            background.read_pixel(0, 0),
+            Some(Color::color(0.0, 0.0, 0.0))
+        );
+        assert_eq!(
+            background.read_pixel(1, 1),
+            Some(Color::color(1.0, 1.0, 1.0))
+        );
+        assert_eq!(
+            background.read_pixel(2, 1),
+            Some(Color::color(0.0, 0.0, 0.0))
+        );
+        assert_eq!(
+            background.read_pixel(2, 2),
+            Some(Color::color(0.5, 0.5, 0.5))
+        );
+        assert!(!background.has_transparency());
+
+        // The parts outside the canvas are clipped
+        background.composite_over(&layer, 2, 2);
+        assert_eq!(
+            background.read_pixel(2, 2),
+            Some(Color::color(1.0, 1.0, 1.0))
+        );
+
+        // A source far outside the canvas does not overflow the coordinates
+        let before = background.clone();
+        background.composite_over(&layer, usize::MAX, 0);
+        background.composite_over(&layer, 0, usize::MAX);
+        background.composite_over(&layer, usize::MAX, usize::MAX);
+        assert!(background.pixels().eq(before.pixels()));
     }
 }
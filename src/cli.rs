@@ -0,0 +1,662 @@
+use ray_tracer::output::{Format, Operator, OutputTransform};
+use ray_tracer::world::AmbientOcclusion;
+use ray_tracer::Integrator;
+use std::f64;
+use std::thread;
+
+// Module for the command line interface.
+//
+// The commands and the options are described in the tables below. The same tables are
+// used to parse the command line and to generate the --help text, so they cannot get
+// out of sync. Options can be given anywhere on the command line, as "--name value",
+// "--name=value" or, for options with a short name, "-n value". Everything after "--"
+// is treated as positional arguments.
+
+pub struct Command {
+    pub name: &'static str,
+    // The names of the positional arguments, for example "<scene-file>"
+    pub arguments: &'static [&'static str],
+    pub help: &'static str,
+}
+
+pub const COMMANDS: &[Command] = &[
+    Command {
+        name: "trajectory",
+        arguments: &[],
+        help: "Create an image of a projectile's trajectory",
+    },
+    Command {
+        name: "clock",
+        arguments: &[],
+        help: "Create a simple clock case with a dot for each hour",
+    },
+    Command {
+        name: "shadow",
+        arguments: &[],
+        help: "Primitive ray tracing of a sphere's 'shadow' on a wall",
+    },
+    Command {
+        name: "sphere",
+        arguments: &[],
+        help: "First ray tracing using Phong reflection model",
+    },
+    Command {
+        name: "world",
+        arguments: &[],
+        help: "Create a ray traced image of a pre-configured world",
+    },
+    Command {
+        name: "planets",
+        arguments: &[],
+        help: "Create a ray traced animation of two planets",
+    },
+    Command {
+        name: "render",
+        arguments: &["<scene-file>"],
+        help: "Render a scene file",
+    },
+];
+
+// The options given on the command line. The options that are not given are None, or
+// have their default value.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Options {
+    pub output: Option<String>,
+    pub width: Option<usize>,
+    pub height: Option<usize>,
+    // In radians
+    pub field_of_view: Option<f64>,
+    pub samples: usize,
+    pub threads: usize,
+    pub integrator: Option<Integrator>,
+    pub max_depth: Option<usize>,
+    // The ambient occlusion settings, None without ambient occlusion
+    pub ambient_occlusion: Option<AmbientOcclusion>,
+    pub format: Option<Format>,
+    // The output transform: the exposure in stops, the tone mapping operator and
+    // whether the colors are sRGB encoded for the 8 and 16 bit formats
+    pub exposure: f64,
+    pub tonemap: Operator,
+    pub srgb: bool,
+    // The first and the last frame to render, both included
+    pub frames: Option<(usize, usize)>,
+    pub help: bool,
+}
+
+impl Options {
+    pub fn new() -> Options {
+        // The output transform defaults to the one of the library, which keeps the
+        // colors as they are
+        let transform = OutputTransform::new();
+        Options {
+            output: None,
+            width: None,
+            height: None,
+            field_of_view: None,
+            samples: 1,
+            threads: thread::available_parallelism()
+                .map(|threads| threads.get())
+                .unwrap_or(1),
+            integrator: None,
+            max_depth: None,
+            ambient_occlusion: None,
+            format: None,
+            exposure: transform.exposure,
+            tonemap: transform.operator,
+            srgb: transform.srgb,
+            frames: None,
+            help: false,
+        }
+    }
+}
+
+struct OptionSpec {
+    long: &'static str,
+    short: Option<char>,
+    // The name of the value in the help text, or None for a flag
+    value: Option<&'static str>,
+    help: &'static str,
+    // Store the value (the empty string for a flag) in the options
+    apply: fn(&mut Options, &str) -> Result<(), String>,
+}
+
+const OPTIONS: &[OptionSpec] = &[
+    OptionSpec {
+        long: "output",
+        short: Some('o'),
+        value: Some("FILE"),
+        help: "Write the image to FILE instead of the default file name",
+        apply: |options, value| {
+            options.output = Some(value.to_string());
+            return Ok(());
+        },
+    },
+    OptionSpec {
+        long: "width",
+        short: None,
+        value: Some("PIXELS"),
+        help: "Width of the image (ray traced commands)",
+        apply: |options, value| {
+            options.width = Some(parse_positive(value)?);
+            return Ok(());
+        },
+    },
+    OptionSpec {
+        long: "height",
+        short: None,
+        value: Some("PIXELS"),
+        help: "Height of the image (ray traced commands)",
+        apply: |options, value| {
+            options.height = Some(parse_positive(value)?);
+            return Ok(());
+        },
+    },
+    OptionSpec {
+        long: "fov",
+        short: None,
+        value: Some("DEGREES"),
+        help: "Field of view of the camera (ray traced commands)",
+        apply: |options, value| {
+            let degrees = parse_number(value)?;
+            if degrees <= 0.0 || degrees >= 180.0 {
+                return Err(format!(
+                    "the field of view must be between 0 and 180 degrees, got {}",
+                    value
+                ));
+            }
+            options.field_of_view = Some(degrees.to_radians());
+            return Ok(());
+        },
+    },
+    OptionSpec {
+        long: "samples",
+        short: Some('s'),
+        value: Some("N"),
+        help: "Number of rays per pixel for anti-aliasing, or paths per pixel \
+               with path tracing (default 1)",
+        apply: |options, value| {
+            options.samples = parse_positive(value)?;
+            return Ok(());
+        },
+    },
+    OptionSpec {
+        long: "threads",
+        short: Some('j'),
+        value: Some("N"),
+        help: "Number of threads used for rendering (default: one per CPU)",
+        apply: |options, value| {
+            options.threads = parse_positive(value)?;
+            return Ok(());
+        },
+    },
+    OptionSpec {
+        long: "integrator",
+        short: None,
+        value: Some("NAME"),
+        help: "How rays are shaded: whitted (Phong shading, the default), path \
+               (path tracing with indirect light) or ao (only the ambient occlusion)",
+        apply: |options, value| {
+            let integrator =
+                Integrator::from_name(value).ok_or(format!("unknown integrator '{}'", value))?;
+            options.integrator = Some(integrator);
+            return Ok(());
+        },
+    },
+    OptionSpec {
+        long: "max-depth",
+        short: None,
+        value: Some("N"),
+        help: "Maximum number of bounces of a path (default 8)",
+        apply: |options, value| {
+            options.max_depth = Some(parse_positive(value)?);
+            return Ok(());
+        },
+    },
+    OptionSpec {
+        long: "ao",
+        short: None,
+        value: Some("N"),
+        help: "Darken the ambient light in corners with ambient occlusion, \
+               shooting N rays from each hit",
+        apply: |options, value| {
+            let samples = parse_positive(value)?;
+            let settings = options.ambient_occlusion.unwrap_or_default();
+            options.ambient_occlusion = Some(AmbientOcclusion {
+                samples,
+                ..settings
+            });
+            return Ok(());
+        },
+    },
+    OptionSpec {
+        long: "ao-distance",
+        short: None,
+        value: Some("DISTANCE"),
+        help: "Objects further away do not occlude, implies --ao 16 (default 1)",
+        apply: |options, value| {
+            let distance = parse_number(value)?;
+            if distance <= 0.0 {
+                return Err(format!(
+                    "the distance must be greater than 0, got {}",
+                    value
+                ));
+            }
+            let settings = options.ambient_occlusion.unwrap_or_default();
+            options.ambient_occlusion = Some(AmbientOcclusion {
+                distance,
+                ..settings
+            });
+            return Ok(());
+        },
+    },
+    OptionSpec {
+        long: "format",
+        short: Some('f'),
+        value: Some("FORMAT"),
+        help: "Image format: png, png16, ppm, ppm-ascii, pfm or hdr \
+               (default: from the file extension)",
+        apply: |options, value| {
+            let format =
+                Format::from_name(value).ok_or(format!("unknown image format '{}'", value))?;
+            options.format = Some(format);
+            return Ok(());
+        },
+    },
+    OptionSpec {
+        long: "exposure",
+        short: None,
+        value: Some("STOPS"),
+        help: "Brighten (positive) or darken (negative) the image before it is saved \
+               (default 0)",
+        apply: |options, value| {
+            options.exposure = parse_number(value)?;
+            return Ok(());
+        },
+    },
+    OptionSpec {
+        long: "tonemap",
+        short: None,
+        value: Some("OPERATOR"),
+        help: "How bright colors are fitted into the image: clamp (the default), \
+               reinhard, reinhard-extended[:WHITE] (white point 4 by default) or filmic",
+        apply: |options, value| {
+            let operator = Operator::from_name(value)
+                .ok_or(format!("unknown tone mapping operator '{}'", value))?;
+            options.tonemap = operator;
+            return Ok(());
+        },
+    },
+    OptionSpec {
+        long: "srgb",
+        short: None,
+        value: Some("on|off"),
+        help: "Encode the colors of png, ppm and gif images with the sRGB transfer \
+               function (default off)",
+        apply: |options, value| {
+            options.srgb = match value {
+                "on" => true,
+                "off" => false,
+                _ => return Err(format!("expected on or off, got '{}'", value)),
+            };
+            return Ok(());
+        },
+    },
+    OptionSpec {
+        long: "frames",
+        short: None,
+        value: Some("FIRST-LAST"),
+        help: "Render only the frames FIRST to LAST of an animation, or a single frame",
+        apply: |options, value| {
+            let (first, last) = match value.split_once('-') {
+                Some((first, last)) => (parse_integer(first)?, parse_integer(last)?),
+                None => (parse_integer(value)?, parse_integer(value)?),
+            };
+            if first > last {
+                return Err(format!("invalid frame range '{}'", value));
+            }
+            options.frames = Some((first, last));
+            return Ok(());
+        },
+    },
+    OptionSpec {
+        long: "help",
+        short: Some('h'),
+        value: None,
+        help: "Print this help and exit",
+        apply: |options, _| {
+            options.help = true;
+            return Ok(());
+        },
+    },
+];
+
+fn parse_integer(value: &str) -> Result<usize, String> {
+    return value
+        .trim()
+        .parse()
+        .map_err(|_| format!("expected a non-negative integer, got '{}'", value));
+}
+
+fn parse_positive(value: &str) -> Result<usize, String> {
+    match parse_integer(value)? {
+        0 => Err("expected a number greater than 0, got '0'".to_string()),
+        number => Ok(number),
+    }
+}
+
+fn parse_number(value: &str) -> Result<f64, String> {
+    return value
+        .parse::<f64>()
+        .ok()
+        .filter(|number| number.is_finite())
+        .ok_or(format!("expected a number, got '{}'", value));
+}
+
+pub struct Config {
+    // None when only --help is given
+    pub command: Option<&'static Command>,
+    pub arguments: Vec<String>,
+    pub options: Options,
+}
+
+impl Config {
+    pub fn build(args: &[String]) -> Result<Config, String> {
+        let mut options = Options::new();
+        let mut positional = Vec::new();
+        let mut args = args.iter().skip(1);
+
+        while let Some(arg) = args.next() {
+            if arg == "--" {
+                positional.extend(args.by_ref().cloned());
+                break;
+            }
+            let (spec, inline_value) = if let Some(long) = arg.strip_prefix("--") {
+                let (name, value) = match long.split_once('=') {
+                    Some((name, value)) => (name, Some(value)),
+                    None => (long, None),
+                };
+                let spec = OPTIONS.iter().find(|spec| spec.long == name);
+                (spec.ok_or(format!("unknown option '--{}'", name))?, value)
+            } else if arg.len() == 2 && arg.starts_with('-') {
+                let short = arg.chars().nth(1);
+                let spec = OPTIONS.iter().find(|spec| spec.short == short);
+                (spec.ok_or(format!("unknown option '{}'", arg))?, None)
+            } else {
+                positional.push(arg.clone());
+                continue;
+            };
+
+            let value = match (spec.value, inline_value) {
+                (Some(_), Some(value)) => value.to_string(),
+                (Some(name), None) => args
+                    .next()
+                    .cloned()
+                    .ok_or(format!("option '--{}' expects a value {}", spec.long, name))?,
+                (None, Some(_)) => {
+                    return Err(format!("option '--{}' does not take a value", spec.long))
+                }
+                (None, None) => String::new(),
+            };
+            (spec.apply)(&mut options, &value)
+                .map_err(|err| format!("invalid value for '--{}': {}", spec.long, err))?;
+        }
+
+        let mut positional = positional.into_iter();
+        let command = match positional.next() {
+            Some(name) => Some(
+                COMMANDS
+                    .iter()
+                    .find(|command| command.name == name)
+                    .ok_or(format!("unknown command '{}'", name))?,
+            ),
+            None if options.help => None,
+            None => return Err("missing command".to_string()),
+        };
+        let arguments: Vec<String> = positional.collect();
+        if let Some(command) = command {
+            if arguments.len() != command.arguments.len() {
+                return Err(
+                    format!("usage: {} {}", command.name, command.arguments.join(" "))
+                        .trim_end()
+                        .to_string(),
+                );
+            }
+        }
+
+        Ok(Config {
+            command,
+            arguments,
+            options,
+        })
+    }
+}
+
+// The help text, generated from the tables of commands and options
+pub fn usage() -> String {
+    let mut text = String::from("Usage: ray-tracer [OPTIONS] <command> [arguments]\n\nCommands:\n");
+    let commands: Vec<(String, &str)> = COMMANDS
+        .iter()
+        .map(|command| {
+            let mut name = command.name.to_string();
+            for argument in command.arguments {
+                name = format!("{} {}", name, argument);
+            }
+            (name, command.help)
+        })
+        .collect();
+    append_table(&mut text, &commands);
+
+    text.push_str("\nOptions:\n");
+    let options: Vec<(String, &str)> = OPTIONS
+        .iter()
+        .map(|spec| {
+            let short = match spec.short {
+                Some(short) => format!("-{}, ", short),
+                None => "    ".to_string(),
+            };
+            let value = spec
+                .value
+                .map(|name| format!(" {}", name))
+                .unwrap_or_default();
+            (format!("{}--{}{}", short, spec.long, value), spec.help)
+        })
+        .collect();
+    append_table(&mut text, &options);
+    return text;
+}
+
+fn append_table(text: &mut String, rows: &[(String, &str)]) {
+    let width = rows.iter().map(|(name, _)| name.len()).max().unwrap_or(0);
+    for (name, help) in rows {
+        text.push_str(&format!("  {:width$}  {}\n", name, help, width = width));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ray_tracer::output::BitDepth;
+
+    fn build(args: &[&str]) -> Result<Config, String> {
+        let mut all = vec!["ray-tracer".to_string()];
+        all.extend(args.iter().map(|arg| arg.to_string()));
+        return Config::build(&all);
+    }
+
+    #[test]
+    fn command_without_options() {
+        let config = build(&["world"]).unwrap();
+        assert_eq!(config.command.unwrap().name, "world");
+        assert!(config.arguments.is_empty());
+        assert_eq!(config.options.output, None);
+        assert_eq!(config.options.samples, 1);
+        assert!(!config.options.srgb);
+        assert!(config.options.threads >= 1);
+    }
+
+    #[test]
+    fn options_in_all_forms() {
+        let config = build(&[
+            "-o",
+            "out.png",
+            "render",
+            "--width=320",
+            "--height",
+            "200",
+            "scene.yml",
+            "--fov",
+            "90",
+            "-s",
+            "16",
+            "-j",
+            "3",
+            "--format",
+            "png16",
+            "--frames",
+            "10-19",
+            "--integrator",
+            "path",
+            "--max-depth=5",
+            "--exposure",
+            "-1.5",
+            "--tonemap=filmic",
+            "--srgb",
+            "on",
+        ])
+        .unwrap();
+        assert_eq!(config.command.unwrap().name, "render");
+        assert_eq!(config.arguments, vec!["scene.yml"]);
+        let options = config.options;
+        assert_eq!(options.output, Some("out.png".to_string()));
+        assert_eq!(options.width, Some(320));
+        assert_eq!(options.height, Some(200));
+        assert!((options.field_of_view.unwrap() - f64::consts::PI / 2.).abs() < 0.00001);
+        assert_eq!(options.samples, 16);
+        assert_eq!(options.threads, 3);
+        assert_eq!(options.format, Some(Format::Png(BitDepth::Sixteen)));
+        assert_eq!(options.frames, Some((10, 19)));
+        assert_eq!(
+            options.integrator,
+            Some(Integrator::PathTracing { max_depth: 8 })
+        );
+        assert_eq!(options.max_depth, Some(5));
+        assert_eq!(options.exposure, -1.5);
+        assert_eq!(options.tonemap, Operator::Filmic);
+        assert!(options.srgb);
+        assert!(!options.help);
+    }
+
+    #[test]
+    fn single_frame_and_positional_after_double_dash() {
+        let config = build(&["--frames", "7", "render", "--", "--odd-name.yml"]).unwrap();
+        assert_eq!(config.options.frames, Some((7, 7)));
+        assert_eq!(config.arguments, vec!["--odd-name.yml"]);
+    }
+
+    #[test]
+    fn extended_reinhard_with_a_white_point() {
+        let config = build(&["world", "--tonemap", "reinhard-extended:2"]).unwrap();
+        assert_eq!(
+            config.options.tonemap,
+            Operator::ReinhardExtended { white: 2.0 }
+        );
+    }
+
+    #[test]
+    fn help_does_not_need_a_command() {
+        let config = build(&["--help"]).unwrap();
+        assert!(config.command.is_none());
+        assert!(config.options.help);
+        assert!(build(&["-h", "world"]).unwrap().options.help);
+    }
+
+    #[test]
+    fn invalid_command_lines() {
+        assert_eq!(build(&[]).err(), Some("missing command".to_string()));
+        assert_eq!(
+            build(&["paint"]).err(),
+            Some("unknown command 'paint'".to_string())
+        );
+        assert_eq!(
+            build(&["render"]).err(),
+            Some("usage: render <scene-file>".to_string())
+        );
+        assert_eq!(
+            build(&["world", "extra"]).err(),
+            Some("usage: world".to_string())
+        );
+        assert_eq!(
+            build(&["world", "--colour"]).err(),
+            Some("unknown option '--colour'".to_string())
+        );
+        assert_eq!(
+            build(&["world", "-x"]).err(),
+            Some("unknown option '-x'".to_string())
+        );
+        assert_eq!(
+            build(&["world", "--output"]).err(),
+            Some("option '--output' expects a value FILE".to_string())
+        );
+        assert_eq!(
+            build(&["world", "--help=yes"]).err(),
+            Some("option '--help' does not take a value".to_string())
+        );
+        assert_eq!(
+            build(&["world", "-s", "0"]).err(),
+            Some(
+                "invalid value for '--samples': expected a number greater than 0, got '0'"
+                    .to_string()
+            )
+        );
+        assert!(build(&["world", "--fov", "180"]).is_err());
+        assert!(build(&["world", "--frames", "9-3"]).is_err());
+        assert!(build(&["world", "--ao-distance", "0"]).is_err());
+        assert!(build(&["world", "--format", "bmp"]).is_err());
+        assert!(build(&["world", "--tonemap", "aces"]).is_err());
+        assert!(build(&["world", "--tonemap", "reinhard-extended:-1"]).is_err());
+        assert!(build(&["world", "--exposure", "inf"]).is_err());
+        assert!(build(&["world", "--srgb", "yes"]).is_err());
+        assert_eq!(
+            build(&["world", "--integrator", "photon"]).err(),
+            Some("invalid value for '--integrator': unknown integrator 'photon'".to_string())
+        );
+    }
+
+    #[test]
+    fn ambient_occlusion_options() {
+        assert_eq!(build(&["world"]).unwrap().options.ambient_occlusion, None);
+        assert_eq!(
+            build(&["world", "--ao", "32"])
+                .unwrap()
+                .options
+                .ambient_occlusion,
+            Some(AmbientOcclusion {
+                samples: 32,
+                distance: 1.
+            })
+        );
+        assert_eq!(
+            build(&["world", "--ao-distance", "2.5", "--ao=8"])
+                .unwrap()
+                .options
+                .ambient_occlusion,
+            Some(AmbientOcclusion {
+                samples: 8,
+                distance: 2.5
+            })
+        );
+    }
+
+    #[test]
+    fn usage_lists_all_commands_and_options() {
+        let text = usage();
+        for command in COMMANDS {
+            assert!(text.contains(command.name));
+        }
+        for spec in OPTIONS {
+            assert!(text.contains(&format!("--{}", spec.long)));
+        }
+        assert!(text.contains("  render <scene-file>  "));
+        assert!(text.contains("-o, --output FILE"));
+    }
+}
@@ -18,7 +18,7 @@ fn normalize_u8_part(part: f64) -> u8 {
 }
 
 impl Color {
-    pub(crate) fn color(red: f64, green: f64, blue: f64) -> Color {
+    pub fn color(red: f64, green: f64, blue: f64) -> Color {
         return Color { red, green, blue };
     }
 
@@ -87,10 +87,6 @@ impl PartialEq for Color {
             && (self.green - other.green).abs() < EPSILON
             && (self.blue - other.blue).abs() < EPSILON
     }
-
-    fn ne(&self, other: &Self) -> bool {
-        return !self.eq(other);
-    }
 }
 
 #[cfg(test)]
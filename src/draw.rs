@@ -0,0 +1,285 @@
+use crate::canvas::{over, Canvas};
+use crate::color::Color;
+
+// Module with 2D drawing primitives on a canvas, used by the demos and for debug
+// overlays on top of rendered images.
+//
+// The coordinates are given in pixels with the origin in the top left corner and y
+// pointing down, and the center of a pixel is at integer coordinates. Use `y_up` to
+// convert from a coordinate system where y points up. The edges of lines and circles
+// are anti-aliased: a pixel that is only partly covered is blended with the existing
+// color using the coverage as alpha.
+
+impl Canvas {
+    // Convert a point where y points up from the bottom of the canvas to canvas
+    // coordinates
+    pub fn y_up(&self, x: f64, y: f64) -> (f64, f64) {
+        return (x, (self.height as f64 - 1.0) - y);
+    }
+
+    // Blend the color over the pixel with the given coverage (0.0 - 1.0). Pixels
+    // outside the canvas are ignored.
+    pub fn blend_pixel(&mut self, x: i64, y: i64, color: Color, coverage: f64) {
+        if x < 0 || y < 0 || coverage <= 0.0 {
+            return;
+        }
+        let (x, y) = (x as usize, y as usize);
+        if let (Some(dest), Some(dest_alpha)) = (self.read_pixel(x, y), self.read_alpha(x, y)) {
+            let (color, alpha) = over(color, coverage.min(1.0), dest, dest_alpha);
+            self.write_pixel_alpha(x, y, color, alpha);
+        }
+    }
+
+    // Draw an anti-aliased line with Xiaolin Wu's algorithm. Only the part of the line
+    // over the canvas is walked, so a long line outside the canvas is quick to draw.
+    pub fn draw_line(&mut self, x0: f64, y0: f64, x1: f64, y1: f64, color: Color) {
+        if ![x0, y0, x1, y1].iter().all(|value| value.is_finite()) {
+            return;
+        }
+        let steep = (y1 - y0).abs() > (x1 - x0).abs();
+        // Walk along the major axis from left to right
+        let (mut x0, mut y0, mut x1, mut y1) = if steep {
+            (y0, x0, y1, x1)
+        } else {
+            (x0, y0, x1, y1)
+        };
+        if x0 > x1 {
+            (x0, x1) = (x1, x0);
+            (y0, y1) = (y1, y0);
+        }
+        let dx = x1 - x0;
+        let gradient = if dx == 0.0 { 1.0 } else { (y1 - y0) / dx };
+        let size = if steep { self.height } else { self.width } as f64;
+
+        let mut plot = |major: f64, minor: f64, coverage: f64| {
+            let (x, y) = if steep {
+                (minor, major)
+            } else {
+                (major, minor)
+            };
+            self.blend_pixel(x as i64, y as i64, color, coverage);
+        };
+
+        // The end points only cover the part of their pixel that is inside the line
+        let mut endpoint = |x: f64, y: f64, gap: f64| {
+            let major = x.round();
+            let minor = y + gradient * (major - x);
+            let fraction = fpart(minor);
+            plot(major, minor.floor(), (1.0 - fraction) * gap);
+            plot(major, minor.floor() + 1.0, fraction * gap);
+            return (major, minor + gradient);
+        };
+        let (start, intersection) = endpoint(x0, y0, 1.0 - fpart(x0 + 0.5));
+        let (end, _) = endpoint(x1, y1, fpart(x1 + 0.5));
+
+        let mut major = (start + 1.0).max(0.0);
+        let mut intersection = intersection + gradient * (major - start - 1.0);
+        let end = end.min(size);
+        while major < end {
+            let fraction = fpart(intersection);
+            plot(major, intersection.floor(), 1.0 - fraction);
+            plot(major, intersection.floor() + 1.0, fraction);
+            intersection += gradient;
+            major += 1.0;
+        }
+    }
+
+    // Draw the outline of a circle with a width of one pixel
+    pub fn draw_circle(&mut self, cx: f64, cy: f64, radius: f64, color: Color) {
+        self.draw_disc(
+            cx,
+            cy,
+            radius + 0.5,
+            |distance| 1.0 - (distance - radius).abs(),
+            color,
+        );
+    }
+
+    pub fn fill_circle(&mut self, cx: f64, cy: f64, radius: f64, color: Color) {
+        self.draw_disc(
+            cx,
+            cy,
+            radius + 0.5,
+            |distance| radius + 0.5 - distance,
+            color,
+        );
+    }
+
+    // Visit the pixels of the canvas within the given distance of the center and blend
+    // the color with the coverage given by the distance of the pixel center from the
+    // center
+    fn draw_disc<F: Fn(f64) -> f64>(
+        &mut self,
+        cx: f64,
+        cy: f64,
+        extent: f64,
+        coverage: F,
+        color: Color,
+    ) {
+        let (width, height) = (self.width as i64, self.height as i64);
+        let rows =
+            ((cy - extent).floor() as i64).max(0)..=((cy + extent).ceil() as i64).min(height - 1);
+        for y in rows {
+            let columns = ((cx - extent).floor() as i64).max(0)
+                ..=((cx + extent).ceil() as i64).min(width - 1);
+            for x in columns {
+                let distance = ((x as f64 - cx).powi(2) + (y as f64 - cy).powi(2)).sqrt();
+                self.blend_pixel(x, y, color, coverage(distance).clamp(0.0, 1.0));
+            }
+        }
+    }
+
+    // Fill a rectangle with the top left corner at (x, y). The parts outside the
+    // canvas are clipped.
+    pub fn fill_rect(&mut self, x: usize, y: usize, width: usize, height: usize, color: Color) {
+        for row in y..y.saturating_add(height).min(self.height) {
+            for column in x..x.saturating_add(width).min(self.width) {
+                self.write_pixel(column, row, color);
+            }
+        }
+    }
+
+    // Draw the outline of a rectangle, for example a bounding box
+    pub fn draw_rect(&mut self, x: usize, y: usize, width: usize, height: usize, color: Color) {
+        if width == 0 || height == 0 {
+            return;
+        }
+        self.fill_rect(x, y, width, 1, color);
+        self.fill_rect(x, y.saturating_add(height - 1), width, 1, color);
+        self.fill_rect(x, y, 1, height, color);
+        self.fill_rect(x.saturating_add(width - 1), y, 1, height, color);
+    }
+}
+
+// The fractional part, also for negative values
+fn fpart(value: f64) -> f64 {
+    return value - value.floor();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn white() -> Color {
+        return Color::color(1.0, 1.0, 1.0);
+    }
+
+    #[test]
+    fn y_up_flips_the_y_axis() {
+        let canvas = Canvas::new(10, 5);
+        assert_eq!(canvas.y_up(3.0, 0.0), (3.0, 4.0));
+        assert_eq!(canvas.y_up(3.0, 4.0), (3.0, 0.0));
+    }
+
+    #[test]
+    fn blend_pixel_with_partial_coverage() {
+        let mut canvas = Canvas::new(2, 2);
+        canvas.blend_pixel(1, 1, white(), 0.25);
+        assert_eq!(
+            canvas.read_pixel(1, 1),
+            Some(Color::color(0.25, 0.25, 0.25))
+        );
+        canvas.blend_pixel(-1, 0, white(), 1.0);
+        canvas.blend_pixel(2, 0, white(), 1.0);
+        assert!(canvas
+            .pixels()
+            .take(3)
+            .all(|p| *p == Color::color(0.0, 0.0, 0.0)));
+    }
+
+    #[test]
+    fn horizontal_line_covers_whole_pixels() {
+        let mut canvas = Canvas::new(5, 3);
+        canvas.draw_line(0.0, 1.0, 4.0, 1.0, white());
+        for x in 1..4 {
+            assert_eq!(canvas.read_pixel(x, 1), Some(white()));
+        }
+        // The line ends at the centers of the end pixels, so they are half covered
+        assert_eq!(canvas.read_pixel(0, 1), Some(Color::color(0.5, 0.5, 0.5)));
+        assert_eq!(canvas.read_pixel(4, 1), Some(Color::color(0.5, 0.5, 0.5)));
+        for x in 0..5 {
+            assert_eq!(canvas.read_pixel(x, 0), Some(Color::color(0.0, 0.0, 0.0)));
+            assert_eq!(canvas.read_pixel(x, 2), Some(Color::color(0.0, 0.0, 0.0)));
+        }
+    }
+
+    #[test]
+    fn line_between_pixels_is_anti_aliased() {
+        let mut canvas = Canvas::new(5, 3);
+        canvas.draw_line(0.0, 0.5, 4.0, 0.5, white());
+        assert_eq!(canvas.read_pixel(2, 0), Some(Color::color(0.5, 0.5, 0.5)));
+        assert_eq!(canvas.read_pixel(2, 1), Some(Color::color(0.5, 0.5, 0.5)));
+
+        // A steep line is drawn the same way as a flat one
+        let mut canvas = Canvas::new(3, 5);
+        canvas.draw_line(1.0, 4.0, 1.0, 0.0, white());
+        for y in 1..4 {
+            assert_eq!(canvas.read_pixel(1, y), Some(white()));
+        }
+        assert_eq!(canvas.read_pixel(0, 2), Some(Color::color(0.0, 0.0, 0.0)));
+    }
+
+    #[test]
+    fn diagonal_line() {
+        let mut canvas = Canvas::new(4, 4);
+        canvas.draw_line(0.0, 0.0, 3.0, 3.0, white());
+        for i in 1..3 {
+            assert_eq!(canvas.read_pixel(i, i), Some(white()));
+        }
+        assert_eq!(canvas.read_pixel(1, 0), Some(Color::color(0.0, 0.0, 0.0)));
+    }
+
+    #[test]
+    fn circles() {
+        let mut canvas = Canvas::new(11, 11);
+        canvas.draw_circle(5.0, 5.0, 4.0, white());
+        assert_eq!(canvas.read_pixel(9, 5), Some(white()));
+        assert_eq!(canvas.read_pixel(5, 1), Some(white()));
+        assert_eq!(canvas.read_pixel(5, 5), Some(Color::color(0.0, 0.0, 0.0)));
+
+        canvas.fill_circle(5.0, 5.0, 2.0, white());
+        assert_eq!(canvas.read_pixel(5, 5), Some(white()));
+        assert_eq!(canvas.read_pixel(6, 5), Some(white()));
+        // The edge goes through the center of the pixel
+        assert_eq!(canvas.read_pixel(7, 5), Some(Color::color(0.5, 0.5, 0.5)));
+        assert_eq!(canvas.read_pixel(5, 8), Some(Color::color(0.0, 0.0, 0.0)));
+    }
+
+    #[test]
+    fn shapes_far_outside_the_canvas_are_clipped() {
+        // Walking every pixel of these would take far too long
+        let mut canvas = Canvas::new(5, 3);
+        canvas.draw_line(-1e15, 1.0, 1e15, 1.0, white());
+        assert!((0..5).all(|x| canvas.read_pixel(x, 1) == Some(white())));
+        canvas.draw_line(2.0, -1e15, 2.0, 1e15, white());
+        assert_eq!(canvas.read_pixel(2, 0), Some(white()));
+        canvas.draw_line(1e15, 1e15, 2e15, 2e15, white());
+        canvas.draw_line(0.0, 0.0, f64::INFINITY, 0.0, white());
+        canvas.draw_circle(1e15, -1e15, 1e12, white());
+        assert_eq!(canvas.pixels().filter(|p| **p == white()).count(), 7);
+
+        let mut canvas = Canvas::new(5, 3);
+        canvas.fill_circle(2.0, 1.0, 1e15, white());
+        assert!(canvas.pixels().all(|p| *p == white()));
+    }
+
+    #[test]
+    fn rectangles_are_clipped() {
+        let mut canvas = Canvas::new(4, 4);
+        canvas.fill_rect(2, 2, 10, 10, white());
+        assert_eq!(canvas.pixels().filter(|p| **p == white()).count(), 4);
+
+        let mut canvas = Canvas::new(4, 4);
+        canvas.draw_rect(0, 0, 4, 4, white());
+        assert_eq!(canvas.pixels().filter(|p| **p == white()).count(), 12);
+        assert_eq!(canvas.read_pixel(1, 1), Some(Color::color(0.0, 0.0, 0.0)));
+
+        // Huge sizes and rectangles that start outside the canvas do not overflow
+        let mut canvas = Canvas::new(4, 4);
+        canvas.fill_rect(3, 3, usize::MAX, usize::MAX, white());
+        canvas.fill_rect(usize::MAX, usize::MAX, 2, 2, white());
+        canvas.draw_rect(1, 1, usize::MAX, usize::MAX, white());
+        canvas.draw_rect(usize::MAX, 0, 4, 4, white());
+        assert_eq!(canvas.pixels().filter(|p| **p == white()).count(), 6);
+    }
+}
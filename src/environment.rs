@@ -0,0 +1,506 @@
+use crate::canvas::Canvas;
+use crate::color::Color;
+use crate::random::Random;
+use crate::tuple::Tuple;
+use std::f64::consts::PI;
+
+// Module with the environment of a world: the light that comes from far away in every
+// direction, like the sky. Camera rays that miss every object see the environment, and
+// the path tracer also uses it as a light that surrounds the scene.
+//
+// An environment map is an equirectangular image, where the x axis is the angle around
+// the y axis and the y axis the angle from straight up (the top row) to straight down
+// (the bottom row). The center of the image is seen in the +z direction. Images with
+// high dynamic range, like the Radiance .hdr format, give the best light.
+//
+// A cube map, or skybox, is six square images on the faces of a cube around the scene.
+// The faces can also be one image in the horizontal cross layout, four faces wide and
+// three high:
+//
+//          up
+//   left  front  right  back
+//         down
+//
+// Each face is seen as it is from inside the cube, with the front in the +z direction.
+
+#[derive(Debug, Clone)]
+pub enum Environment {
+    // The same color in every direction
+    Constant(Color),
+    // A vertical gradient from the color straight down to the color straight up
+    Gradient { bottom: Color, top: Color },
+    Map(EnvironmentMap),
+    CubeMap(CubeMap),
+}
+
+impl Environment {
+    // The light that comes from the direction, which must be normalized
+    pub fn color_at(&self, direction: &Tuple) -> Color {
+        match self {
+            Environment::Constant(color) => *color,
+            Environment::Gradient { bottom, top } => {
+                let t = ((direction.y + 1.0) / 2.0).clamp(0.0, 1.0);
+                *bottom * (1.0 - t) + *top * t
+            }
+            Environment::Map(map) => map.color_at(direction),
+            Environment::CubeMap(cube) => cube.color_at(direction),
+        }
+    }
+
+    // A random direction to sample the environment as a light. The directions of an
+    // environment map are picked in proportion to their brightness, the other
+    // environments pick every direction with the same probability.
+    pub(crate) fn sample(&self, random: &mut Random) -> Tuple {
+        match self {
+            Environment::Map(map) => map.sample(random),
+            _ => uniform_sphere(random),
+        }
+    }
+
+    // The probability density of the direction, per steradian, when it is picked with
+    // sample
+    pub fn pdf(&self, direction: &Tuple) -> f64 {
+        match self {
+            Environment::Map(map) => map.pdf(direction),
+            _ => 1.0 / (4.0 * PI),
+        }
+    }
+}
+
+fn uniform_sphere(random: &mut Random) -> Tuple {
+    let y = 1.0 - 2.0 * random.next_f64();
+    let r = (1.0 - y * y).max(0.0).sqrt();
+    let phi = 2.0 * PI * random.next_f64();
+    return Tuple::vector(r * phi.sin(), y, r * phi.cos());
+}
+
+// An equirectangular image with the distribution used to pick bright directions more
+// often. The weight of a pixel is its brightness times the size of the solid angle it
+// covers, which gets smaller towards the poles.
+#[derive(Debug, Clone)]
+pub struct EnvironmentMap {
+    image: Canvas,
+    // The cumulative weights of the rows, ending with 1.0
+    row_distribution: Vec<f64>,
+    // The cumulative weights of the pixels in each row, each row ending with 1.0
+    column_distributions: Vec<Vec<f64>>,
+    // The weight of each pixel divided by the total weight
+    probabilities: Vec<f64>,
+}
+
+impl EnvironmentMap {
+    pub fn new(image: Canvas) -> EnvironmentMap {
+        let (width, height) = (image.width, image.height);
+        let mut weights = Vec::with_capacity(width * height);
+        for (y, row) in image.rows().enumerate() {
+            let sin_theta = (PI * (y as f64 + 0.5) / height as f64).sin();
+            weights.extend(
+                row.iter()
+                    .map(|color| luminance(*color).max(0.0) * sin_theta),
+            );
+        }
+        let mut total: f64 = weights.iter().sum();
+        if total <= 0.0 {
+            // A black image: pick the pixels by their solid angle only
+            for (index, weight) in weights.iter_mut().enumerate() {
+                *weight = (PI * ((index / width) as f64 + 0.5) / height as f64).sin();
+            }
+            total = weights.iter().sum();
+        }
+
+        let mut row_distribution = Vec::with_capacity(height);
+        let mut column_distributions = Vec::with_capacity(height);
+        let mut sum = 0.0;
+        for row in weights.chunks(width.max(1)) {
+            let row_total: f64 = row.iter().sum();
+            sum += row_total;
+            row_distribution.push(sum / total);
+            column_distributions.push(cumulative(row, row_total));
+        }
+        let probabilities = weights.iter().map(|weight| weight / total).collect();
+        return EnvironmentMap {
+            image,
+            row_distribution,
+            column_distributions,
+            probabilities,
+        };
+    }
+
+    pub fn image(&self) -> &Canvas {
+        return &self.image;
+    }
+
+    // The pixel seen in the direction
+    fn pixel_at(&self, direction: &Tuple) -> (usize, usize) {
+        let u = 0.5 + direction.x.atan2(direction.z) / (2.0 * PI);
+        let v = direction.y.clamp(-1.0, 1.0).acos() / PI;
+        let x = ((u * self.image.width as f64) as usize).min(self.image.width - 1);
+        let y = ((v * self.image.height as f64) as usize).min(self.image.height - 1);
+        return (x, y);
+    }
+
+    pub fn color_at(&self, direction: &Tuple) -> Color {
+        if self.image.width == 0 || self.image.height == 0 {
+            return Color::color(0.0, 0.0, 0.0);
+        }
+        let (x, y) = self.pixel_at(direction);
+        return self
+            .image
+            .read_pixel(x, y)
+            .unwrap_or(Color::color(0.0, 0.0, 0.0));
+    }
+
+    // Pick a pixel with its probability and a random direction within it
+    fn sample(&self, random: &mut Random) -> Tuple {
+        if self.image.width == 0 || self.image.height == 0 {
+            return uniform_sphere(random);
+        }
+        let y = pick(&self.row_distribution, random.next_f64());
+        let x = pick(&self.column_distributions[y], random.next_f64());
+        let u = (x as f64 + random.next_f64()) / self.image.width as f64;
+        let v = (y as f64 + random.next_f64()) / self.image.height as f64;
+        let phi = (u - 0.5) * 2.0 * PI;
+        let theta = v * PI;
+        return Tuple::vector(
+            theta.sin() * phi.sin(),
+            theta.cos(),
+            theta.sin() * phi.cos(),
+        );
+    }
+
+    // The probability of the pixel spread evenly over the rectangle of angles it
+    // covers, which is 2π² sin(theta) per steradian for the whole image
+    fn pdf(&self, direction: &Tuple) -> f64 {
+        if self.image.width == 0 || self.image.height == 0 {
+            return 1.0 / (4.0 * PI);
+        }
+        let sin_theta = (1.0 - direction.y * direction.y).max(0.0).sqrt();
+        if sin_theta <= 0.0 {
+            return 0.0;
+        }
+        let (x, y) = self.pixel_at(direction);
+        let probability = self.probabilities[y * self.image.width + x];
+        let pixels = (self.image.width * self.image.height) as f64;
+        return probability * pixels / (2.0 * PI * PI * sin_theta);
+    }
+}
+
+// The faces of a cube map, in the order of the faces array
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Face {
+    Right,
+    Left,
+    Up,
+    Down,
+    Front,
+    Back,
+}
+
+impl Face {
+    pub const ALL: [Face; 6] = [
+        Face::Right,
+        Face::Left,
+        Face::Up,
+        Face::Down,
+        Face::Front,
+        Face::Back,
+    ];
+
+    // The column and row of the face in the horizontal cross layout
+    fn cross_position(&self) -> (usize, usize) {
+        match self {
+            Face::Right => (2, 1),
+            Face::Left => (0, 1),
+            Face::Up => (1, 0),
+            Face::Down => (1, 2),
+            Face::Front => (1, 1),
+            Face::Back => (3, 1),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct CubeMap {
+    // The six faces in the order of Face::ALL
+    faces: Vec<Canvas>,
+}
+
+impl CubeMap {
+    // The faces are given in the order right (+x), left (-x), up (+y), down (-y), front
+    // (+z) and back (-z). Returns None if a face is empty.
+    pub fn new(faces: [Canvas; 6]) -> Option<CubeMap> {
+        if faces.iter().any(|face| face.width == 0 || face.height == 0) {
+            return None;
+        }
+        return Some(CubeMap {
+            faces: faces.into(),
+        });
+    }
+
+    // Cut the faces out of an image in the horizontal cross layout. Returns None if
+    // the image is not four faces wide and three faces high.
+    pub fn from_cross(image: &Canvas) -> Option<CubeMap> {
+        let size = image.width / 4;
+        if size == 0 || image.width != size * 4 || image.height != size * 3 {
+            return None;
+        }
+        let faces = Face::ALL.map(|face| {
+            let (column, row) = face.cross_position();
+            let mut canvas = Canvas::new(size, size);
+            for y in 0..size {
+                for x in 0..size {
+                    let color = image.read_pixel(column * size + x, row * size + y);
+                    canvas.write_pixel(x, y, color.unwrap_or(Color::color(0.0, 0.0, 0.0)));
+                }
+            }
+            canvas
+        });
+        return CubeMap::new(faces);
+    }
+
+    pub fn face(&self, face: Face) -> &Canvas {
+        return &self.faces[face as usize];
+    }
+
+    // The face in the direction and the position on it, from 0.0 to 1.0 from the left
+    // and from the top
+    pub fn face_at(direction: &Tuple) -> (Face, f64, f64) {
+        let (x, y, z) = (direction.x, direction.y, direction.z);
+        let (ax, ay, az) = (x.abs(), y.abs(), z.abs());
+        let (face, right, down, major) = if ax >= ay && ax >= az {
+            if x > 0.0 {
+                (Face::Right, -z, -y, ax)
+            } else {
+                (Face::Left, z, -y, ax)
+            }
+        } else if ay >= az {
+            if y > 0.0 {
+                (Face::Up, x, z, ay)
+            } else {
+                (Face::Down, x, -z, ay)
+            }
+        } else if z > 0.0 {
+            (Face::Front, x, -y, az)
+        } else {
+            (Face::Back, -x, -y, az)
+        };
+        return (
+            face,
+            (right / major + 1.0) / 2.0,
+            (down / major + 1.0) / 2.0,
+        );
+    }
+
+    pub fn color_at(&self, direction: &Tuple) -> Color {
+        let (face, u, v) = CubeMap::face_at(direction);
+        let canvas = self.face(face);
+        let x = ((u * canvas.width as f64) as usize).min(canvas.width - 1);
+        let y = ((v * canvas.height as f64) as usize).min(canvas.height - 1);
+        return canvas
+            .read_pixel(x, y)
+            .unwrap_or(Color::color(0.0, 0.0, 0.0));
+    }
+}
+
+// The perceived brightness of a linear color
+fn luminance(color: Color) -> f64 {
+    return 0.2126 * color.red + 0.7152 * color.green + 0.0722 * color.blue;
+}
+
+// The cumulative sums of the weights, divided by the total
+fn cumulative(weights: &[f64], total: f64) -> Vec<f64> {
+    let mut sum = 0.0;
+    return weights
+        .iter()
+        .map(|weight| {
+            sum += weight;
+            if total > 0.0 {
+                sum / total
+            } else {
+                1.0
+            }
+        })
+        .collect();
+}
+
+// The first index where the cumulative distribution is above the random number
+fn pick(distribution: &[f64], random: f64) -> usize {
+    let index = distribution.partition_point(|&value| value <= random);
+    return index.min(distribution.len() - 1);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    const EPSILON: f64 = 0.00001;
+
+    // A map that is black except for one bright pixel
+    fn map_with_sun() -> EnvironmentMap {
+        let mut image = Canvas::new(8, 4);
+        image.write_pixel(6, 1, Color::color(100.0, 90.0, 80.0));
+        return EnvironmentMap::new(image);
+    }
+
+    #[test]
+    fn constant_and_gradient_environments() {
+        let sky = Environment::Constant(Color::color(0.2, 0.4, 0.8));
+        assert_eq!(
+            sky.color_at(&Tuple::vector(0.0, -1.0, 0.0)),
+            Color::color(0.2, 0.4, 0.8)
+        );
+        let gradient = Environment::Gradient {
+            bottom: Color::color(1.0, 1.0, 1.0),
+            top: Color::color(0.0, 0.5, 1.0),
+        };
+        assert_eq!(
+            gradient.color_at(&Tuple::vector(0.0, 1.0, 0.0)),
+            Color::color(0.0, 0.5, 1.0)
+        );
+        assert_eq!(
+            gradient.color_at(&Tuple::vector(1.0, 0.0, 0.0)),
+            Color::color(0.5, 0.75, 1.0)
+        );
+        assert!((gradient.pdf(&Tuple::vector(0.0, 0.0, 1.0)) - 1.0 / (4.0 * PI)).abs() < EPSILON);
+    }
+
+    #[test]
+    fn directions_on_the_environment_map() {
+        let mut image = Canvas::new(4, 2);
+        image.write_pixel(2, 0, Color::color(1.0, 0.0, 0.0));
+        image.write_pixel(0, 1, Color::color(0.0, 1.0, 0.0));
+        image.write_pixel(3, 1, Color::color(0.0, 0.0, 1.0));
+        let map = Environment::Map(EnvironmentMap::new(image));
+        // The center of the image is ahead and slightly up, the left edge is behind
+        // and down, and the right edge is behind from the other side
+        assert_eq!(
+            map.color_at(&Tuple::vector(0.1, 0.5, 1.0).normalize()),
+            Color::color(1.0, 0.0, 0.0)
+        );
+        assert_eq!(
+            map.color_at(&Tuple::vector(-0.1, -0.5, -1.0).normalize()),
+            Color::color(0.0, 1.0, 0.0)
+        );
+        assert_eq!(
+            map.color_at(&Tuple::vector(0.1, -0.5, -1.0).normalize()),
+            Color::color(0.0, 0.0, 1.0)
+        );
+    }
+
+    // A cross image where each face has its own color, with a white pixel in the top
+    // left corner of each face
+    fn cross_image(size: usize) -> Canvas {
+        let mut image = Canvas::new(size * 4, size * 3);
+        for (index, face) in Face::ALL.iter().enumerate() {
+            let (column, row) = face.cross_position();
+            for y in 0..size {
+                for x in 0..size {
+                    let color = if x == 0 && y == 0 {
+                        Color::color(1.0, 1.0, 1.0)
+                    } else {
+                        Color::color(index as f64 / 10.0, 0.0, 0.0)
+                    };
+                    image.write_pixel(column * size + x, row * size + y, color);
+                }
+            }
+        }
+        return image;
+    }
+
+    #[test]
+    fn faces_of_a_cube_map() {
+        for (direction, face) in [
+            (Tuple::vector(1.0, 0.2, -0.3), Face::Right),
+            (Tuple::vector(-1.0, 0.2, 0.3), Face::Left),
+            (Tuple::vector(0.1, 1.0, 0.3), Face::Up),
+            (Tuple::vector(0.1, -1.0, 0.3), Face::Down),
+            (Tuple::vector(0.1, 0.2, 1.0), Face::Front),
+            (Tuple::vector(0.1, 0.2, -1.0), Face::Back),
+        ] {
+            assert_eq!(CubeMap::face_at(&direction).0, face);
+        }
+
+        // Just inside the top left corner of each face
+        for (direction, face) in [
+            (Tuple::vector(-0.99, 0.99, 1.0), Face::Front),
+            (Tuple::vector(1.0, 0.99, 0.99), Face::Right),
+            (Tuple::vector(0.99, 0.99, -1.0), Face::Back),
+            (Tuple::vector(-1.0, 0.99, -0.99), Face::Left),
+            (Tuple::vector(-0.99, 1.0, -0.99), Face::Up),
+            (Tuple::vector(-0.99, -1.0, 0.99), Face::Down),
+        ] {
+            let (found, u, v) = CubeMap::face_at(&direction);
+            assert_eq!(found, face);
+            assert!(u < 0.01 && v < 0.01, "{:?}: {} {}", face, u, v);
+        }
+    }
+
+    #[test]
+    fn cube_map_from_a_cross_image() {
+        let cube = CubeMap::from_cross(&cross_image(4)).unwrap();
+        for (index, face) in Face::ALL.iter().enumerate() {
+            let canvas = cube.face(*face);
+            assert_eq!((canvas.width, canvas.height), (4, 4));
+            assert_eq!(canvas.read_pixel(0, 0), Some(Color::color(1.0, 1.0, 1.0)));
+            assert_eq!(
+                canvas.read_pixel(2, 3),
+                Some(Color::color(index as f64 / 10.0, 0.0, 0.0))
+            );
+        }
+        let environment = Environment::CubeMap(cube);
+        assert_eq!(
+            environment.color_at(&Tuple::vector(0.0, 0.0, -1.0)),
+            Color::color(0.5, 0.0, 0.0)
+        );
+        assert_eq!(
+            environment.color_at(&Tuple::vector(0.0, -1.0, 0.0)),
+            Color::color(0.3, 0.0, 0.0)
+        );
+        assert!(CubeMap::from_cross(&Canvas::new(8, 8)).is_none());
+    }
+
+    #[test]
+    fn samples_are_in_the_bright_pixel() {
+        let map = map_with_sun();
+        let environment = Environment::Map(map.clone());
+        let mut random = Random::new(5);
+        for _ in 0..100 {
+            let direction = environment.sample(&mut random);
+            assert!((direction.magnitude() - 1.0).abs() < EPSILON);
+            assert_eq!(map.pixel_at(&direction), (6, 1));
+            assert!(environment.pdf(&direction) > 1.0);
+        }
+        assert_eq!(environment.pdf(&Tuple::vector(0.0, 0.0, 1.0)), 0.0);
+    }
+
+    #[test]
+    fn pdf_integrates_to_one() {
+        let mut image = Canvas::new(16, 8);
+        for (index, pixel) in image.pixels_mut().enumerate() {
+            *pixel = Color::color(index as f64 % 5.0, 1.0, 0.5);
+        }
+        let environment = Environment::Map(EnvironmentMap::new(image));
+        // Uniform directions have the density 1/(4π), so the mean of the ratio of the
+        // densities is the integral of the pdf
+        let mut random = Random::new(9);
+        let count = 20000;
+        let mut integral = 0.0;
+        for _ in 0..count {
+            let direction = uniform_sphere(&mut random);
+            integral += environment.pdf(&direction) * 4.0 * PI / count as f64;
+        }
+        assert!((integral - 1.0).abs() < 0.03, "{}", integral);
+    }
+
+    #[test]
+    fn black_map_is_sampled_evenly() {
+        let environment = Environment::Map(EnvironmentMap::new(Canvas::new(8, 4)));
+        let mut random = Random::new(2);
+        let mut up = 0;
+        for _ in 0..1000 {
+            if environment.sample(&mut random).y > 0.0 {
+                up += 1;
+            }
+        }
+        assert!((400..600).contains(&up), "{}", up);
+    }
+}
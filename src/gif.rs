@@ -0,0 +1,551 @@
+use crate::canvas::Canvas;
+use std::collections::HashMap;
+use std::io::{self, prelude::*};
+
+// Module to encode a sequence of canvases as an animated GIF89a image.
+//
+// Each frame gets its own color table of at most 256 colors, which is calculated with
+// the median cut algorithm. Optionally, the quantization error is spread to the
+// neighbouring pixels (Floyd-Steinberg dithering) to avoid banding. The color indices
+// are compressed with variable code width LZW.
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Repeat {
+    // Play the animation once
+    Once,
+    // Repeat the animation the given number of extra times, Times(0) is the same as
+    // Once
+    Times(u16),
+    Infinite,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Options {
+    // The time each frame is shown in hundredths of a second
+    pub delay: u16,
+    pub repeat: Repeat,
+    pub dither: bool,
+    // The maximum number of colors in each frame's color table, 2 - 256
+    pub colors: usize,
+}
+
+impl Default for Options {
+    fn default() -> Options {
+        return Options::new();
+    }
+}
+
+impl Options {
+    pub fn new() -> Options {
+        Options {
+            delay: 4,
+            repeat: Repeat::Infinite,
+            dither: false,
+            colors: 256,
+        }
+    }
+}
+
+// The encoder writes the frames as they are added, so an animation does not have to be
+// kept in memory. Call finish when all frames have been added.
+pub struct Encoder<W: Write> {
+    writer: W,
+    width: usize,
+    height: usize,
+    options: Options,
+}
+
+impl<W: Write> Encoder<W> {
+    pub fn new(
+        mut writer: W,
+        width: usize,
+        height: usize,
+        options: Options,
+    ) -> io::Result<Encoder<W>> {
+        if width > u16::MAX as usize || height > u16::MAX as usize {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("image size {}x{} is too large for GIF", width, height),
+            ));
+        }
+
+        writer.write_all(b"GIF89a")?;
+        // Logical screen descriptor without a global color table
+        writer.write_all(&(width as u16).to_le_bytes())?;
+        writer.write_all(&(height as u16).to_le_bytes())?;
+        writer.write_all(&[0, 0, 0])?;
+
+        // The NETSCAPE2.0 application extension makes the animation loop. A loop count
+        // of 0 means forever, so repeating 0 times leaves the extension out.
+        let loops = match options.repeat {
+            Repeat::Once | Repeat::Times(0) => None,
+            Repeat::Times(count) => Some(count),
+            Repeat::Infinite => Some(0),
+        };
+        if let Some(loops) = loops {
+            writer.write_all(&[0x21, 0xff, 0x0b])?;
+            writer.write_all(b"NETSCAPE2.0")?;
+            writer.write_all(&[0x03, 0x01])?;
+            writer.write_all(&loops.to_le_bytes())?;
+            writer.write_all(&[0x00])?;
+        }
+
+        Ok(Encoder {
+            writer,
+            width,
+            height,
+            options,
+        })
+    }
+
+    pub fn add_frame(&mut self, canvas: &Canvas) -> io::Result<()> {
+        if canvas.width != self.width || canvas.height != self.height {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "frame size {}x{} does not match the image size {}x{}",
+                    canvas.width, canvas.height, self.width, self.height
+                ),
+            ));
+        }
+
+        let pixels = canvas_to_rgb(canvas);
+        let mut palette = median_cut(&pixels, self.options.colors.clamp(2, 256));
+        let indices = map_to_palette(&pixels, &palette, self.width, self.options.dither);
+
+        // The color table size must be a power of two, with at least two bits per index
+        let bits = (usize::BITS - (palette.len() - 1).leading_zeros()).max(2);
+        palette.resize(1 << bits, [0, 0, 0]);
+
+        // Graphic control extension with the frame delay. Disposal method 1 leaves the
+        // frame in place, which is fine since every frame covers the whole image.
+        self.writer.write_all(&[0x21, 0xf9, 0x04, 0x04])?;
+        self.writer.write_all(&self.options.delay.to_le_bytes())?;
+        self.writer.write_all(&[0x00, 0x00])?;
+
+        // Image descriptor with a local color table
+        self.writer.write_all(&[0x2c, 0, 0, 0, 0])?;
+        self.writer.write_all(&(self.width as u16).to_le_bytes())?;
+        self.writer.write_all(&(self.height as u16).to_le_bytes())?;
+        self.writer.write_all(&[0x80 | (bits as u8 - 1)])?;
+        for color in &palette {
+            self.writer.write_all(color)?;
+        }
+
+        self.writer.write_all(&[bits as u8])?;
+        for block in lzw_compress(&indices, bits).chunks(255) {
+            self.writer.write_all(&[block.len() as u8])?;
+            self.writer.write_all(block)?;
+        }
+        return self.writer.write_all(&[0x00]);
+    }
+
+    // Write the trailer and return the writer
+    pub fn finish(mut self) -> io::Result<W> {
+        self.writer.write_all(&[0x3b])?;
+        self.writer.flush()?;
+        return Ok(self.writer);
+    }
+}
+
+// Encode all frames in one go
+pub fn write<W: Write>(frames: &[Canvas], options: Options, writer: W) -> io::Result<W> {
+    let (width, height) = match frames.first() {
+        Some(frame) => (frame.width, frame.height),
+        None => return Err(io::Error::new(io::ErrorKind::InvalidInput, "no frames")),
+    };
+    let mut encoder = Encoder::new(writer, width, height, options)?;
+    for frame in frames {
+        encoder.add_frame(frame)?;
+    }
+    return encoder.finish();
+}
+
+fn canvas_to_rgb(canvas: &Canvas) -> Vec<[u8; 3]> {
+    return canvas
+        .pixels()
+        .map(|pixel| {
+            let color = pixel.normalize_u8();
+            [color.red, color.green, color.blue]
+        })
+        .collect();
+}
+
+// A box in the color space with the colors it contains and how many pixels have each color
+struct ColorBox {
+    colors: Vec<([u8; 3], usize)>,
+}
+
+impl ColorBox {
+    // The channel with the largest range and the size of the range
+    fn widest_channel(&self) -> (usize, u8) {
+        let mut widest = (0, 0);
+        for channel in 0..3 {
+            let min = self.colors.iter().map(|(c, _)| c[channel]).min().unwrap();
+            let max = self.colors.iter().map(|(c, _)| c[channel]).max().unwrap();
+            if max - min > widest.1 {
+                widest = (channel, max - min);
+            }
+        }
+        return widest;
+    }
+
+    fn average(&self) -> [u8; 3] {
+        let mut sum = [0usize; 3];
+        let mut count = 0;
+        for (color, n) in &self.colors {
+            for channel in 0..3 {
+                sum[channel] += color[channel] as usize * n;
+            }
+            count += n;
+        }
+        return sum.map(|s| ((s + count / 2) / count) as u8);
+    }
+}
+
+// Reduce the colors to a palette by repeatedly splitting the box with the widest color
+// range at the median pixel, until there are as many boxes as colors in the palette.
+fn median_cut(pixels: &[[u8; 3]], max_colors: usize) -> Vec<[u8; 3]> {
+    let mut histogram: HashMap<[u8; 3], usize> = HashMap::new();
+    for pixel in pixels {
+        *histogram.entry(*pixel).or_insert(0) += 1;
+    }
+    let mut colors: Vec<([u8; 3], usize)> = histogram.into_iter().collect();
+    colors.sort();
+    if colors.is_empty() {
+        return vec![[0, 0, 0]];
+    }
+
+    let mut boxes = vec![ColorBox { colors }];
+    while boxes.len() < max_colors {
+        let candidate = boxes
+            .iter()
+            .enumerate()
+            .filter(|(_, b)| b.colors.len() > 1)
+            .max_by_key(|(_, b)| b.widest_channel().1);
+        let index = match candidate {
+            Some((index, _)) => index,
+            None => break,
+        };
+
+        let mut color_box = boxes.swap_remove(index);
+        let (channel, _) = color_box.widest_channel();
+        color_box.colors.sort_by_key(|(c, _)| c[channel]);
+
+        // Split at the median pixel, but keep at least one color in each half
+        let total: usize = color_box.colors.iter().map(|(_, n)| n).sum();
+        let mut count = 0;
+        let mut split = 1;
+        for (i, (_, n)) in color_box.colors.iter().enumerate() {
+            count += n;
+            if count * 2 >= total {
+                split = i + 1;
+                break;
+            }
+        }
+        let split = split.clamp(1, color_box.colors.len() - 1);
+        let upper = color_box.colors.split_off(split);
+        boxes.push(color_box);
+        boxes.push(ColorBox { colors: upper });
+    }
+    return boxes.iter().map(|b| b.average()).collect();
+}
+
+fn nearest(palette: &[[u8; 3]], color: [u8; 3]) -> u8 {
+    let distance = |p: &[u8; 3]| {
+        (0..3)
+            .map(|i| (p[i] as i32 - color[i] as i32).pow(2))
+            .sum::<i32>()
+    };
+    let (index, _) = palette
+        .iter()
+        .enumerate()
+        .min_by_key(|(_, p)| distance(p))
+        .unwrap();
+    return index as u8;
+}
+
+fn map_to_palette(pixels: &[[u8; 3]], palette: &[[u8; 3]], width: usize, dither: bool) -> Vec<u8> {
+    let mut cache: HashMap<[u8; 3], u8> = HashMap::new();
+    let mut lookup = |color: [u8; 3]| {
+        *cache
+            .entry(color)
+            .or_insert_with(|| nearest(palette, color))
+    };
+
+    if !dither {
+        return pixels.iter().map(|pixel| lookup(*pixel)).collect();
+    }
+
+    // Floyd-Steinberg: 7/16 of the error goes to the right, 3/16 down left, 5/16 down
+    // and 1/16 down right. Only the current and the next row need to be kept.
+    let mut indices = Vec::with_capacity(pixels.len());
+    let mut current = vec![[0.0f64; 3]; width + 2];
+    let mut next = vec![[0.0f64; 3]; width + 2];
+    for (i, pixel) in pixels.iter().enumerate() {
+        let x = i % width;
+        if x == 0 && i > 0 {
+            std::mem::swap(&mut current, &mut next);
+            next.iter_mut().for_each(|e| *e = [0.0; 3]);
+        }
+
+        let mut wanted = [0u8; 3];
+        for c in 0..3 {
+            wanted[c] = (pixel[c] as f64 + current[x + 1][c])
+                .round()
+                .clamp(0.0, 255.0) as u8;
+        }
+        let index = lookup(wanted);
+        indices.push(index);
+
+        let chosen = palette[index as usize];
+        for c in 0..3 {
+            let error = pixel[c] as f64 + current[x + 1][c] - chosen[c] as f64;
+            current[x + 2][c] += error * 7.0 / 16.0;
+            next[x][c] += error * 3.0 / 16.0;
+            next[x + 1][c] += error * 5.0 / 16.0;
+            next[x + 2][c] += error / 16.0;
+        }
+    }
+    return indices;
+}
+
+// GIF codes are packed starting with the least significant bit
+struct BitWriter {
+    bytes: Vec<u8>,
+    buffer: u32,
+    count: u32,
+}
+
+impl BitWriter {
+    fn write(&mut self, code: u16, size: u32) {
+        self.buffer |= (code as u32) << self.count;
+        self.count += size;
+        while self.count >= 8 {
+            self.bytes.push(self.buffer as u8);
+            self.buffer >>= 8;
+            self.count -= 8;
+        }
+    }
+}
+
+const MAX_CODE: u16 = 4095;
+
+// Variable code width LZW as used by GIF. The code width starts one bit above the
+// minimum code size and grows to at most 12 bits, after which the table is reset.
+fn lzw_compress(indices: &[u8], min_code_size: u32) -> Vec<u8> {
+    let clear_code: u16 = 1 << min_code_size;
+    let end_code = clear_code + 1;
+    let mut writer = BitWriter {
+        bytes: Vec::new(),
+        buffer: 0,
+        count: 0,
+    };
+    let mut table: HashMap<(u16, u8), u16> = HashMap::new();
+    let mut code_size = min_code_size + 1;
+    let mut next_code = end_code + 1;
+
+    writer.write(clear_code, code_size);
+    let mut prefix = match indices.first() {
+        Some(index) => *index as u16,
+        None => {
+            writer.write(end_code, code_size);
+            return writer.finish();
+        }
+    };
+
+    for index in &indices[1..] {
+        if let Some(code) = table.get(&(prefix, *index)) {
+            prefix = *code;
+            continue;
+        }
+
+        writer.write(prefix, code_size);
+        if next_code <= MAX_CODE {
+            table.insert((prefix, *index), next_code);
+            next_code += 1;
+            // The decoder adds its entries one code later, so it widens the codes when
+            // the next code is beyond what fits in the current width
+            if next_code > (1 << code_size) && code_size < 12 {
+                code_size += 1;
+            }
+        } else {
+            writer.write(clear_code, code_size);
+            table.clear();
+            code_size = min_code_size + 1;
+            next_code = end_code + 1;
+        }
+        prefix = *index as u16;
+    }
+
+    writer.write(prefix, code_size);
+    writer.write(end_code, code_size);
+    return writer.finish();
+}
+
+impl BitWriter {
+    fn finish(mut self) -> Vec<u8> {
+        if self.count > 0 {
+            self.bytes.push(self.buffer as u8);
+        }
+        return self.bytes;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::color::Color;
+
+    // Reference LZW decoder, used to verify the encoder
+    fn lzw_decompress(data: &[u8], min_code_size: u32) -> Vec<u8> {
+        let clear_code = 1usize << min_code_size;
+        let end_code = clear_code + 1;
+        let mut pos = 0;
+        let mut read = |size: u32| {
+            let mut code = 0;
+            for i in 0..size {
+                code |= ((data[pos / 8] >> (pos % 8)) as usize & 1) << i;
+                pos += 1;
+            }
+            code
+        };
+
+        let mut output = Vec::new();
+        let mut table: Vec<Vec<u8>> = Vec::new();
+        let mut code_size = min_code_size + 1;
+        let mut previous: Option<usize> = None;
+        loop {
+            let code = read(code_size);
+            if code == clear_code {
+                table = (0..clear_code).map(|i| vec![i as u8]).collect();
+                table.push(vec![]);
+                table.push(vec![]);
+                code_size = min_code_size + 1;
+                previous = None;
+                continue;
+            }
+            if code == end_code {
+                break;
+            }
+            let entry = if code < table.len() {
+                table[code].clone()
+            } else {
+                let mut entry = table[previous.unwrap()].clone();
+                entry.push(entry[0]);
+                entry
+            };
+            if let Some(previous) = previous {
+                if table.len() < 4096 {
+                    let mut new_entry = table[previous].clone();
+                    new_entry.push(entry[0]);
+                    table.push(new_entry);
+                    if table.len() == (1 << code_size) && code_size < 12 {
+                        code_size += 1;
+                    }
+                }
+            }
+            output.extend_from_slice(&entry);
+            previous = Some(code);
+        }
+        return output;
+    }
+
+    #[test]
+    fn lzw_round_trip() {
+        let mut indices: Vec<u8> = (0..5000).map(|i| ((i * 31) % 7 + i / 700) as u8).collect();
+        indices.extend(vec![3u8; 20000]);
+        indices.extend((0..30000).map(|i| (i * 7919 % 256) as u8));
+        let compressed = lzw_compress(&indices, 8);
+        assert_eq!(lzw_decompress(&compressed, 8), indices);
+
+        let small: Vec<u8> = vec![0, 1, 1, 0, 2, 3, 3, 3, 3, 3, 1, 0, 1];
+        assert_eq!(lzw_decompress(&lzw_compress(&small, 2), 2), small);
+    }
+
+    #[test]
+    fn median_cut_keeps_few_colors_exact() {
+        let pixels = vec![[255, 0, 0], [0, 255, 0], [0, 255, 0], [0, 0, 255]];
+        let mut palette = median_cut(&pixels, 256);
+        palette.sort();
+        assert_eq!(palette, vec![[0, 0, 255], [0, 255, 0], [255, 0, 0]]);
+    }
+
+    #[test]
+    fn median_cut_limits_palette_size() {
+        let pixels: Vec<[u8; 3]> = (0..=255).map(|i| [i as u8, (255 - i) as u8, 128]).collect();
+        let palette = median_cut(&pixels, 16);
+        assert_eq!(palette.len(), 16);
+        // The palette should span the range of colors
+        assert!(palette.iter().any(|c| c[0] < 16));
+        assert!(palette.iter().any(|c| c[0] > 240));
+    }
+
+    #[test]
+    fn dithering_preserves_average_color() {
+        let width = 64;
+        let pixels = vec![[128u8, 128, 128]; width * 8];
+        let palette = vec![[0, 0, 0], [255, 255, 255]];
+        let plain = map_to_palette(&pixels, &palette, width, false);
+        assert!(plain.iter().all(|i| *i == 1));
+
+        let dithered = map_to_palette(&pixels, &palette, width, true);
+        let white = dithered.iter().filter(|i| **i == 1).count();
+        assert!((white as f64 / dithered.len() as f64 - 0.5).abs() < 0.05);
+    }
+
+    #[test]
+    fn animated_gif_structure() {
+        let mut frame1 = Canvas::new(3, 2);
+        frame1.write_pixel(0, 0, Color::color(1.0, 0.0, 0.0));
+        let mut frame2 = Canvas::new(3, 2);
+        frame2.write_pixel(2, 1, Color::color(0.0, 0.0, 1.0));
+        let mut options = Options::new();
+        options.delay = 10;
+        let data = write(&[frame1, frame2], options, Vec::new()).unwrap();
+
+        assert_eq!(&data[..6], b"GIF89a");
+        assert_eq!(&data[6..10], &[3, 0, 2, 0]);
+        assert_eq!(&data[13..16], &[0x21, 0xff, 0x0b]);
+        assert_eq!(&data[16..27], b"NETSCAPE2.0");
+        assert_eq!(&data[29..31], &[0, 0]); // Loop forever
+        assert_eq!(*data.last().unwrap(), 0x3b);
+
+        // The first frame: graphic control extension, image descriptor and color table
+        let frame = &data[32..];
+        assert_eq!(&frame[..8], &[0x21, 0xf9, 0x04, 0x04, 10, 0, 0, 0]);
+        assert_eq!(frame[8], 0x2c);
+        assert_eq!(&frame[13..17], &[3, 0, 2, 0]);
+        // Black and red padded to four colors, the smallest table for two bit codes
+        assert_eq!(frame[17], 0x81);
+        let palette = &frame[18..30];
+        let code_size = frame[30];
+        assert_eq!(code_size, 2);
+        let block_len = frame[31] as usize;
+        let indices = lzw_decompress(&frame[32..32 + block_len], 2);
+        assert_eq!(indices.len(), 6);
+        let red = indices[0] as usize;
+        assert_eq!(&palette[red * 3..red * 3 + 3], &[255, 0, 0]);
+        assert!(indices[1..].iter().all(|i| *i as usize != red));
+    }
+
+    #[test]
+    fn frames_must_match_image_size() {
+        let mut encoder = Encoder::new(Vec::new(), 2, 2, Options::new()).unwrap();
+        assert!(encoder.add_frame(&Canvas::new(3, 2)).is_err());
+        assert!(write(&[], Options::new(), Vec::new()).is_err());
+    }
+
+    #[test]
+    fn play_once_has_no_loop_extension() {
+        let mut options = Options::new();
+        options.repeat = Repeat::Once;
+        let data = write(&[Canvas::new(1, 1)], options, Vec::new()).unwrap();
+        assert_eq!(&data[13..15], &[0x21, 0xf9]);
+
+        // A loop count of 0 would play forever
+        options.repeat = Repeat::Times(0);
+        let repeat_none = write(&[Canvas::new(1, 1)], options, Vec::new()).unwrap();
+        assert_eq!(repeat_none, data);
+    }
+}
@@ -0,0 +1,449 @@
+use crate::canvas::{Canvas, DecodeError};
+use crate::color::Color;
+use crate::ppm::malformed;
+use std::fs::File;
+use std::io::{self, prelude::*, BufWriter};
+use std::path::Path;
+
+// Module to read and write Radiance RGBE (.hdr) images.
+//
+// Each pixel is stored as four bytes: a mantissa for each of the red, green and blue
+// parts and a shared exponent. The scanlines are run-length encoded one component at
+// a time. Only the standard orientation, "-Y height +X width", is supported.
+
+const MIN_RLE_WIDTH: usize = 8;
+const MAX_RLE_WIDTH: usize = 0x7fff;
+const MIN_RUN: usize = 4;
+// The most pixels a byte of pixel data is allowed to expand to. A run stores up to 127
+// pixels of a component in two bytes, so real images stay well below this.
+const MAX_PIXELS_PER_BYTE: usize = 128;
+
+// The largest value that can be stored, 255/256 * 2^127, with the mantissa 255 and the
+// exponent 127
+const MAX_RGBE_VALUE: f64 = 255.0 / 256.0 * 1.7014118346046923e38;
+
+// Convert a color to the shared exponent representation. Negative and NaN parts are
+// stored as 0.0, larger parts than the largest value as the largest value.
+fn to_rgbe(color: Color) -> [u8; 4] {
+    let part = |value: f64| {
+        if value.is_nan() {
+            0.0
+        } else {
+            value.clamp(0.0, MAX_RGBE_VALUE)
+        }
+    };
+    let (red, green, blue) = (part(color.red), part(color.green), part(color.blue));
+    let max = red.max(green).max(blue);
+    if max < 1e-32 {
+        return [0, 0, 0, 0];
+    }
+
+    // max = mantissa * 2^exponent, where the mantissa is in [0.5, 1)
+    let mut exponent = max.log2().floor() as i32 + 1;
+    if max / 2f64.powi(exponent) >= 1.0 {
+        exponent += 1;
+    }
+    let scale = 256.0 / 2f64.powi(exponent);
+    return [
+        (red * scale).min(255.0) as u8,
+        (green * scale).min(255.0) as u8,
+        (blue * scale).min(255.0) as u8,
+        (exponent + 128).clamp(0, 255) as u8,
+    ];
+}
+
+fn from_rgbe(rgbe: [u8; 4]) -> Color {
+    if rgbe[3] == 0 {
+        return Color::color(0.0, 0.0, 0.0);
+    }
+    let factor = 2f64.powi(rgbe[3] as i32 - (128 + 8));
+    return Color::color(
+        (rgbe[0] as f64 + 0.5) * factor,
+        (rgbe[1] as f64 + 0.5) * factor,
+        (rgbe[2] as f64 + 0.5) * factor,
+    );
+}
+
+pub fn write<W: Write>(canvas: &Canvas, writer: &mut W) -> io::Result<()> {
+    write!(
+        writer,
+        "#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n-Y {} +X {}\n",
+        canvas.height, canvas.width
+    )?;
+
+    let rle = (MIN_RLE_WIDTH..=MAX_RLE_WIDTH).contains(&canvas.width);
+    let mut scanline: Vec<[u8; 4]> = Vec::with_capacity(canvas.width);
+    let mut encoded = Vec::new();
+    for pixels in canvas.rows() {
+        scanline.clear();
+        scanline.extend(pixels.iter().map(|color| to_rgbe(*color)));
+
+        encoded.clear();
+        if rle {
+            encoded.extend_from_slice(&[2, 2, (canvas.width >> 8) as u8, canvas.width as u8]);
+            for component in 0..4 {
+                let values: Vec<u8> = scanline.iter().map(|rgbe| rgbe[component]).collect();
+                encode_runs(&values, &mut encoded);
+            }
+        } else {
+            encoded.extend(scanline.iter().flatten());
+        }
+        writer.write_all(&encoded)?;
+    }
+    return Ok(());
+}
+
+// Encode the values as runs of identical values, with a count above 128, and dumps of
+// differing values, with a count of at most 128.
+fn encode_runs(values: &[u8], output: &mut Vec<u8>) {
+    let mut pos = 0;
+    while pos < values.len() {
+        // Find the start of the next run that is long enough to be worth encoding
+        let mut run_start = pos;
+        let mut run_length = 0;
+        while run_start < values.len() {
+            run_length = values[run_start..]
+                .iter()
+                .take(127)
+                .take_while(|value| **value == values[run_start])
+                .count();
+            if run_length >= MIN_RUN {
+                break;
+            }
+            run_start += run_length;
+        }
+        if run_length < MIN_RUN {
+            run_start = values.len();
+        }
+
+        // Dump the values before the run
+        while pos < run_start {
+            let count = (run_start - pos).min(128);
+            output.push(count as u8);
+            output.extend_from_slice(&values[pos..pos + count]);
+            pos += count;
+        }
+
+        if run_start < values.len() {
+            output.push(128 + run_length as u8);
+            output.push(values[run_start]);
+            pos = run_start + run_length;
+        }
+    }
+}
+
+// Write the canvas to a file. An existing file is overwritten.
+pub fn write_file<P: AsRef<Path>>(canvas: &Canvas, path: P) -> io::Result<()> {
+    let mut file = BufWriter::new(File::create(path)?);
+    write(canvas, &mut file)?;
+    return file.flush();
+}
+
+pub fn read<R: Read>(reader: &mut R) -> Result<Canvas, DecodeError> {
+    let mut data = Vec::new();
+    reader.read_to_end(&mut data)?;
+    let mut lines = HeaderLines {
+        data: &data,
+        pos: 0,
+    };
+
+    match lines.next() {
+        Some("#?RADIANCE") | Some("#?RGBE") => (),
+        _ => return Err(malformed("missing Radiance signature".to_string())),
+    }
+
+    // The header variables end with an empty line
+    loop {
+        match lines.next() {
+            Some("") => break,
+            Some(line) => {
+                if let Some(format) = line.strip_prefix("FORMAT=") {
+                    if format != "32-bit_rle_rgbe" {
+                        return Err(malformed(format!("unsupported format '{}'", format)));
+                    }
+                }
+            }
+            None => return Err(malformed("header is truncated".to_string())),
+        }
+    }
+
+    let resolution = lines.next().unwrap_or("");
+    let parts: Vec<&str> = resolution.split_whitespace().collect();
+    let (height, width) = match parts.as_slice() {
+        ["-Y", height, "+X", width] => match (height.parse::<usize>(), width.parse::<usize>()) {
+            (Ok(height), Ok(width)) if height > 0 && width > 0 => (height, width),
+            _ => return Err(malformed(format!("invalid resolution '{}'", resolution))),
+        },
+        _ => {
+            return Err(malformed(format!(
+                "unsupported resolution '{}'",
+                resolution
+            )))
+        }
+    };
+
+    // The size in the header is checked against the data before the canvas is
+    // allocated. Every scanline takes at least four bytes.
+    let remaining = data.len() - lines.pos;
+    let fits = width
+        .checked_mul(height)
+        .is_some_and(|pixels| pixels / MAX_PIXELS_PER_BYTE <= remaining);
+    if !fits || height > remaining / 4 {
+        return Err(malformed(format!(
+            "the pixel data is too short for the size {}x{}",
+            width, height
+        )));
+    }
+
+    let mut pos = lines.pos;
+    let mut canvas = Canvas::new(width, height);
+    let mut scanline = vec![[0u8; 4]; width];
+    for y in 0..height {
+        pos = read_scanline(&data, pos, &mut scanline)?;
+        for (x, rgbe) in scanline.iter().enumerate() {
+            canvas.write_pixel(x, y, from_rgbe(*rgbe));
+        }
+    }
+    return Ok(canvas);
+}
+
+pub fn read_file<P: AsRef<Path>>(path: P) -> Result<Canvas, DecodeError> {
+    let mut file = io::BufReader::new(File::open(path)?);
+    return read(&mut file);
+}
+
+struct HeaderLines<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Iterator for HeaderLines<'a> {
+    type Item = &'a str;
+
+    fn next(&mut self) -> Option<&'a str> {
+        let length = self.data[self.pos..].iter().position(|b| *b == b'\n')?;
+        let line = std::str::from_utf8(&self.data[self.pos..self.pos + length]).ok()?;
+        self.pos += length + 1;
+        return Some(line.trim_end_matches('\r'));
+    }
+}
+
+fn truncated() -> DecodeError {
+    malformed("pixel data is truncated".to_string())
+}
+
+// Read one scanline starting at pos and return the position after it
+fn read_scanline(data: &[u8], pos: usize, scanline: &mut [[u8; 4]]) -> Result<usize, DecodeError> {
+    let width = scanline.len();
+    let header = data.get(pos..pos + 4).ok_or_else(truncated)?;
+    let run_length_encoded = (MIN_RLE_WIDTH..=MAX_RLE_WIDTH).contains(&width)
+        && header[0] == 2
+        && header[1] == 2
+        && header[2] & 0x80 == 0;
+    if !run_length_encoded {
+        return read_flat_scanline(data, pos, scanline);
+    }
+
+    if ((header[2] as usize) << 8 | header[3] as usize) != width {
+        return Err(malformed("scanline width mismatch".to_string()));
+    }
+
+    let mut pos = pos + 4;
+    for component in 0..4 {
+        let mut x = 0;
+        while x < width {
+            let count = *data.get(pos).ok_or_else(truncated)? as usize;
+            pos += 1;
+            if count > 128 {
+                let count = count - 128;
+                let value = *data.get(pos).ok_or_else(truncated)?;
+                pos += 1;
+                if x + count > width {
+                    return Err(malformed("run exceeds scanline".to_string()));
+                }
+                for rgbe in &mut scanline[x..x + count] {
+                    rgbe[component] = value;
+                }
+                x += count;
+            } else {
+                if count == 0 || x + count > width {
+                    return Err(malformed("invalid run length".to_string()));
+                }
+                let values = data.get(pos..pos + count).ok_or_else(truncated)?;
+                for (rgbe, value) in scanline[x..x + count].iter_mut().zip(values) {
+                    rgbe[component] = *value;
+                }
+                pos += count;
+                x += count;
+            }
+        }
+    }
+    return Ok(pos);
+}
+
+// Flat scanlines store four bytes per pixel. Old style run-length encoding repeats the
+// previous pixel when the first three bytes are 1.
+fn read_flat_scanline(
+    data: &[u8],
+    pos: usize,
+    scanline: &mut [[u8; 4]],
+) -> Result<usize, DecodeError> {
+    let mut pos = pos;
+    let mut x = 0;
+    let mut shift = 0;
+    while x < scanline.len() {
+        let bytes = data.get(pos..pos + 4).ok_or_else(truncated)?;
+        pos += 4;
+        if bytes[0] == 1 && bytes[1] == 1 && bytes[2] == 1 && x > 0 {
+            // Consecutive runs give the next higher byte of the count, which can only
+            // go on until the shift is as wide as the count
+            let count = (bytes[3] as usize)
+                .checked_shl(shift)
+                .filter(|count| *count <= scanline.len() - x)
+                .ok_or_else(|| malformed("run exceeds scanline".to_string()))?;
+            for i in x..x + count {
+                scanline[i] = scanline[x - 1];
+            }
+            x += count;
+            shift += 8;
+        } else {
+            scanline[x] = [bytes[0], bytes[1], bytes[2], bytes[3]];
+            x += 1;
+            shift = 0;
+        }
+    }
+    return Ok(pos);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn approx_eq(lhs: Color, rhs: Color) -> bool {
+        // RGBE keeps about 8 bits of precision relative to the brightest part
+        let tolerance = rhs.red.max(rhs.green).max(rhs.blue) / 100.0;
+        (lhs.red - rhs.red).abs() <= tolerance
+            && (lhs.green - rhs.green).abs() <= tolerance
+            && (lhs.blue - rhs.blue).abs() <= tolerance
+    }
+
+    #[test]
+    fn rgbe_conversion() {
+        assert_eq!(to_rgbe(Color::color(0.0, 0.0, 0.0)), [0, 0, 0, 0]);
+        assert_eq!(to_rgbe(Color::color(1.0, 0.5, 0.0)), [128, 64, 0, 129]);
+        assert_eq!(to_rgbe(Color::color(0.5, 0.25, 0.0)), [128, 64, 0, 128]);
+        assert_eq!(from_rgbe([0, 0, 0, 0]), Color::color(0.0, 0.0, 0.0));
+        assert!(approx_eq(
+            from_rgbe(to_rgbe(Color::color(1000.0, 3.0, 0.25))),
+            Color::color(1000.0, 3.0, 0.25)
+        ));
+        // Values that are too large or not numbers
+        assert_eq!(
+            to_rgbe(Color::color(f64::INFINITY, 1e300, 0.0)),
+            [255, 255, 0, 255]
+        );
+        assert_eq!(
+            to_rgbe(Color::color(f64::NAN, 0.5, f64::NEG_INFINITY)),
+            [0, 128, 0, 128]
+        );
+    }
+
+    #[test]
+    fn encode_runs_of_values() {
+        let mut output = Vec::new();
+        encode_runs(&[1, 2, 3, 7, 7, 7, 7, 7, 4, 4], &mut output);
+        assert_eq!(output, vec![3, 1, 2, 3, 128 + 5, 7, 2, 4, 4]);
+
+        output.clear();
+        encode_runs(&[9; 300], &mut output);
+        assert_eq!(output, vec![128 + 127, 9, 128 + 127, 9, 128 + 46, 9]);
+    }
+
+    fn round_trip(width: usize, height: usize) {
+        let mut canvas = Canvas::new(width, height);
+        for y in 0..height {
+            for x in 0..width {
+                let value = if x < width / 2 {
+                    10.0
+                } else {
+                    x as f64 * 0.1 + y as f64
+                };
+                canvas.write_pixel(x, y, Color::color(value, value * 0.5, 0.1));
+            }
+        }
+        let mut output: Vec<u8> = Vec::new();
+        write(&canvas, &mut output).unwrap();
+
+        let decoded = read(&mut output.as_slice()).unwrap();
+        assert_eq!(decoded.width, width);
+        assert_eq!(decoded.height, height);
+        for y in 0..height {
+            for x in 0..width {
+                assert!(approx_eq(
+                    decoded.read_pixel(x, y).unwrap(),
+                    canvas.read_pixel(x, y).unwrap()
+                ));
+            }
+        }
+    }
+
+    #[test]
+    fn run_length_encoded_round_trip() {
+        round_trip(40, 3);
+    }
+
+    #[test]
+    fn flat_round_trip_for_narrow_images() {
+        round_trip(5, 2);
+    }
+
+    #[test]
+    fn read_old_style_run_length_encoding() {
+        let mut data = b"#?RADIANCE\n\n-Y 1 +X 4\n".to_vec();
+        data.extend_from_slice(&[128, 64, 0, 129, 1, 1, 1, 3]);
+        let canvas = read(&mut data.as_slice()).unwrap();
+        for x in 0..4 {
+            assert!(approx_eq(
+                canvas.read_pixel(x, 0).unwrap(),
+                Color::color(1.0, 0.5, 0.0)
+            ));
+        }
+    }
+
+    #[test]
+    fn read_reports_malformed_files() {
+        let read_str = |text: &str| read(&mut text.as_bytes());
+        assert!(matches!(read_str("P6\n"), Err(DecodeError::Malformed(_))));
+        assert!(matches!(
+            read_str("#?RADIANCE\nFORMAT=32-bit_rle_xyze\n\n-Y 1 +X 1\n"),
+            Err(DecodeError::Malformed(_))
+        ));
+        assert!(matches!(
+            read_str("#?RADIANCE\n\n+Y 1 +X 1\n"),
+            Err(DecodeError::Malformed(_))
+        ));
+        assert!(matches!(
+            read_str("#?RADIANCE\n\n-Y 1 +X 2\n\u{1}\u{1}"),
+            Err(DecodeError::Malformed(_))
+        ));
+        // Old style runs whose count is shifted past the width of the count
+        let mut data = b"#?RADIANCE\n\n-Y 1 +X 4\n".to_vec();
+        data.extend_from_slice(&[128, 64, 0, 129]);
+        for _ in 0..10 {
+            data.extend_from_slice(&[1, 1, 1, 0]);
+        }
+        assert!(matches!(
+            read(&mut data.as_slice()),
+            Err(DecodeError::Malformed(_))
+        ));
+        // A huge image with almost no pixel data, and a size that overflows
+        assert!(matches!(
+            read_str("#?RADIANCE\n\n-Y 100000 +X 100000\n\u{2}\u{2}\u{0}\u{8}"),
+            Err(DecodeError::Malformed(_))
+        ));
+        assert!(matches!(
+            read_str("#?RADIANCE\n\n-Y 4294967296 +X 4294967296\n\u{2}\u{2}\u{0}\u{8}"),
+            Err(DecodeError::Malformed(_))
+        ));
+    }
+}
@@ -0,0 +1,67 @@
+// Explicit returns and the `Color::color` constructor are part of the code style
+#![allow(clippy::needless_return, clippy::self_named_constructors)]
+
+// A ray tracer based on the book "The Ray Tracer Challenge" by Jamis Buck.
+//
+// A world of shapes and lights is rendered with a camera into a canvas, which can be
+// saved in several image formats:
+//
+//   let mut world = World::new();
+//   world.lights.push(PointLight::new(Tuple::point(-10., 10., -10.), Color::color(1., 1., 1.)).into());
+//   world.objects.push(Sphere::new());
+//   let mut camera = Camera::new(200, 100, std::f64::consts::PI / 3.);
+//   camera.set_view_transformation(&from, &to, &up);
+//   output::save(&camera.render(&world), "image.png")?;
+//
+// A world and a camera can also be described in a scene file, see load_scene.
+//
+// The public API is the modules below and the types exported at the top of the crate.
+// The image formats are used through the output module.
+
+pub mod camera;
+pub mod matrices;
+pub mod output;
+pub mod shapes;
+pub mod tuple;
+pub mod world;
+
+pub(crate) mod brdf;
+pub(crate) mod bump;
+pub(crate) mod canvas;
+pub(crate) mod color;
+pub(crate) mod environment;
+pub(crate) mod gif;
+pub(crate) mod hdr;
+pub(crate) mod lights;
+pub(crate) mod pathtracer;
+pub(crate) mod pfm;
+pub(crate) mod png;
+pub(crate) mod ppm;
+pub(crate) mod random;
+pub(crate) mod rays;
+pub(crate) mod scene;
+pub(crate) mod texture;
+pub(crate) mod tonemap;
+
+// The drawing methods are added to Canvas and the YAML parser is only used for scenes
+mod draw;
+mod yaml;
+
+pub use bump::Bump;
+pub use camera::Camera;
+pub use canvas::{Canvas, OutOfBounds};
+pub use color::{Color, ColorU8};
+pub use environment::{CubeMap, Environment, EnvironmentMap, Face};
+pub use lights::{
+    lighting, AreaLight, Attenuation, DirectionalLight, Light, LightSample, LightSource,
+    PointLight, ShapeLight, SpotLight,
+};
+pub use matrices::Matrix;
+pub use pathtracer::Integrator;
+pub use rays::{hit, Computation, Intersection, Ray};
+pub use scene::{load as load_scene, parse as parse_scene, Scene, SceneError};
+pub use shapes::{Material, ShadingModel, Sphere};
+pub use texture::{Filter, Mapping, Texture};
+pub use tuple::Tuple;
+pub use world::World;
+pub use yaml::ParseError;
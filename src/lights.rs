@@ -1,11 +1,55 @@
+use crate::brdf;
 use crate::color::Color;
-use crate::shapes::Material;
-use crate::tuple::{dot, reflect, Tuple};
+use crate::matrices::{to_matrix, to_tuple, Matrix};
+use crate::random::Random;
+use crate::shapes::{Material, ShadingModel, Sphere};
+use crate::tuple::{cross, dot, orthonormal_basis, reflect, Tuple};
+use std::f64::consts::PI;
 
-#[derive(Debug, PartialEq)]
+// How the intensity of a light decreases with the distance d from the light. Without
+// attenuation a light is as bright far away as close by. Linear and inverse square
+// attenuation give the full intensity at distance 1, and more closer to the light.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Attenuation {
+    None,
+    // 1 / d
+    Linear,
+    // 1 / d^2, like a physical light
+    InverseSquare,
+    // 1 / (constant + linear * d + quadratic * d^2)
+    Coefficients {
+        constant: f64,
+        linear: f64,
+        quadratic: f64,
+    },
+}
+
+impl Attenuation {
+    // The fraction of the intensity that reaches the distance
+    pub fn factor(&self, distance: f64) -> f64 {
+        let (constant, linear, quadratic) = match *self {
+            Attenuation::None => return 1.0,
+            Attenuation::Linear => (0.0, 1.0, 0.0),
+            Attenuation::InverseSquare => (0.0, 0.0, 1.0),
+            Attenuation::Coefficients {
+                constant,
+                linear,
+                quadratic,
+            } => (constant, linear, quadratic),
+        };
+        let divisor = constant + linear * distance + quadratic * distance * distance;
+        if divisor <= 0.0 {
+            return 1.0;
+        }
+        return 1.0 / divisor;
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub struct PointLight {
     pub position: Tuple,
     pub intensity: Color,
+    pub attenuation: Attenuation,
 }
 
 impl PointLight {
@@ -13,55 +57,553 @@ impl PointLight {
         PointLight {
             position,
             intensity,
+            attenuation: Attenuation::None,
+        }
+    }
+}
+
+// A rectangular light with a corner and two edges. The rectangle is divided into
+// usteps x vsteps cells and the light is sampled at one point in each cell, which
+// gives soft shadows where only some of the cells are visible. With jitter the point
+// is a random position in the cell, which turns the banding of the shadow edges into
+// noise.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AreaLight {
+    pub corner: Tuple,
+    // The edges of one cell
+    pub uvec: Tuple,
+    pub usteps: usize,
+    pub vvec: Tuple,
+    pub vsteps: usize,
+    pub intensity: Color,
+    pub jitter: bool,
+    pub attenuation: Attenuation,
+}
+
+impl AreaLight {
+    // The edges are given for the whole light. Returns None if usteps or vsteps is 0.
+    pub fn new(
+        corner: Tuple,
+        full_uvec: Tuple,
+        usteps: usize,
+        full_vvec: Tuple,
+        vsteps: usize,
+        intensity: Color,
+    ) -> Option<AreaLight> {
+        if usteps == 0 || vsteps == 0 {
+            return None;
+        }
+        return Some(AreaLight {
+            corner,
+            uvec: full_uvec * (1.0 / usteps as f64),
+            usteps,
+            vvec: full_vvec * (1.0 / vsteps as f64),
+            vsteps,
+            intensity,
+            jitter: true,
+            attenuation: Attenuation::None,
+        });
+    }
+
+    pub fn sample_count(&self) -> usize {
+        return self.usteps * self.vsteps;
+    }
+
+    // The center of the light
+    pub fn position(&self) -> Tuple {
+        return self.corner
+            + self.uvec * (self.usteps as f64 / 2.0)
+            + self.vvec * (self.vsteps as f64 / 2.0);
+    }
+
+    // The sample point in the cell (u, v), jittered by the given offsets (0.0 - 1.0)
+    pub fn point_on_light(&self, u: usize, v: usize, offsets: (f64, f64)) -> Tuple {
+        return self.corner
+            + self.uvec * (u as f64 + offsets.0)
+            + self.vvec * (v as f64 + offsets.1);
+    }
+}
+
+// A spot light shines in a cone around its direction. The light has full intensity
+// inside the inner angle and fades out smoothly towards the outer angle. The angles
+// are measured from the direction, in radians.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SpotLight {
+    pub position: Tuple,
+    pub direction: Tuple,
+    pub inner_angle: f64,
+    pub outer_angle: f64,
+    pub intensity: Color,
+    pub attenuation: Attenuation,
+}
+
+impl SpotLight {
+    pub fn new(
+        position: Tuple,
+        direction: Tuple,
+        inner_angle: f64,
+        outer_angle: f64,
+        intensity: Color,
+    ) -> SpotLight {
+        SpotLight {
+            position,
+            direction: direction.normalize(),
+            inner_angle,
+            outer_angle,
+            intensity,
+            attenuation: Attenuation::None,
+        }
+    }
+
+    // The fraction of the intensity in the direction from the light to the point
+    pub fn falloff(&self, point: &Tuple) -> f64 {
+        let cos_angle = dot(&(*point - self.position).normalize(), &self.direction);
+        let cos_inner = self.inner_angle.cos();
+        let cos_outer = self.outer_angle.cos();
+        if cos_inner <= cos_outer {
+            return if cos_angle >= cos_outer { 1.0 } else { 0.0 };
+        }
+        let t = ((cos_angle - cos_outer) / (cos_inner - cos_outer)).clamp(0.0, 1.0);
+        return t * t * (3.0 - 2.0 * t);
+    }
+}
+
+// A light infinitely far away, like the sun, where all rays are parallel. The
+// direction is the direction the light travels in.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DirectionalLight {
+    pub direction: Tuple,
+    pub intensity: Color,
+}
+
+impl DirectionalLight {
+    pub fn new(direction: Tuple, intensity: Color) -> DirectionalLight {
+        DirectionalLight {
+            direction: direction.normalize(),
+            intensity,
+        }
+    }
+}
+
+// A shape with an emissive material used as a light. It is sampled at random points on
+// the part of the sphere that is visible from the shaded point. The intensity of a
+// sample is the emission times the solid angle the surface covers, divided by pi, so a
+// surface that is surrounded by emission gets the same light as from a point light
+// with the emission as the intensity.
+#[derive(Debug, Clone)]
+pub struct ShapeLight<'a> {
+    pub shape: &'a Sphere,
+    pub samples: usize,
+    // Gives different lights different random positions
+    pub seed: u64,
+}
+
+impl LightSource for ShapeLight<'_> {
+    // The emission is the brightness of the surface, not the light that reaches the
+    // objects, so it would make the ambient reflection far too bright. An emissive
+    // object gives no ambient light.
+    fn intensity(&self) -> Color {
+        return Color::color(0.0, 0.0, 0.0);
+    }
+
+    fn samples(&self, point: &Tuple) -> Vec<LightSample> {
+        let transformation = &self.shape.transformation;
+        let inverse = match transformation.invert() {
+            Some(inverse) => inverse,
+            None => return Vec::new(),
+        };
+        let normal_transformation = inverse.transpose();
+        let transform = |matrix: &_, tuple: &Tuple| to_tuple(&(matrix * &to_matrix(tuple)));
+
+        // A point inside the shape cannot see the outside of it
+        let object_point = transform(&inverse, point);
+        let to_center = Tuple::point(0.0, 0.0, 0.0) - object_point;
+        let center_distance = to_center.magnitude();
+        if center_distance <= 1.0 {
+            return Vec::new();
+        }
+
+        // In object space the shape is a unit sphere, which is seen within a cone around
+        // the direction to the center. The directions are picked evenly within the cone.
+        let cos_max = (1.0 - 1.0 / (center_distance * center_distance)).sqrt();
+        let solid_angle = 2.0 * PI * (1.0 - cos_max);
+        let axis = to_center.normalize();
+
+        let mut random = Random::from_point(point, self.seed);
+        let mut samples = Vec::with_capacity(self.samples);
+        for _ in 0..self.samples {
+            // The nearest point on the unit sphere in the direction. A direction along
+            // the edge of the cone may just miss it because of rounding errors.
+            let object_direction = random.uniform_cone(&axis, cos_max);
+            let b = dot(&object_direction, &to_center);
+            let t = b
+                - (b * b - center_distance * center_distance + 1.0)
+                    .max(0.0)
+                    .sqrt();
+            let local = object_point + object_direction * t - Tuple::point(0.0, 0.0, 0.0);
+            let cos_object = dot(&local, &-object_direction).max(0.0);
+
+            let surface = transform(transformation, &Tuple::point(local.x, local.y, local.z));
+            let normal = transform(&normal_transformation, &local).normalize();
+            let vector = surface - *point;
+            let distance = vector.magnitude();
+            let direction = vector.normalize();
+            let cos_light = dot(&normal, &-direction).max(0.0);
+            if cos_object <= 0.0 || cos_light <= 0.0 {
+                samples.push(LightSample {
+                    direction,
+                    distance,
+                    intensity: Color::color(0.0, 0.0, 0.0),
+                });
+                continue;
+            }
+
+            // The inverse of the probability of the sample per area of the transformed
+            // surface
+            let area_per_sample =
+                solid_angle * t * t * stretch(transformation, &local) / cos_object;
+
+            samples.push(LightSample {
+                direction,
+                distance,
+                intensity: self.shape.material.emission
+                    * (cos_light * area_per_sample / (PI * distance * distance)),
+            });
+        }
+        return samples;
+    }
+}
+
+impl ShapeLight<'_> {
+    // The probability density per solid angle that a sample from the point is in the
+    // direction of the given point on the surface, or 0 where there are no samples
+    pub fn pdf(&self, point: &Tuple, surface: &Tuple) -> f64 {
+        let transformation = &self.shape.transformation;
+        let inverse = match transformation.invert() {
+            Some(inverse) => inverse,
+            None => return 0.0,
+        };
+        let transform = |matrix: &_, tuple: &Tuple| to_tuple(&(matrix * &to_matrix(tuple)));
+        let object_point = transform(&inverse, point);
+        let center_distance = (object_point - Tuple::point(0.0, 0.0, 0.0)).magnitude();
+        if center_distance <= 1.0 {
+            return 0.0;
+        }
+        let cos_max = (1.0 - 1.0 / (center_distance * center_distance)).sqrt();
+        let solid_angle = 2.0 * PI * (1.0 - cos_max);
+
+        // Only the side of the unit sphere that faces the point is sampled
+        let local = transform(&inverse, surface) - Tuple::point(0.0, 0.0, 0.0);
+        let object_vector = Tuple::point(local.x, local.y, local.z) - object_point;
+        let t = object_vector.magnitude();
+        let cos_object = dot(&local, &-object_vector.normalize());
+        let normal = transform(&inverse.transpose(), &local).normalize();
+        let vector = *surface - *point;
+        let distance = vector.magnitude();
+        let cos_light = dot(&normal, &-vector.normalize());
+        if cos_object <= 0.0 || cos_light <= 0.0 {
+            return 0.0;
+        }
+        let area_per_sample = solid_angle * t * t * stretch(transformation, &local) / cos_object;
+        return distance * distance / (cos_light * area_per_sample);
+    }
+}
+
+// How much the transformation stretches the area around a point on the unit sphere,
+// the length of the cross product of the transformed tangents
+fn stretch(transformation: &Matrix<4, 4>, local: &Tuple) -> f64 {
+    let transform = |tuple: &Tuple| to_tuple(&(transformation * &to_matrix(tuple)));
+    let (tangent, bitangent) = orthonormal_basis(local);
+    return cross(&transform(&tangent), &transform(&bitangent)).magnitude();
+}
+
+// The light from one sample of a light source as seen from a point
+#[derive(Debug, Clone, PartialEq)]
+pub struct LightSample {
+    // Normalized vector from the point towards the light
+    pub direction: Tuple,
+    // The distance to the light, infinite for a directional light. Objects further
+    // away do not cast shadows on the point.
+    pub distance: f64,
+    pub intensity: Color,
+}
+
+impl LightSample {
+    // A sample from a position, with the intensity reduced by the attenuation over the
+    // distance to the point
+    fn towards(
+        position: &Tuple,
+        point: &Tuple,
+        intensity: Color,
+        attenuation: Attenuation,
+    ) -> LightSample {
+        let vector = *position - *point;
+        let distance = vector.magnitude();
+        return LightSample {
+            direction: vector.normalize(),
+            distance,
+            intensity: intensity * attenuation.factor(distance),
+        };
+    }
+}
+
+// Trait for all kinds of lights. The shading and the shadow test only see the samples
+// of a light, so they work the same way for every kind.
+pub trait LightSource {
+    // The intensity used for the ambient reflection
+    fn intensity(&self) -> Color;
+
+    // The samples of the light that reach the point if nothing is in the way. A point
+    // light has one sample and an area light has one for each cell.
+    fn samples(&self, point: &Tuple) -> Vec<LightSample>;
+}
+
+impl LightSource for PointLight {
+    fn intensity(&self) -> Color {
+        return self.intensity;
+    }
+
+    fn samples(&self, point: &Tuple) -> Vec<LightSample> {
+        return vec![LightSample::towards(
+            &self.position,
+            point,
+            self.intensity,
+            self.attenuation,
+        )];
+    }
+}
+
+impl LightSource for AreaLight {
+    fn intensity(&self) -> Color {
+        return self.intensity;
+    }
+
+    // The jitter is seeded from the point, so the shading and the shadow test of a
+    // point use the same positions and a render gives the same image every time
+    fn samples(&self, point: &Tuple) -> Vec<LightSample> {
+        let mut random = Random::from_point(point, 0);
+        let mut samples = Vec::with_capacity(self.sample_count());
+        for v in 0..self.vsteps {
+            for u in 0..self.usteps {
+                let offsets = if self.jitter {
+                    (random.next_f64(), random.next_f64())
+                } else {
+                    (0.5, 0.5)
+                };
+                let position = self.point_on_light(u, v, offsets);
+                samples.push(LightSample::towards(
+                    &position,
+                    point,
+                    self.intensity,
+                    self.attenuation,
+                ));
+            }
+        }
+        return samples;
+    }
+}
+
+impl LightSource for SpotLight {
+    fn intensity(&self) -> Color {
+        return self.intensity;
+    }
+
+    fn samples(&self, point: &Tuple) -> Vec<LightSample> {
+        let intensity = self.intensity * self.falloff(point);
+        return vec![LightSample::towards(
+            &self.position,
+            point,
+            intensity,
+            self.attenuation,
+        )];
+    }
+}
+
+impl LightSource for DirectionalLight {
+    fn intensity(&self) -> Color {
+        return self.intensity;
+    }
+
+    fn samples(&self, _point: &Tuple) -> Vec<LightSample> {
+        return vec![LightSample {
+            direction: -self.direction,
+            distance: f64::INFINITY,
+            intensity: self.intensity,
+        }];
+    }
+}
+
+// The lights of a world
+#[derive(Debug, Clone, PartialEq)]
+pub enum Light {
+    Point(PointLight),
+    Area(AreaLight),
+    Spot(SpotLight),
+    Directional(DirectionalLight),
+}
+
+impl From<PointLight> for Light {
+    fn from(light: PointLight) -> Light {
+        Light::Point(light)
+    }
+}
+
+impl From<AreaLight> for Light {
+    fn from(light: AreaLight) -> Light {
+        Light::Area(light)
+    }
+}
+
+impl From<SpotLight> for Light {
+    fn from(light: SpotLight) -> Light {
+        Light::Spot(light)
+    }
+}
+
+impl From<DirectionalLight> for Light {
+    fn from(light: DirectionalLight) -> Light {
+        Light::Directional(light)
+    }
+}
+
+impl Light {
+    fn source(&self) -> &dyn LightSource {
+        match self {
+            Light::Point(light) => light,
+            Light::Area(light) => light,
+            Light::Spot(light) => light,
+            Light::Directional(light) => light,
         }
     }
 }
 
+impl LightSource for Light {
+    fn intensity(&self) -> Color {
+        return self.source().intensity();
+    }
+
+    fn samples(&self, point: &Tuple) -> Vec<LightSample> {
+        return self.source().samples(point);
+    }
+}
+
 // Lighting calculates the combination of the ambient, diffuse, and specular reflection for a point
 // at a material that is affected by a light and observed at a specified location.
+// The intensity is the fraction of the light that reaches the point (0.0 - 1.0), which
+// is 0.0 for a point in shadow. The diffuse and specular parts are averaged over the
+// samples of the light.
 pub fn lighting(
     material: &Material,
-    light: &PointLight,
+    light: &dyn LightSource,
     point: &Tuple,
     eye_vector: &Tuple,
     normal: &Tuple,
+    intensity: f64,
 ) -> Color {
-    let mut diffuse = Color::color(0.0, 0.0, 0.0);
-    let mut specular = Color::color(0.0, 0.0, 0.0);
+    // Add the components to get the lighting value
+    return ambient_light(material, light)
+        + direct_light(material, light, point, eye_vector, normal, intensity);
+}
 
-    // Combination of the material and the light intensity
-    let effective_color = material.color * light.intensity;
+// The ambient contribution depend only on the material and the light
+pub fn ambient_light(material: &Material, light: &dyn LightSource) -> Color {
+    return material.color * light.intensity() * material.ambient;
+}
 
-    // The ambient contribution depend only on the material and the light
-    let ambient = effective_color * material.ambient;
+// The diffuse and specular parts of lighting, without the ambient light
+pub fn direct_light(
+    material: &Material,
+    light: &dyn LightSource,
+    point: &Tuple,
+    eye_vector: &Tuple,
+    normal: &Tuple,
+    intensity: f64,
+) -> Color {
+    let black = Color::color(0.0, 0.0, 0.0);
+    if intensity <= 0.0 {
+        return black;
+    }
 
-    let light_vector = (light.position - *point).normalize();
-    let light_dot_normal = dot(&light_vector, &normal);
+    let samples = light.samples(point);
+    if samples.is_empty() {
+        return black;
+    }
+    let mut sum = black;
+    for sample in &samples {
+        sum = sum + reflected_light(material, sample, eye_vector, normal);
+    }
+    return sum * (intensity / samples.len() as f64);
+}
 
-    if light_dot_normal > 0.0 {
-        // The diffuse reflection depends only on the angle between the light source and the normal
-        diffuse = effective_color * material.diffuse * light_dot_normal;
+// The diffuse and specular reflection of the light from one sample of a light
+pub fn reflected_light(
+    material: &Material,
+    sample: &LightSample,
+    eye_vector: &Tuple,
+    normal: &Tuple,
+) -> Color {
+    let black = Color::color(0.0, 0.0, 0.0);
+    let light_vector = sample.direction;
+    let light_dot_normal = dot(&light_vector, normal);
+    if light_dot_normal <= 0.0 {
+        return black;
+    }
 
-        // The specular part depends on the angle between the reflection vector and the eye vector
-        // |a| dot |b| = |a||b|cos v, where v is the angle between the vectors a and b
-        // Note that the dot product is the cos value if the vectors are normalized
-        let reflect_vector = reflect(&-light_vector, &normal).normalize();
-        let reflect_dot_eye = dot(&reflect_vector, &eye_vector);
+    // Combination of the material and the light intensity
+    let effective_color = material.color * sample.intensity;
+
+    // The diffuse reflection depends only on the angle between the light source and the normal
+    let diffuse = effective_color * material.diffuse * light_dot_normal;
 
-        if reflect_dot_eye > 0.0 {
-            let factor = reflect_dot_eye.powf(material.shininess);
-            specular = light.intensity * material.specular * factor;
+    match material.model {
+        ShadingModel::Phong => {
+            // The specular part depends on the angle between the reflection vector and the eye vector
+            // |a| dot |b| = |a||b|cos v, where v is the angle between the vectors a and b
+            // Note that the dot product is the cos value if the vectors are normalized
+            let reflect_vector = reflect(&-light_vector, normal).normalize();
+            let reflect_dot_eye = dot(&reflect_vector, eye_vector);
+            return diffuse + highlight(material, sample, reflect_dot_eye);
+        }
+        ShadingModel::BlinnPhong => {
+            let half_vector = (light_vector + *eye_vector).normalize();
+            let half_dot_normal = dot(&half_vector, normal);
+            return diffuse + highlight(material, sample, half_dot_normal);
+        }
+        ShadingModel::Lambert => return diffuse,
+        ShadingModel::OrenNayar { roughness } => {
+            return diffuse * brdf::oren_nayar(roughness, &light_vector, eye_vector, normal);
+        }
+        ShadingModel::MetallicRoughness {
+            metallic,
+            roughness,
+        } => {
+            let brdf = brdf::metallic_roughness(
+                material.color,
+                metallic,
+                roughness,
+                &light_vector,
+                eye_vector,
+                normal,
+            );
+            return brdf * sample.intensity * (PI * light_dot_normal);
         }
     }
+}
 
-    // Add the components to get the lighting value
-    // return ambient + diffuse + specular;
-    return ambient + diffuse + specular;
+// The specular highlight of the Phong models, given the cosine of the angle that
+// decides how close the eye is to the reflection of the light
+fn highlight(material: &Material, sample: &LightSample, cos: f64) -> Color {
+    if cos <= 0.0 {
+        return Color::color(0.0, 0.0, 0.0);
+    }
+    return sample.intensity * material.specular * cos.powf(material.shininess);
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::matrices::Matrix;
+    use std::f64::consts::FRAC_1_SQRT_2;
     const EPSILON: f64 = 0.00001;
 
     pub fn approx_eq(lhs: Color, rhs: Color) -> bool {
@@ -87,7 +629,7 @@ mod tests {
         let normal = Tuple::point(0.0, 0.0, -1.0);
         let light = PointLight::new(Tuple::point(0.0, 0.0, -10.0), Color::color(1.0, 1.0, 1.0));
         assert_eq!(
-            lighting(&m, &light, &position, &eyev, &normal),
+            lighting(&m, &light, &position, &eyev, &normal, 1.0),
             Color::color(1.9, 1.9, 1.9)
         );
     }
@@ -100,7 +642,7 @@ mod tests {
         let normal = Tuple::point(0.0, 0.0, -1.0);
         let light = PointLight::new(Tuple::point(0.0, 0.0, -10.0), Color::color(1.0, 1.0, 1.0));
         assert_eq!(
-            lighting(&m, &light, &position, &eyev, &normal),
+            lighting(&m, &light, &position, &eyev, &normal, 1.0),
             Color::color(1.0, 1.0, 1.0)
         );
     }
@@ -113,7 +655,7 @@ mod tests {
         let normal = Tuple::point(0.0, 0.0, -1.0);
         let light = PointLight::new(Tuple::point(0.0, 10.0, -10.0), Color::color(1.0, 1.0, 1.0));
         assert!(approx_eq(
-            lighting(&m, &light, &position, &eyev, &normal),
+            lighting(&m, &light, &position, &eyev, &normal, 1.0),
             Color::color(0.7364, 0.7364, 0.7364)
         ));
     }
@@ -126,7 +668,7 @@ mod tests {
         let normal = Tuple::point(0.0, 0.0, -1.0);
         let light = PointLight::new(Tuple::point(0.0, 10.0, -10.0), Color::color(1.0, 1.0, 1.0));
         assert!(approx_eq(
-            lighting(&m, &light, &position, &eyev, &normal),
+            lighting(&m, &light, &position, &eyev, &normal, 1.0),
             Color::color(1.6364, 1.6364, 1.6364)
         ));
     }
@@ -139,8 +681,450 @@ mod tests {
         let normal = Tuple::point(0.0, 0.0, -1.0);
         let light = PointLight::new(Tuple::point(0.0, 0.0, 10.0), Color::color(1.0, 1.0, 1.0));
         assert!(approx_eq(
-            lighting(&m, &light, &position, &eyev, &normal),
+            lighting(&m, &light, &position, &eyev, &normal, 1.0),
+            Color::color(0.1, 0.1, 0.1)
+        ));
+    }
+
+    #[test]
+    fn lighting_with_the_surface_in_shadow() {
+        let m = Material::new();
+        let position = Tuple::point(0.0, 0.0, 0.0);
+        let eyev = Tuple::vector(0.0, 0.0, -1.0);
+        let normal = Tuple::vector(0.0, 0.0, -1.0);
+        let light = PointLight::new(Tuple::point(0.0, 0.0, -10.0), Color::color(1.0, 1.0, 1.0));
+        assert_eq!(
+            lighting(&m, &light, &position, &eyev, &normal, 0.0),
             Color::color(0.1, 0.1, 0.1)
+        );
+    }
+
+    #[test]
+    fn lighting_with_other_shading_models() {
+        let position = Tuple::point(0.0, 0.0, 0.0);
+        let normal = Tuple::vector(0.0, 0.0, -1.0);
+        let light = PointLight::new(Tuple::point(0.0, 10.0, -10.0), Color::color(1.0, 1.0, 1.0));
+        let shade = |model, shininess, eyev: &Tuple| {
+            let m = Material {
+                model,
+                shininess,
+                ..Material::new()
+            };
+            return lighting(&m, &light, &position, eyev, &normal, 1.0);
+        };
+
+        // The half vector is halfway between the light and the eye, so Blinn-Phong has
+        // a larger highlight than Phong with the same shininess
+        let eyev = Tuple::vector(0.0, 0.0, -1.0);
+        assert!(approx_eq(
+            shade(ShadingModel::Phong, 10.0, &eyev),
+            Color::color(0.76452, 0.76452, 0.76452)
+        ));
+        assert!(approx_eq(
+            shade(ShadingModel::BlinnPhong, 10.0, &eyev),
+            Color::color(1.14415, 1.14415, 1.14415)
+        ));
+
+        // Lambert has no highlight even with the eye in the path of the reflection
+        let eyev = Tuple::vector(0.0, -FRAC_1_SQRT_2, -FRAC_1_SQRT_2);
+        assert!(approx_eq(
+            shade(ShadingModel::Lambert, 200.0, &eyev),
+            Color::color(0.73640, 0.73640, 0.73640)
+        ));
+        assert!(approx_eq(
+            shade(ShadingModel::OrenNayar { roughness: 0.0 }, 200.0, &eyev),
+            Color::color(0.73640, 0.73640, 0.73640)
+        ));
+    }
+
+    #[test]
+    fn oren_nayar_is_darker_head_on() {
+        let m = Material {
+            model: ShadingModel::OrenNayar { roughness: 0.5 },
+            ..Material::new()
+        };
+        let position = Tuple::point(0.0, 0.0, 0.0);
+        let eyev = Tuple::vector(0.0, 0.0, -1.0);
+        let normal = Tuple::vector(0.0, 0.0, -1.0);
+        let light = PointLight::new(Tuple::point(0.0, 0.0, -10.0), Color::color(1.0, 1.0, 1.0));
+        assert!(approx_eq(
+            lighting(&m, &light, &position, &eyev, &normal, 1.0),
+            Color::color(0.80603, 0.80603, 0.80603)
         ));
     }
+
+    #[test]
+    fn lighting_with_a_metallic_roughness_material() {
+        // A fully rough dielectric lit head on reflects 96% diffuse light and a small
+        // highlight: D = 1/π, G = 1 and F = 0.04 give π * 0.04 / (4π) = 0.01
+        let m = Material {
+            model: ShadingModel::MetallicRoughness {
+                metallic: 0.0,
+                roughness: 1.0,
+            },
+            ..Material::new()
+        };
+        let position = Tuple::point(0.0, 0.0, 0.0);
+        let eyev = Tuple::vector(0.0, 0.0, -1.0);
+        let normal = Tuple::vector(0.0, 0.0, -1.0);
+        let light = PointLight::new(Tuple::point(0.0, 0.0, -10.0), Color::color(1.0, 1.0, 1.0));
+        assert!(approx_eq(
+            lighting(&m, &light, &position, &eyev, &normal, 1.0),
+            Color::color(1.07, 1.07, 1.07)
+        ));
+
+        // A smooth metal only reflects the light towards the eye in the mirror direction
+        let metal = Material {
+            color: Color::color(1.0, 0.8, 0.4),
+            model: ShadingModel::MetallicRoughness {
+                metallic: 1.0,
+                roughness: 0.2,
+            },
+            ..Material::new()
+        };
+        let light = PointLight::new(Tuple::point(0.0, 10.0, -10.0), Color::color(1.0, 1.0, 1.0));
+        let eyev = Tuple::vector(0.0, -FRAC_1_SQRT_2, -FRAC_1_SQRT_2);
+        let highlight = lighting(&metal, &light, &position, &eyev, &normal, 1.0);
+        let eyev = Tuple::vector(0.0, FRAC_1_SQRT_2, -FRAC_1_SQRT_2);
+        let elsewhere = lighting(&metal, &light, &position, &eyev, &normal, 1.0);
+        assert!(highlight.red > 1.0, "{:?}", highlight);
+        assert!(elsewhere.red < 0.11, "{:?}", elsewhere);
+    }
+
+    #[test]
+    fn create_area_light() {
+        let light = AreaLight::new(
+            Tuple::point(0.0, 0.0, 0.0),
+            Tuple::vector(2.0, 0.0, 0.0),
+            4,
+            Tuple::vector(0.0, 0.0, 1.0),
+            2,
+            Color::color(1.0, 1.0, 1.0),
+        )
+        .unwrap();
+        assert_eq!(light.uvec, Tuple::vector(0.5, 0.0, 0.0));
+        assert_eq!(light.vvec, Tuple::vector(0.0, 0.0, 0.5));
+        assert_eq!(light.sample_count(), 8);
+        assert_eq!(light.position(), Tuple::point(1.0, 0.0, 0.5));
+    }
+
+    #[test]
+    fn area_light_needs_steps() {
+        for (usteps, vsteps) in [(0, 2), (4, 0)] {
+            let light = AreaLight::new(
+                Tuple::point(0.0, 0.0, 0.0),
+                Tuple::vector(2.0, 0.0, 0.0),
+                usteps,
+                Tuple::vector(0.0, 0.0, 1.0),
+                vsteps,
+                Color::color(1.0, 1.0, 1.0),
+            );
+            assert!(light.is_none());
+        }
+    }
+
+    #[test]
+    fn points_on_area_light() {
+        let mut light = AreaLight::new(
+            Tuple::point(0.0, 0.0, 0.0),
+            Tuple::vector(2.0, 0.0, 0.0),
+            4,
+            Tuple::vector(0.0, 0.0, 1.0),
+            2,
+            Color::color(1.0, 1.0, 1.0),
+        )
+        .unwrap();
+        assert_eq!(
+            light.point_on_light(0, 0, (0.5, 0.5)),
+            Tuple::point(0.25, 0.0, 0.25)
+        );
+        assert_eq!(
+            light.point_on_light(3, 1, (0.5, 0.5)),
+            Tuple::point(1.75, 0.0, 0.75)
+        );
+        assert_eq!(
+            light.point_on_light(2, 0, (0.3, 0.7)),
+            Tuple::point(1.15, 0.0, 0.35)
+        );
+
+        // Without jitter the samples are at the centers of the cells
+        light.jitter = false;
+        let point = Tuple::point(0.0, 5.0, 0.0);
+        let positions = |samples: Vec<LightSample>| -> Vec<Tuple> {
+            samples
+                .iter()
+                .map(|sample| point + sample.direction * sample.distance)
+                .collect()
+        };
+        let centers = positions(light.samples(&point));
+        assert_eq!(centers.len(), 8);
+        assert_eq!(centers[7], Tuple::point(1.75, 0.0, 0.75));
+
+        // With jitter each sample stays in its cell and a point always gets the same
+        // samples
+        light.jitter = true;
+        let light = Light::from(light);
+        let jittered = positions(light.samples(&point));
+        assert_eq!(jittered, positions(light.samples(&point)));
+        assert_ne!(
+            light.samples(&point),
+            light.samples(&Tuple::point(0.0, 6.0, 0.0))
+        );
+        assert!((jittered[7].x - 1.75).abs() <= 0.25);
+        assert!((jittered[7].z - 0.75).abs() <= 0.25);
+    }
+
+    #[test]
+    fn lighting_samples_the_area_light() {
+        let mut light = AreaLight::new(
+            Tuple::point(-0.5, -0.5, -5.0),
+            Tuple::vector(1.0, 0.0, 0.0),
+            2,
+            Tuple::vector(0.0, 1.0, 0.0),
+            2,
+            Color::color(1.0, 1.0, 1.0),
+        )
+        .unwrap();
+        light.jitter = false;
+        let light = Light::from(light);
+        let m = Material {
+            ambient: 0.1,
+            diffuse: 0.9,
+            specular: 0.0,
+            color: Color::color(1.0, 1.0, 1.0),
+            ..Material::new()
+        };
+        let eye = Tuple::point(0.0, 0.0, -5.0);
+        for (point, expected) in [
+            (Tuple::point(0.0, 0.0, -1.0), 0.9965),
+            (Tuple::point(0.0, FRAC_1_SQRT_2, -FRAC_1_SQRT_2), 0.62318),
+        ] {
+            let eyev = (eye - point).normalize();
+            let normal = Tuple::vector(point.x, point.y, point.z);
+            assert!(approx_eq(
+                lighting(&m, &light, &point, &eyev, &normal, 1.0),
+                Color::color(expected, expected, expected)
+            ));
+        }
+    }
+
+    #[test]
+    fn spot_light_fades_out_between_the_angles() {
+        let light = SpotLight::new(
+            Tuple::point(0.0, 0.0, 0.0),
+            Tuple::vector(0.0, 0.0, 2.0),
+            PI / 8.0,
+            PI / 4.0,
+            Color::color(1.0, 1.0, 1.0),
+        );
+        assert_eq!(light.direction, Tuple::vector(0.0, 0.0, 1.0));
+        assert_eq!(light.falloff(&Tuple::point(0.0, 0.0, 5.0)), 1.0);
+        assert_eq!(light.falloff(&Tuple::point(1.0, 0.0, 5.0)), 1.0);
+        assert_eq!(light.falloff(&Tuple::point(5.0, 0.0, 5.0)), 0.0);
+        assert_eq!(light.falloff(&Tuple::point(0.0, 0.0, -5.0)), 0.0);
+
+        // Halfway between the cosines of the angles
+        let angle = ((PI / 8.0).cos() / 2.0 + (PI / 4.0).cos() / 2.0).acos();
+        let point = Tuple::point(angle.tan(), 0.0, 1.0);
+        assert!((light.falloff(&point) - 0.5).abs() < EPSILON);
+        let samples = light.samples(&point);
+        assert!(approx_eq(samples[0].intensity, Color::color(0.5, 0.5, 0.5)));
+        assert!((samples[0].distance - point.magnitude()).abs() < EPSILON);
+    }
+
+    #[test]
+    fn lighting_with_a_spot_light() {
+        let m = Material::new();
+        let position = Tuple::point(0.0, 0.0, 0.0);
+        let eyev = Tuple::vector(0.0, 0.0, -1.0);
+        let normal = Tuple::vector(0.0, 0.0, -1.0);
+        let towards = SpotLight::new(
+            Tuple::point(0.0, 0.0, -10.0),
+            Tuple::vector(0.0, 0.0, 1.0),
+            PI / 8.0,
+            PI / 4.0,
+            Color::color(1.0, 1.0, 1.0),
+        );
+        assert_eq!(
+            lighting(&m, &towards, &position, &eyev, &normal, 1.0),
+            Color::color(1.9, 1.9, 1.9)
+        );
+
+        // Only the ambient reflection when the point is outside the cone
+        let away = SpotLight {
+            direction: Tuple::vector(0.0, 1.0, 0.0),
+            ..towards
+        };
+        assert_eq!(
+            lighting(&m, &away, &position, &eyev, &normal, 1.0),
+            Color::color(0.1, 0.1, 0.1)
+        );
+    }
+
+    #[test]
+    fn directional_light_is_the_same_everywhere() {
+        let light =
+            DirectionalLight::new(Tuple::vector(0.0, -2.0, 0.0), Color::color(1.0, 1.0, 1.0));
+        for point in [Tuple::point(0.0, 0.0, 0.0), Tuple::point(100.0, -5.0, 3.0)] {
+            let samples = light.samples(&point);
+            assert_eq!(samples.len(), 1);
+            assert_eq!(samples[0].direction, Tuple::vector(0.0, 1.0, 0.0));
+            assert_eq!(samples[0].distance, f64::INFINITY);
+        }
+
+        let m = Material::new();
+        let position = Tuple::point(0.0, 0.0, 0.0);
+        let normal = Tuple::vector(0.0, 1.0, 0.0);
+        assert_eq!(
+            lighting(&m, &Light::from(light), &position, &normal, &normal, 1.0),
+            Color::color(1.9, 1.9, 1.9)
+        );
+    }
+
+    #[test]
+    fn attenuation_factors() {
+        assert_eq!(Attenuation::None.factor(10.0), 1.0);
+        assert_eq!(Attenuation::Linear.factor(4.0), 0.25);
+        assert_eq!(Attenuation::InverseSquare.factor(4.0), 0.0625);
+        let attenuation = Attenuation::Coefficients {
+            constant: 1.0,
+            linear: 0.5,
+            quadratic: 0.25,
+        };
+        assert_eq!(attenuation.factor(0.0), 1.0);
+        assert_eq!(attenuation.factor(2.0), 1.0 / 3.0);
+        // Coefficients that would divide by zero leave the intensity unchanged
+        assert_eq!(Attenuation::InverseSquare.factor(0.0), 1.0);
+    }
+
+    #[test]
+    fn attenuation_applies_to_diffuse_and_specular() {
+        let m = Material::new();
+        let position = Tuple::point(0.0, 0.0, 0.0);
+        let eyev = Tuple::vector(0.0, 0.0, -1.0);
+        let normal = Tuple::vector(0.0, 0.0, -1.0);
+        let mut light = PointLight::new(Tuple::point(0.0, 0.0, -2.0), Color::color(1.0, 1.0, 1.0));
+        light.attenuation = Attenuation::InverseSquare;
+        let samples = light.samples(&position);
+        assert_eq!(samples[0].intensity, Color::color(0.25, 0.25, 0.25));
+        // The ambient part is not attenuated: 0.1 + (0.9 + 0.9) / 4
+        assert!(approx_eq(
+            lighting(&m, &light, &position, &eyev, &normal, 1.0),
+            Color::color(0.55, 0.55, 0.55)
+        ));
+
+        let mut spot = SpotLight::new(
+            Tuple::point(0.0, 0.0, -2.0),
+            Tuple::vector(0.0, 0.0, 1.0),
+            0.1,
+            0.2,
+            Color::color(1.0, 1.0, 1.0),
+        );
+        spot.attenuation = Attenuation::Linear;
+        assert_eq!(
+            spot.samples(&position)[0].intensity,
+            Color::color(0.5, 0.5, 0.5)
+        );
+
+        let mut area = AreaLight::new(
+            Tuple::point(-0.5, -0.5, -2.0),
+            Tuple::vector(1.0, 0.0, 0.0),
+            1,
+            Tuple::vector(0.0, 1.0, 0.0),
+            1,
+            Color::color(1.0, 1.0, 1.0),
+        )
+        .unwrap();
+        area.jitter = false;
+        area.attenuation = Attenuation::InverseSquare;
+        assert_eq!(
+            area.samples(&position)[0].intensity,
+            Color::color(0.25, 0.25, 0.25)
+        );
+    }
+
+    #[test]
+    fn shape_light_is_like_a_point_light_from_far_away() {
+        let mut shape = Sphere::new();
+        shape.transformation = Matrix::new_identity()
+            .scale(0.5, 0.5, 0.5)
+            .translate(0.0, 0.0, -20.0);
+        shape.material.emission = Color::color(400.0, 400.0, 400.0);
+        let light = ShapeLight {
+            shape: &shape,
+            samples: 64,
+            seed: 1,
+        };
+        let point = Tuple::point(0.0, 0.0, 0.0);
+        let samples = light.samples(&point);
+        assert_eq!(samples.len(), 64);
+        for sample in &samples {
+            assert!((sample.distance - 19.5).abs() < 0.5);
+            assert!(dot(&sample.direction, &Tuple::vector(0.0, 0.0, -1.0)) > 0.999);
+        }
+
+        // A disc with radius 0.5 at distance 20 covers a solid angle of about
+        // pi * 0.5^2 / 20^2, which gives 400 / 1600 = 0.25 of the emission
+        let mean = samples.iter().map(|s| s.intensity.red).sum::<f64>() / 64.0;
+        assert!((mean - 0.25).abs() < 0.02, "mean intensity {}", mean);
+
+        // The visible part of a sphere is sampled evenly, so every sample of a sphere
+        // that is not stretched has the same intensity
+        for sample in &samples {
+            assert!((sample.intensity.red - mean).abs() < 0.0001);
+        }
+    }
+
+    #[test]
+    fn shape_light_pdf_matches_the_samples() {
+        let mut shape = Sphere::new();
+        shape.transformation = Matrix::new_identity()
+            .scale(2.0, 0.5, 1.0)
+            .translate(0.0, 0.0, -5.0);
+        shape.material.emission = Color::color(1.0, 1.0, 1.0);
+        let light = ShapeLight {
+            shape: &shape,
+            samples: 16,
+            seed: 1,
+        };
+
+        // The intensity of a sample is the emission divided by pi times the pdf
+        let point = Tuple::point(0.5, 0.0, 0.0);
+        for sample in light.samples(&point) {
+            let surface = point + sample.direction * sample.distance;
+            let pdf = light.pdf(&point, &surface);
+            assert!((sample.intensity.red * PI * pdf - 1.0).abs() < 0.0001);
+        }
+
+        // Only the front of the shape is sampled
+        assert!(light.pdf(&point, &Tuple::point(0.0, 0.0, -4.0)) > 0.0);
+        assert_eq!(light.pdf(&point, &Tuple::point(0.0, 0.0, -6.0)), 0.0);
+    }
+
+    #[test]
+    fn shape_light_has_no_samples_inside_the_shape() {
+        let mut shape = Sphere::new();
+        shape.material.emission = Color::color(1.0, 1.0, 1.0);
+        let light = ShapeLight {
+            shape: &shape,
+            samples: 4,
+            seed: 1,
+        };
+        assert!(light.samples(&Tuple::point(0.0, 0.5, 0.0)).is_empty());
+        let surface = Tuple::point(0.0, 1.0, 0.0);
+        assert_eq!(light.pdf(&Tuple::point(0.0, 0.5, 0.0), &surface), 0.0);
+        let m = Material::new();
+        let normal = Tuple::vector(0.0, 1.0, 0.0);
+        assert_eq!(
+            lighting(
+                &m,
+                &light,
+                &Tuple::point(0.0, 0.5, 0.0),
+                &normal,
+                &normal,
+                1.0
+            ),
+            Color::color(0.0, 0.0, 0.0)
+        );
+    }
 }
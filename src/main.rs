@@ -1,25 +1,20 @@
-mod camera;
-mod canvas;
-mod color;
-mod lights;
-mod matrices;
-mod ppm;
-mod rays;
-mod shapes;
-mod tuple;
-mod world;
-
-use crate::camera::Camera;
-use crate::canvas::{Canvas, Coordinate};
-use crate::color::Color;
-use crate::lights::{lighting, PointLight};
-use crate::rays::hit;
-use crate::shapes::{Normal, Sphere};
-use crate::world::World;
-use matrices::{to_tuple, Matrix};
-use ppm::Ppm;
+// Explicit returns are part of the code style
+#![allow(clippy::needless_return)]
+
+// The demo commands of the ray tracer. The ray tracer itself is in the library crate.
+
+mod cli;
+
+use crate::cli::{Config, Options};
+use ray_tracer::matrices::to_tuple;
+use ray_tracer::output::{self, GifEncoder, GifOptions, OutputTransform};
+use ray_tracer::shapes::{self, Normal};
+use ray_tracer::{hit, lighting, load_scene, Filter, Integrator, Mapping, Ray, Texture};
+use ray_tracer::{Camera, Canvas, Color, Material, Matrix, PointLight, Sphere, Tuple, World};
+use std::fs::File;
+use std::io::{self, BufWriter};
+use std::path::Path;
 use std::{env, f64, process};
-use tuple::Tuple;
 
 struct Projectile {
     position: Tuple,
@@ -31,19 +26,69 @@ struct Environment {
     wind: Tuple,
 }
 
-struct Config {
-    command: String,
+// The name of the output file: the --output option or the default name. If only
+// --format is given, the extension of the default name is replaced.
+fn output_filename(options: &Options, default: &str) -> String {
+    match (&options.output, options.format) {
+        (Some(output), _) => output.clone(),
+        (None, Some(format)) => Path::new(default)
+            .with_extension(format.extension())
+            .to_string_lossy()
+            .into_owned(),
+        (None, None) => default.to_string(),
+    }
 }
 
-impl Config {
-    fn build(args: &[String]) -> Result<Config, &'static str> {
-        if args.len() != 2 {
-            return Err("Expect <command>");
-        }
+// The output transform given by the --exposure, --tonemap and --srgb options
+fn output_transform(options: &Options) -> OutputTransform {
+    return OutputTransform {
+        exposure: options.exposure,
+        operator: options.tonemap,
+        srgb: options.srgb,
+    };
+}
+
+// Save the canvas in the image format given by the --format option or the file
+// extension, after applying the output transform. Exit if the file cannot be written.
+fn save_canvas(canvas: &Canvas, filename: &str, options: &Options) {
+    let result = match options
+        .format
+        .or_else(|| output::Format::from_path(filename))
+    {
+        Some(format) => output::save_with(canvas, filename, format, &output_transform(options)),
+        None => output::save(canvas, filename),
+    };
+    if let Err(err) = result {
+        eprintln!("Could not write {}: {}", filename, err);
+        process::exit(1);
+    }
+    println!("Image saved in file: {}", filename);
+}
 
-        let command = args[1].clone();
+// Apply the size, field of view, samples and threads options to a camera
+fn configure_camera(camera: &Camera, options: &Options) -> Camera {
+    let mut configured = Camera::new(
+        options.width.unwrap_or(camera.hsize),
+        options.height.unwrap_or(camera.vsize),
+        options.field_of_view.unwrap_or(camera.field_of_view),
+    );
+    configured.transform = camera.transform;
+    configured.samples = options.samples;
+    configured.threads = options.threads;
+    configured.integrator = match (options.integrator, options.max_depth) {
+        (Some(Integrator::PathTracing { .. }), Some(max_depth)) => {
+            Integrator::PathTracing { max_depth }
+        }
+        (Some(integrator), _) => integrator,
+        (None, _) => camera.integrator,
+    };
+    return configured;
+}
 
-        Ok(Config { command })
+// Apply the ambient occlusion options to a world
+fn configure_world(world: &mut World, options: &Options) {
+    if options.ambient_occlusion.is_some() {
+        world.ambient_occlusion = options.ambient_occlusion;
     }
 }
 
@@ -52,24 +97,18 @@ fn tick(environment: &Environment, projectile: &mut Projectile) {
     projectile.velocity = projectile.velocity + environment.gravity + environment.wind;
 }
 
-fn get_canvas_coordinate(x: f64, y: f64, height: usize) -> Coordinate {
-    return Coordinate {
-        x: x as usize,
-        y: height - y as usize,
-    };
-}
-
 fn print_trajectory(canvas: &mut Canvas, projectile: &mut Projectile, environment: Environment) {
     let white = Color::color(1.0, 1.0, 1.0);
     while projectile.position.y > 0.0 {
+        let (x0, y0) = canvas.y_up(projectile.position.x, projectile.position.y);
         tick(&environment, projectile);
-        let c = get_canvas_coordinate(projectile.position.x, projectile.position.y, canvas.height);
-        canvas.write_pixel(c.x, c.y, white);
+        let (x1, y1) = canvas.y_up(projectile.position.x, projectile.position.y);
+        canvas.draw_line(x0, y0, x1, y1, white);
     }
 }
 
-fn create_trajectory() {
-    println!("Creating a trajectory image: trajectory.ppm");
+fn create_trajectory(options: &Options) {
+    println!("Creating a trajectory image");
     let mut projectile = Projectile {
         position: Tuple::point(0.0, 1.0, 0.0),
         velocity: Tuple::vector(1.0, 1.8, 0.0).normalize() * 11.25,
@@ -80,16 +119,20 @@ fn create_trajectory() {
         wind: Tuple::vector(-0.01, 0.0, 0.0),
     };
 
-    let mut image = Ppm::new(&"trajectory.ppm".to_string());
     let mut canvas = Canvas::new(900, 550);
     print_trajectory(&mut canvas, &mut projectile, garden);
-    image.add_canvas(canvas);
-    image.write_file();
+    save_canvas(
+        &canvas,
+        &output_filename(options, "trajectory.png"),
+        options,
+    );
 }
 
 fn print_clock_hours(canvas: &mut Canvas) {
     let white = Color::color(1.0, 1.0, 1.0);
-    canvas.write_pixel(200, 200, white);
+    let (x, y) = canvas.y_up(200.0, 200.0);
+    canvas.fill_circle(x, y, 2.0, white);
+    canvas.draw_circle(x, y, 120.0, white);
 
     for hour in 0..12 {
         let p = Tuple::point(0.0, 1.0, 0.0);
@@ -98,18 +141,16 @@ fn print_clock_hours(canvas: &mut Canvas) {
             .rotate_z(-f64::consts::PI * 2.0 * hour as f64 / 12.0)
             .translate(200.0, 200.0, 0.0);
         let dot = to_tuple(&(&transform * &p));
-        let c = get_canvas_coordinate(dot.x, dot.y, canvas.height);
-        canvas.write_pixel(c.x, c.y, white);
+        let (x, y) = canvas.y_up(dot.x, dot.y);
+        canvas.fill_circle(x, y, 5.0, white);
     }
 }
 
-fn create_clock() {
-    println!("Creating a clock image: clock.ppm");
-    let mut image = Ppm::new(&"clock.ppm".to_string());
+fn create_clock(options: &Options) {
+    println!("Creating a clock image");
     let mut canvas = Canvas::new(400, 400);
     print_clock_hours(&mut canvas);
-    image.add_canvas(canvas);
-    image.write_file();
+    save_canvas(&canvas, &output_filename(options, "clock.png"), options);
 }
 
 fn generate_sphere_shadow(canvas: &mut Canvas) {
@@ -133,26 +174,22 @@ fn generate_sphere_shadow(canvas: &mut Canvas) {
 
             // Point on the wall that the ray targets
             let wall_point = Tuple::point(world_x, world_y, wall_z);
-            let r = rays::Ray::new(ray_origin, wall_point);
+            let r = Ray::new(ray_origin, wall_point);
             let xs = r.intersects(&shape);
 
             // If there is a hit, the sphere casts a 'shadow' on the wall
-            match hit(&xs) {
-                Some(_) => canvas.write_pixel(x, y, shadow),
-                None => (),
+            if hit(&xs).is_some() {
+                canvas.write_pixel(x, y, shadow);
             }
         }
     }
 }
 
-fn trace_shadow() {
+fn trace_shadow(options: &Options) {
     println!("Primitive ray tracing of a sphere's 'shadow' on a wall. Please wait...");
-    let mut image = Ppm::new(&"shadow.ppm".to_string());
     let mut canvas = Canvas::new(300, 300);
     generate_sphere_shadow(&mut canvas);
-    image.add_canvas(canvas);
-    image.write_file();
-    println!("Image saved in file: shadow.ppm");
+    save_canvas(&canvas, &output_filename(options, "shadow.png"), options);
 }
 
 fn generate_phong_reflection(canvas: &mut Canvas) {
@@ -180,31 +217,25 @@ fn generate_phong_reflection(canvas: &mut Canvas) {
 
             // Point on the wall that the ray targets
             let wall_point = Tuple::point(world_x, world_y, wall_z);
-            let r = rays::Ray::new(ray_origin, wall_point);
+            let r = Ray::new(ray_origin, wall_point);
             let xs = r.intersects(&shape);
 
-            match hit(&xs) {
-                Some(xs) => {
-                    let point = r.position(xs.t);
-                    let normal = xs.object.normal_at(&point);
-                    let eyev = -r.direction.normalize();
-                    let color = lighting(&xs.object.material, &light, &point, &eyev, &normal);
-                    canvas.write_pixel(x, y, color);
-                }
-                None => (),
+            if let Some(xs) = hit(&xs) {
+                let point = r.position(xs.t);
+                let normal = xs.object.normal_at(&point);
+                let eyev = -r.direction.normalize();
+                let color = lighting(&xs.object.material, &light, &point, &eyev, &normal, 1.0);
+                canvas.write_pixel(x, y, color);
             }
         }
     }
 }
 
-fn phong_reflection() {
+fn phong_reflection(options: &Options) {
     println!("Ray tracing using the Phong reflection model. Please wait...");
-    let mut image = Ppm::new(&"sphere.ppm".to_string());
     let mut canvas = Canvas::new(300, 300);
     generate_phong_reflection(&mut canvas);
-    image.add_canvas(canvas);
-    image.write_file();
-    println!("Image saved in file: sphere.ppm");
+    save_canvas(&canvas, &output_filename(options, "sphere.png"), options);
 }
 
 fn pre_configure_world(light_x: f64) -> World {
@@ -213,13 +244,16 @@ fn pre_configure_world(light_x: f64) -> World {
         Tuple::point(light_x, 10.0, -10.0),
         Color::color(1.0, 1.0, 1.0),
     );
-    world.lights.push(light);
+    world.lights.push(light.into());
+
+    // The floor and the walls are just transformed spheres with the same material
+    let mut wall_material = Material::new();
+    wall_material.color = Color::color(1., 0.9, 0.9);
+    wall_material.specular = 0.;
 
-    // The floor and the walls are just transformed spheres
     let mut floor = Sphere::new();
     floor.transformation = Matrix::new_identity().scale(10., 0.01, 10.);
-    floor.material.color = Color::color(1., 0.9, 0.9);
-    floor.material.specular = 0.;
+    floor.material = wall_material.clone();
     world.objects.push(floor);
 
     // Left wall
@@ -229,8 +263,7 @@ fn pre_configure_world(light_x: f64) -> World {
         .rotate_x(f64::consts::PI / 2.)
         .rotate_y(-f64::consts::PI / 4.)
         .translate(0., 0., 5.);
-    left_wall.material.color = Color::color(1., 0.9, 0.9);
-    left_wall.material.specular = 0.;
+    left_wall.material = wall_material.clone();
     world.objects.push(left_wall);
 
     // Right wall
@@ -240,8 +273,7 @@ fn pre_configure_world(light_x: f64) -> World {
         .rotate_x(f64::consts::PI / 2.)
         .rotate_y(f64::consts::PI / 4.)
         .translate(0., 0., 5.);
-    right_wall.material.color = Color::color(1., 0.9, 0.9);
-    right_wall.material.specular = 0.;
+    right_wall.material = wall_material;
     world.objects.push(right_wall);
 
     // Large sphere in the middle: Blue and translated slightly upward
@@ -275,34 +307,45 @@ fn pre_configure_world(light_x: f64) -> World {
     world
 }
 
-fn planets_world(angle: f64) -> World {
+// The Earth texture of the planets, an equirectangular image built into the binary
+fn earth_texture() -> Texture {
+    const EARTH: &[u8] = include_bytes!("../scenes/earth.ppm");
+    let image = output::read(&mut &EARTH[..], output::Format::Ppm(output::PpmFormat::P6))
+        .expect("the Earth texture is a valid PPM image");
+    return Texture::new(image, Mapping::Spherical, Filter::Bilinear)
+        .expect("the Earth texture is not empty");
+}
+
+// Both planets are the Earth, spinning once during the animation
+fn planets_world(angle: f64, earth: &Texture) -> World {
     let mut world = World::new();
     let light = PointLight::new(Tuple::point(-10., 10.0, -10.0), Color::color(1.0, 1.0, 1.0));
-    world.lights.push(light);
+    world.lights.push(light.into());
 
-    // Large sphere in the middle: Blue
+    // Large planet in the middle
     let mut middle = Sphere::new();
-    middle.transformation = Matrix::new_identity();
-    middle.material.color = Color::color(0.0, 0.5, 1.0);
+    middle.transformation = Matrix::new_identity().rotate_y(-angle).rotate_x(0.4);
+    middle.material.texture = Some(earth.clone());
     middle.material.diffuse = 0.7;
     middle.material.specular = 0.3;
     world.objects.push(middle);
 
-    // Rotating planet
+    // Orbiting planet
     let mut left = Sphere::new();
     let x_pos = angle.cos() * 4.;
     let z_pos = angle.sin() * 4.;
     left.transformation = Matrix::new_identity()
+        .rotate_y(-angle)
         .scale(0.33, 0.33, 0.33)
         .translate(x_pos, 0., z_pos);
-    left.material.color = Color::color(1.0, 0.8, 0.1);
+    left.material.texture = Some(earth.clone());
     left.material.diffuse = 0.7;
     left.material.specular = 0.3;
     world.objects.push(left);
 
     world
 }
-fn ray_trace_world() {
+fn ray_trace_world(options: &Options) {
     println!("Ray tracing a pre-configured world using the Phong reflection model. Please wait...");
     let mut camera = Camera::new(600, 300, f64::consts::PI / 3.);
     camera.set_view_transformation(
@@ -310,73 +353,152 @@ fn ray_trace_world() {
         &Tuple::point(0., 1., 0.),
         &Tuple::vector(0., 1., 0.),
     );
-    let world = pre_configure_world(-10.);
-
-    let mut image = Ppm::new(&"world.ppm".to_string());
-    image.add_canvas(camera.render(&world));
-    image.write_file();
-    println!("Image saved in file: world.ppm");
+    let camera = configure_camera(&camera, options);
+    let mut world = pre_configure_world(-10.);
+    configure_world(&mut world, options);
+
+    save_canvas(
+        &camera.render(&world),
+        &output_filename(options, "world.png"),
+        options,
+    );
 }
 
-fn ray_trace_planets() {
+// The frames are written to an animated GIF, or to numbered images if the output
+// file has another extension, for example planets.png gives planets-000.png,
+// planets-001.png and so on.
+fn ray_trace_planets(options: &Options) {
     println!("Creating an animation of planets. WARNING! Takes a long time. Please wait...");
-    const FRAMES: i32 = 200;
-    const NAME: &str = "planet";
+    const FRAMES: usize = 200;
+    let filename = output_filename(options, "planets.gif");
+    let (first, last) = options.frames.unwrap_or((0, FRAMES - 1));
+    if last >= FRAMES {
+        eprintln!("The animation has only {} frames", FRAMES);
+        process::exit(2);
+    }
+
     let mut camera = Camera::new(600, 300, f64::consts::PI / 3.);
+    camera.set_view_transformation(
+        &Tuple::point(0., 1.5, -8.),
+        &Tuple::point(0., 0., 0.),
+        &Tuple::vector(0., 1., 0.),
+    );
+    let camera = configure_camera(&camera, options);
+
+    let path = Path::new(&filename);
+    let is_gif = options.format.is_none()
+        && path
+            .extension()
+            .is_some_and(|extension| extension.eq_ignore_ascii_case("gif"));
+    let exit_on_error = |err: io::Error| -> ! {
+        eprintln!("Could not write {}: {}", filename, err);
+        process::exit(1);
+    };
+    let mut encoder = if is_gif {
+        let encoder = File::create(&filename).and_then(|file| {
+            GifEncoder::new(
+                BufWriter::new(file),
+                camera.hsize,
+                camera.vsize,
+                GifOptions::new(),
+            )
+        });
+        Some(encoder.unwrap_or_else(|err| exit_on_error(err)))
+    } else {
+        None
+    };
 
-    for frame in 0..FRAMES {
+    let earth = earth_texture();
+    for frame in first..=last {
         let angle = f64::consts::PI * 2. / FRAMES as f64 * frame as f64;
-        camera.set_view_transformation(
-            &Tuple::point(0., 1.5, -8.),
-            &Tuple::point(0., 0., 0.),
-            &Tuple::vector(0., 1., 0.),
-        );
-        let world = planets_world(angle);
-
-        let name = if frame < 10 {
-            format!("{}-00{}.ppm", NAME, frame)
-        } else if frame < 100 {
-            format!("{}-0{}.ppm", NAME, frame)
-        } else {
-            format!("{}-{}.ppm", NAME, frame)
-        };
-
-        let mut image = Ppm::new(&name);
-        println!(
-            "Generating image {}/{}: {}",
-            frame,
-            FRAMES - 1,
-            name
-        );
-        image.add_canvas(camera.render(&world));
-        image.write_file();
+        let mut world = planets_world(angle, &earth);
+        configure_world(&mut world, options);
+
+        println!("Generating frame {}/{}", frame, FRAMES - 1);
+        let image = camera.render(&world);
+        match encoder.as_mut() {
+            Some(encoder) => encoder
+                .add_frame(&output_transform(options).apply_canvas(&image))
+                .unwrap_or_else(|err| exit_on_error(err)),
+            None => {
+                let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+                let frame_name = path.with_file_name(format!(
+                    "{}-{:03}.{}",
+                    stem,
+                    frame,
+                    path.extension().unwrap_or_default().to_string_lossy()
+                ));
+                save_canvas(&image, &frame_name.to_string_lossy(), options);
+            }
+        }
+    }
+    if let Some(encoder) = encoder {
+        encoder.finish().unwrap_or_else(|err| exit_on_error(err));
+        println!("Animation saved in file: {}", filename);
     }
-    println!("Done");
 }
 
+// Render a scene file. By default the image is saved in the current directory with the
+// name of the scene file and the png extension.
+fn render_scene(scene_file: &str, options: &Options) {
+    let mut scene = load_scene(scene_file).unwrap_or_else(|err| {
+        eprintln!("Could not load {}: {}", scene_file, err);
+        process::exit(1);
+    });
+    let default = Path::new(scene_file)
+        .with_extension("png")
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "scene.png".to_string());
+
+    println!("Rendering {}. Please wait...", scene_file);
+    let camera = configure_camera(&scene.camera, options);
+    configure_world(&mut scene.world, options);
+    save_canvas(
+        &camera.render(&scene.world),
+        &output_filename(options, &default),
+        options,
+    );
+}
+
+// Exit codes: 0 on success, 1 if the command fails and 2 for an invalid command line
 fn main() {
-    println!("Welcome to the simple Ray Tracer!");
     let args: Vec<String> = env::args().collect();
 
     let config = Config::build(&args).unwrap_or_else(|err| {
-        println!("Problem parsing arguments: {err}");
-        println!("Commands:");
-        println!("trajectory - Create an image of a projectile's trajectory");
-        println!("clock      - Create an simple clock case with a dot for each hour");
-        println!("shadow     - Primitive ray tracing of a sphere's 'shadow' on a wall");
-        println!("sphere     - First ray tracing using Phong reflection model");
-        println!("world      - Create a ray traced image of a pre-configured world");
-        println!("planets    - Create a ray traced animation of two planets");
-        process::exit(1);
+        eprintln!("Problem parsing arguments: {err}");
+        eprintln!("Run with --help to see the commands and options.");
+        process::exit(2);
     });
+    let Some(command) = config.command.filter(|_| !config.options.help) else {
+        print!("{}", cli::usage());
+        return;
+    };
+
+    println!("Welcome to the simple Ray Tracer!");
+    let options = &config.options;
+    match command.name {
+        "trajectory" => create_trajectory(options),
+        "clock" => create_clock(options),
+        "shadow" => trace_shadow(options),
+        "sphere" => phong_reflection(options),
+        "world" => ray_trace_world(options),
+        "planets" => ray_trace_planets(options),
+        "render" => render_scene(&config.arguments[0], options),
+        // The command line parser only accepts the commands in the table
+        _ => unreachable!("unhandled command '{}'", command.name),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    match config.command.as_str() {
-        "trajectory" => create_trajectory(),
-        "clock" => create_clock(),
-        "shadow" => trace_shadow(),
-        "sphere" => phong_reflection(),
-        "world" => ray_trace_world(),
-        "planets" => ray_trace_planets(),
-        _ => println!("Unknown command '{}'", config.command.as_str()),
+    #[test]
+    fn scene_file_describes_the_world_of_the_world_command() {
+        let scene = load_scene("scenes/world.yml").unwrap();
+        let world = pre_configure_world(-10.);
+        assert_eq!(scene.world.lights, world.lights);
+        assert_eq!(scene.world.objects, world.objects);
     }
 }
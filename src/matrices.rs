@@ -24,7 +24,7 @@ use std::ops::Mul;
 //      bp1  bp2  ...   bpq
 //
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
 pub struct Matrix<const R: usize, const C: usize> {
     data: [[f64; C]; R],
 }
@@ -39,6 +39,12 @@ pub trait Submatrix<Rhs = Self> {
 
 const EPSILON: f64 = 0.00001;
 
+impl<const R: usize, const C: usize> Default for Matrix<R, C> {
+    fn default() -> Self {
+        return Matrix::new();
+    }
+}
+
 impl<const R: usize, const C: usize> Matrix<R, C> {
     pub fn new() -> Self {
         let data = [[0.0; C]; R];
@@ -178,7 +184,7 @@ impl<const R: usize> Matrix<R, R> {
         }
 
         let mut min = self.minor(row, col).unwrap();
-        if (row + col) % 2 != 0 {
+        if !(row + col).is_multiple_of(2) {
             min = -min;
         }
 
@@ -196,8 +202,8 @@ impl<const R: usize> Matrix<R, R> {
             // uses the cofactor, minor, and det functions recursively.
             3 | 4 => {
                 let mut det = 0.0;
-                for col in 0..R {
-                    det = det + data[0][col] * self.cofactor(0, col).unwrap();
+                for (col, item) in data[0].iter().enumerate() {
+                    det += item * self.cofactor(0, col).unwrap();
                 }
                 return Some(det);
             }
@@ -238,10 +244,6 @@ impl<const R: usize, const C: usize> PartialEq for Matrix<R, C> {
         }
         return true;
     }
-
-    fn ne(&self, other: &Self) -> bool {
-        return !self.eq(other);
-    }
 }
 
 // Multiplication of two matrices A * B
@@ -258,8 +260,7 @@ impl<const R: usize, const C: usize, const Q: usize> Mul<&Matrix<C, Q>> for &Mat
         for col in 0..Q {
             for row in 0..R {
                 for item in 0..C {
-                    result.data[row][col] =
-                        result.data[row][col] + self.data[row][item] * other.data[item][col]
+                    result.data[row][col] += self.data[row][item] * other.data[item][col]
                 }
             }
         }
@@ -310,10 +311,10 @@ impl<const R: usize, const C: usize, const P: usize, const Q: usize> Submatrix<&
                 for c in 0..C {
                     if c != col {
                         s.data[out_row][out_col] = self.data[r][c];
-                        out_col = out_col + 1;
+                        out_col += 1;
                     }
                 }
-                out_row = out_row + 1;
+                out_row += 1;
             }
         }
 
@@ -0,0 +1,179 @@
+use crate::canvas::Canvas;
+use crate::{hdr, pfm, png, ppm};
+use std::io::{self, Read};
+use std::path::Path;
+
+// The types used with the image formats
+pub use crate::canvas::DecodeError;
+pub use crate::gif::{write as write_gif, Encoder as GifEncoder, Options as GifOptions, Repeat};
+pub use crate::png::BitDepth;
+pub use crate::ppm::Format as PpmFormat;
+pub use crate::tonemap::{srgb_decode, srgb_encode, Operator, OutputTransform};
+
+// The image formats a canvas can be saved in. Pfm and Hdr store the colors as floating
+// point values, so colors outside the 0.0 - 1.0 range are preserved.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    Ppm(ppm::Format),
+    Png(png::BitDepth),
+    Pfm,
+    Hdr,
+}
+
+impl Format {
+    // Select the format from the file extension of the path
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Option<Format> {
+        let extension = path.as_ref().extension()?.to_str()?.to_ascii_lowercase();
+        match extension.as_str() {
+            "ppm" => Some(Format::Ppm(ppm::Format::P6)),
+            "png" => Some(Format::Png(png::BitDepth::Eight)),
+            "pfm" => Some(Format::Pfm),
+            "hdr" => Some(Format::Hdr),
+            _ => None,
+        }
+    }
+
+    // Select the format by name, as given on the command line
+    pub fn from_name(name: &str) -> Option<Format> {
+        match name.to_ascii_lowercase().as_str() {
+            "png" => Some(Format::Png(png::BitDepth::Eight)),
+            "png16" => Some(Format::Png(png::BitDepth::Sixteen)),
+            "ppm" => Some(Format::Ppm(ppm::Format::P6)),
+            "ppm-ascii" => Some(Format::Ppm(ppm::Format::P3)),
+            "pfm" => Some(Format::Pfm),
+            "hdr" => Some(Format::Hdr),
+            _ => None,
+        }
+    }
+
+    // The file extension for the format
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Format::Ppm(_) => "ppm",
+            Format::Png(_) => "png",
+            Format::Pfm => "pfm",
+            Format::Hdr => "hdr",
+        }
+    }
+}
+
+// Save the canvas in the format given by the file extension
+pub fn save<P: AsRef<Path>>(canvas: &Canvas, path: P) -> io::Result<()> {
+    let format = match Format::from_path(&path) {
+        Some(format) => format,
+        None => return Err(unsupported_format(path.as_ref())),
+    };
+    return save_as(canvas, path, format);
+}
+
+pub fn save_as<P: AsRef<Path>>(canvas: &Canvas, path: P, format: Format) -> io::Result<()> {
+    match format {
+        Format::Ppm(format) => ppm::write_file(canvas, format, path),
+        Format::Png(depth) => png::write_file(canvas, depth, path),
+        Format::Pfm => pfm::write_file(canvas, path),
+        Format::Hdr => hdr::write_file(canvas, path),
+    }
+}
+
+// Save the canvas after applying the output transform. The floating point formats keep
+// the linear colors, so only the exposure is applied for them.
+pub fn save_with<P: AsRef<Path>>(
+    canvas: &Canvas,
+    path: P,
+    format: Format,
+    transform: &OutputTransform,
+) -> io::Result<()> {
+    let transformed = match format {
+        Format::Pfm | Format::Hdr => transform.apply_exposure_canvas(canvas),
+        Format::Ppm(_) | Format::Png(_) => transform.apply_canvas(canvas),
+    };
+    return save_as(&transformed, path, format);
+}
+
+// Load an image in the format given by the file extension. PNG images cannot be loaded.
+pub fn load<P: AsRef<Path>>(path: P) -> Result<Canvas, DecodeError> {
+    match Format::from_path(&path) {
+        Some(Format::Ppm(_)) => ppm::read_file(path),
+        Some(Format::Pfm) => pfm::read_file(path),
+        Some(Format::Hdr) => hdr::read_file(path),
+        _ => Err(DecodeError::Io(unsupported_format(path.as_ref()))),
+    }
+}
+
+// Load an image in the given format from a reader, for example an image built into the
+// program. PNG images cannot be loaded.
+pub fn read<R: Read>(reader: &mut R, format: Format) -> Result<Canvas, DecodeError> {
+    match format {
+        Format::Ppm(_) => ppm::read(reader),
+        Format::Pfm => pfm::read(reader),
+        Format::Hdr => hdr::read(reader),
+        Format::Png(_) => Err(DecodeError::Io(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "PNG images cannot be loaded",
+        ))),
+    }
+}
+
+fn unsupported_format(path: &Path) -> io::Error {
+    return io::Error::new(
+        io::ErrorKind::InvalidInput,
+        format!("unsupported image format: {}", path.display()),
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn format_from_file_extension() {
+        assert_eq!(
+            Format::from_path("image.ppm"),
+            Some(Format::Ppm(ppm::Format::P6))
+        );
+        assert_eq!(
+            Format::from_path("image.PNG"),
+            Some(Format::Png(png::BitDepth::Eight))
+        );
+        assert_eq!(
+            Format::from_path("dir/image.png"),
+            Some(Format::Png(png::BitDepth::Eight))
+        );
+        assert_eq!(Format::from_path("image.pfm"), Some(Format::Pfm));
+        assert_eq!(Format::from_path("image.hdr"), Some(Format::Hdr));
+        assert_eq!(Format::from_path("image.bmp"), None);
+        assert_eq!(Format::from_path("image"), None);
+    }
+
+    #[test]
+    fn format_from_name() {
+        assert_eq!(
+            Format::from_name("png16"),
+            Some(Format::Png(png::BitDepth::Sixteen))
+        );
+        assert_eq!(
+            Format::from_name("PPM-ascii"),
+            Some(Format::Ppm(ppm::Format::P3))
+        );
+        assert_eq!(Format::from_name("gif"), None);
+        for name in ["png", "png16", "ppm", "ppm-ascii", "pfm", "hdr"] {
+            let format = Format::from_name(name).unwrap();
+            assert_eq!(
+                Format::from_path(format!("image.{}", format.extension())).map(|f| f.extension()),
+                Some(format.extension())
+            );
+        }
+    }
+
+    #[test]
+    fn save_unsupported_format_fails() {
+        let canvas = Canvas::new(1, 1);
+        let err = save(&canvas, "image.bmp").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn load_unsupported_format_fails() {
+        assert!(matches!(load("image.png"), Err(DecodeError::Io(_))));
+    }
+}
@@ -0,0 +1,542 @@
+use crate::brdf::{scatter, scatter_pdf};
+use crate::color::Color;
+use crate::environment::Environment;
+use crate::lights::{reflected_light, LightSample, LightSource, ShapeLight};
+use crate::random::Random;
+use crate::rays::{hit, Computation, Ray};
+use crate::shapes::{Material, ShadingModel};
+use crate::tuple::dot;
+use crate::world::World;
+use std::f64::consts::PI;
+
+// Module with a Monte Carlo path tracer, an alternative to the Phong shading of
+// World::color_at that also gives indirect light: light that is reflected by other
+// objects, so a red wall tints a white floor next to it.
+//
+// A path starts at the camera and bounces from surface to surface in random directions.
+// At each hit the light that reaches the point directly from the lights is added (next
+// event estimation) and the path continues in a cosine weighted direction, which
+// matches the diffuse reflection, or in a direction picked from the BRDF of a
+// metallic-roughness material. The ambient term of the materials is not used, the
+// indirect light replaces it. Long paths are ended at random with Russian roulette,
+// where the surviving paths are made brighter to make up for the ended ones.
+//
+// The environment of the world is both a light that is sampled at every hit and what
+// a path sees when it leaves the scene. Each way finds some of the light well: a
+// bright sun in an environment map is found by sampling the light, its reflection in
+// a polished metal by following the path. Multiple importance sampling weights the
+// two with the power heuristic, so each counts where it works best. Emissive objects
+// are weighted the same way.
+//
+// Reflective and transparent materials add the color seen in the mirror direction and
+// through the surface, as in the Whitted shading. A path follows one of the surface,
+// the mirror direction and the refracted direction, picked at random in proportion to
+// how much each adds, so on average the parts add up the same as in the Whitted shading.
+
+// How a camera computes the color of a ray
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Integrator {
+    // Phong shading with shadows
+    Whitted,
+    // Path tracing with at most max_depth bounces
+    PathTracing { max_depth: usize },
+    // Only the ambient occlusion, as a gray level
+    AmbientOcclusion,
+}
+
+impl Default for Integrator {
+    fn default() -> Integrator {
+        return Integrator::Whitted;
+    }
+}
+
+impl Integrator {
+    pub const DEFAULT_MAX_DEPTH: usize = 8;
+
+    // Select the integrator by name: whitted, path or ao
+    pub fn from_name(name: &str) -> Option<Integrator> {
+        match name.to_ascii_lowercase().as_str() {
+            "whitted" => Some(Integrator::Whitted),
+            "path" => Some(Integrator::PathTracing {
+                max_depth: Integrator::DEFAULT_MAX_DEPTH,
+            }),
+            "ao" => Some(Integrator::AmbientOcclusion),
+            _ => None,
+        }
+    }
+}
+
+// Paths are never ended by the Russian roulette before this many bounces
+const MIN_BOUNCES: usize = 3;
+
+// How the direction of a path was picked at the last bounce
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Bounce {
+    // The ray from the camera
+    Camera,
+    // The mirror or the refracted direction, which light sampling never finds
+    Specular,
+    // Picked from the material with the probability density
+    Scattered(f64),
+}
+
+// The color and alpha of one random path that starts with the ray. The average of
+// many paths converges to the light that reaches the camera along the ray.
+pub fn trace(world: &World, ray: &Ray, max_depth: usize, random: &mut Random) -> (Color, f64) {
+    let black = Color::color(0., 0., 0.);
+    let mut color = black;
+    // The fraction of the light at the current point that reaches the camera
+    let mut throughput = Color::color(1., 1., 1.);
+    let mut ray = Ray::new(ray.origin, ray.direction);
+    let mut bounce = Bounce::Camera;
+
+    for depth in 0..max_depth {
+        let intersections = ray.intersections_in_world(world);
+        let intersection = match hit(&intersections) {
+            Some(intersection) => intersection,
+            None => {
+                if let Some(environment) = &world.environment {
+                    let direction = ray.direction.normalize();
+                    let weight = match bounce {
+                        Bounce::Camera => return world.background(&ray),
+                        Bounce::Specular => 1.,
+                        Bounce::Scattered(pdf) => power_heuristic(pdf, environment.pdf(&direction)),
+                    };
+                    color = color + throughput * environment.color_at(&direction) * weight;
+                } else if bounce == Bounce::Camera {
+                    return world.background(&ray);
+                }
+                break;
+            }
+        };
+        let computation = ray.prepare_computation_in(intersection, &intersections);
+        let material = &computation.material;
+
+        // Emissive objects are also sampled as lights at the previous hit
+        if material.is_emissive() {
+            let weight = match bounce {
+                Bounce::Camera | Bounce::Specular => 1.,
+                Bounce::Scattered(pdf) => {
+                    let light = ShapeLight {
+                        shape: computation.object,
+                        samples: world.emission_samples,
+                        seed: 0,
+                    };
+                    let light_pdf = light.pdf(&ray.origin, &computation.point);
+                    power_heuristic(pdf, world.emission_samples as f64 * light_pdf)
+                }
+            };
+            color = color + throughput * material.emission * weight;
+        }
+        color = color + throughput * direct_light(world, &computation, random);
+
+        // The picked part is made brighter by the inverse of its probability
+        let (reflective, transparency) = computation.specular_weights();
+        let total = 1. + reflective + transparency;
+        let pick = if total > 1. {
+            random.next_f64() * total
+        } else {
+            0.
+        };
+        throughput = throughput * total;
+        let (origin, direction) = if pick < 1. {
+            // The direction is picked from the material, which also gives the fraction
+            // of the light from that direction that is reflected towards the eye
+            let Some((direction, reflectance)) =
+                scatter(material, &computation.eyev, &computation.normalv, random)
+            else {
+                break;
+            };
+            throughput = throughput * reflectance;
+            bounce = Bounce::Scattered(scatter_pdf(
+                material,
+                &direction,
+                &computation.eyev,
+                &computation.normalv,
+            ));
+            (computation.over_point, direction)
+        } else if pick < 1. + reflective {
+            bounce = Bounce::Specular;
+            (computation.over_point, computation.reflectv)
+        } else {
+            let Some(direction) = computation.refractv() else {
+                break;
+            };
+            bounce = Bounce::Specular;
+            (computation.under_point, direction)
+        };
+
+        let strength = throughput.red.max(throughput.green).max(throughput.blue);
+        if strength <= 0. {
+            break;
+        }
+        if depth + 1 >= MIN_BOUNCES {
+            let survival = strength.clamp(0.05, 0.95);
+            if random.next_f64() >= survival {
+                break;
+            }
+            throughput = throughput * (1. / survival);
+        }
+
+        ray = Ray::new(origin, direction);
+    }
+    return (color, 1.);
+}
+
+// The light that reaches the point directly from the lights, the emissive objects and
+// the environment, reflected towards the eye
+fn direct_light(world: &World, computation: &Computation, random: &mut Random) -> Color {
+    let mut color = Color::color(0., 0., 0.);
+    for light in &world.lights {
+        color = color + sample_light(world, light, computation);
+    }
+    for light in world.shape_lights(computation.object) {
+        color = color + sample_shape_light(world, &light, computation);
+    }
+    if let Some(environment) = &world.environment {
+        color = color + sample_environment(world, environment, computation, random);
+    }
+    return color;
+}
+
+// The light from one direction of the environment, weighted against finding the same
+// direction by following the path
+fn sample_environment(
+    world: &World,
+    environment: &Environment,
+    computation: &Computation,
+    random: &mut Random,
+) -> Color {
+    let black = Color::color(0., 0., 0.);
+    let direction = environment.sample(random);
+    let light_pdf = environment.pdf(&direction);
+    let point = &computation.over_point;
+    if light_pdf <= 0.
+        || dot(&direction, &computation.normalv) <= 0.
+        || world.is_blocked(point, &direction, f64::INFINITY)
+    {
+        return black;
+    }
+
+    // The intensity of a light sample is scaled so that the reflected light is
+    // π BRDF cos intensity, see the brdf module
+    let sample = LightSample {
+        direction,
+        distance: f64::INFINITY,
+        intensity: environment.color_at(&direction) * (1. / (PI * light_pdf)),
+    };
+    return weighted_light(computation, &sample, light_pdf);
+}
+
+// The average of the reflected light from the samples of an emissive object that are
+// not in shadow, each weighted against finding the object by following the path
+fn sample_shape_light(world: &World, light: &ShapeLight, computation: &Computation) -> Color {
+    let point = &computation.over_point;
+    let samples = light.samples(point);
+    let mut sum = Color::color(0., 0., 0.);
+    for sample in &samples {
+        if !world.is_blocked(point, &sample.direction, sample.distance) {
+            let surface = *point + sample.direction * sample.distance;
+            let light_pdf = samples.len() as f64 * light.pdf(point, &surface);
+            sum = sum + weighted_light(computation, sample, light_pdf);
+        }
+    }
+    if samples.is_empty() {
+        return sum;
+    }
+    return sum * (1. / samples.len() as f64);
+}
+
+// The reflected light from a sample picked with the probability density light_pdf,
+// weighted against following the path in the same direction
+fn weighted_light(computation: &Computation, sample: &LightSample, light_pdf: f64) -> Color {
+    let material: &Material = &computation.material;
+    let eye = &computation.eyev;
+    let normal = &computation.normalv;
+    let reflected = reflected_light(material, sample, eye, normal);
+    let bsdf_pdf = scatter_pdf(material, &sample.direction, eye, normal);
+    let weight = power_heuristic(light_pdf, bsdf_pdf);
+    if let ShadingModel::MetallicRoughness { .. } = material.model {
+        return reflected * weight;
+    }
+
+    // The highlights of the Phong models are not followed by the paths, so they are
+    // only found here and are not weighted
+    let diffuse_material = Material {
+        specular: 0.,
+        ..material.clone()
+    };
+    let diffuse = reflected_light(&diffuse_material, sample, eye, normal);
+    return diffuse * weight + (reflected - diffuse);
+}
+
+// The weight of a sample taken with the probability density pdf, when the same light
+// could also have been found with another_pdf
+fn power_heuristic(pdf: f64, another_pdf: f64) -> f64 {
+    let (a, b) = (pdf * pdf, another_pdf * another_pdf);
+    if a + b <= 0. {
+        return 0.;
+    }
+    return a / (a + b);
+}
+
+// The average of the reflected light from the samples that are not in shadow
+fn sample_light(world: &World, light: &dyn LightSource, computation: &Computation) -> Color {
+    let point = &computation.over_point;
+    let samples = light.samples(point);
+    let mut sum = Color::color(0., 0., 0.);
+    for sample in &samples {
+        if !world.is_blocked(point, &sample.direction, sample.distance) {
+            sum = sum
+                + reflected_light(
+                    &computation.material,
+                    sample,
+                    &computation.eyev,
+                    &computation.normalv,
+                );
+        }
+    }
+    if samples.is_empty() {
+        return sum;
+    }
+    return sum * (1. / samples.len() as f64);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::canvas::Canvas;
+    use crate::environment::EnvironmentMap;
+    use crate::lights::PointLight;
+    use crate::matrices::Matrix;
+    use crate::shapes::Sphere;
+    use crate::tuple::Tuple;
+
+    fn approx_eq(lhs: Color, rhs: Color, epsilon: f64) -> bool {
+        return (lhs.red - rhs.red).abs() < epsilon
+            && (lhs.green - rhs.green).abs() < epsilon
+            && (lhs.blue - rhs.blue).abs() < epsilon;
+    }
+
+    #[test]
+    fn integrator_from_name() {
+        assert_eq!(Integrator::from_name("whitted"), Some(Integrator::Whitted));
+        assert_eq!(
+            Integrator::from_name("Path"),
+            Some(Integrator::PathTracing { max_depth: 8 })
+        );
+        assert_eq!(
+            Integrator::from_name("ao"),
+            Some(Integrator::AmbientOcclusion)
+        );
+        assert_eq!(Integrator::from_name("photon"), None);
+    }
+
+    #[test]
+    fn path_that_misses_is_transparent() {
+        let world = World::default_world();
+        let ray = Ray::new(Tuple::point(0., 0., -5.), Tuple::vector(0., 1., 0.));
+        let (color, alpha) = trace(&world, &ray, 8, &mut Random::new(1));
+        assert_eq!(color, Color::color(0., 0., 0.));
+        assert_eq!(alpha, 0.);
+    }
+
+    #[test]
+    fn one_bounce_is_direct_light_without_ambient() {
+        let mut world = World::default_world();
+        for object in &mut world.objects {
+            object.material.ambient = 0.;
+        }
+        let ray = Ray::new(Tuple::point(0., 0., -5.), Tuple::vector(0., 0., 1.));
+        let (color, alpha) = trace(&world, &ray, 1, &mut Random::new(1));
+        assert_eq!(color, world.color_at(&ray));
+        assert_eq!(alpha, 1.);
+    }
+
+    #[test]
+    fn emission_is_seen_directly() {
+        let mut world = World::new();
+        let mut sphere = Sphere::new();
+        sphere.material.emission = Color::color(2., 1., 0.5);
+        world.objects.push(sphere);
+        let ray = Ray::new(Tuple::point(0., 0., -5.), Tuple::vector(0., 0., 1.));
+        let (color, _) = trace(&world, &ray, 8, &mut Random::new(1));
+        assert_eq!(color, Color::color(2., 1., 0.5));
+    }
+
+    #[test]
+    fn indirect_light_reaches_points_in_shadow() {
+        // A white floor lit from above, with a roof that shades the middle of it
+        let mut world = World::new();
+        world
+            .lights
+            .push(PointLight::new(Tuple::point(0., 10., 0.), Color::color(1., 1., 1.)).into());
+        let mut floor = Sphere::new();
+        floor.transformation = Matrix::new_identity().scale(20., 0.01, 20.);
+        floor.material.specular = 0.;
+        world.objects.push(floor);
+        let mut roof = Sphere::new();
+        roof.transformation = Matrix::new_identity()
+            .scale(2., 0.01, 2.)
+            .translate(0., 0.5, 0.);
+        roof.material.specular = 0.;
+        world.objects.push(roof);
+
+        // Look straight down at the floor, beside the roof and under it
+        let under = Ray::new(Tuple::point(0., 0.25, 0.), Tuple::vector(0., -1., 0.));
+        let mut random = Random::new(1);
+        assert_eq!(
+            trace(&world, &under, 1, &mut random).0,
+            Color::color(0., 0., 0.)
+        );
+
+        let paths = 200;
+        let mut sum = Color::color(0., 0., 0.);
+        for _ in 0..paths {
+            sum = sum + trace(&world, &under, 8, &mut random).0;
+        }
+        let mean = sum * (1. / paths as f64);
+        assert!(mean.red > 0.01, "{:?}", mean);
+        assert!(approx_eq(
+            Color::color(mean.red, mean.red, mean.red),
+            mean,
+            0.00001
+        ));
+    }
+
+    #[test]
+    fn same_random_numbers_give_the_same_path() {
+        let world = World::default_world();
+        let ray = Ray::new(
+            Tuple::point(0., 0., -5.),
+            Tuple::vector(0., 0.1, 1.).normalize(),
+        );
+        assert_eq!(
+            trace(&world, &ray, 8, &mut Random::new(9)),
+            trace(&world, &ray, 8, &mut Random::new(9))
+        );
+    }
+
+    // The mean of many paths through the middle of a white sphere
+    fn mean_of_paths(world: &World, paths: usize) -> Color {
+        let ray = Ray::new(Tuple::point(0., 0., -5.), Tuple::vector(0., 0., 1.));
+        let mut random = Random::new(3);
+        let mut sum = Color::color(0., 0., 0.);
+        for _ in 0..paths {
+            sum = sum + trace(world, &ray, 4, &mut random).0;
+        }
+        return sum * (1. / paths as f64);
+    }
+
+    #[test]
+    fn environment_is_seen_and_lights_the_objects() {
+        let mut world = World::new();
+        world.environment = Some(Environment::Constant(Color::color(1., 1., 1.)));
+        let ray = Ray::new(Tuple::point(0., 0., -5.), Tuple::vector(0., 1., 0.));
+        assert_eq!(
+            trace(&world, &ray, 8, &mut Random::new(1)),
+            (Color::color(1., 1., 1.), 1.)
+        );
+
+        // A convex object lit by a uniform environment reflects its albedo, both when
+        // the environment is sampled evenly and when it is an image
+        let mut sphere = Sphere::new();
+        sphere.material.diffuse = 0.5;
+        sphere.material.specular = 0.;
+        world.objects.push(sphere);
+        let mean = mean_of_paths(&world, 500);
+        assert!(
+            approx_eq(mean, Color::color(0.5, 0.5, 0.5), 0.02),
+            "{:?}",
+            mean
+        );
+
+        let mut image = Canvas::new(16, 8);
+        image
+            .pixels_mut()
+            .for_each(|pixel| *pixel = Color::color(1., 1., 1.));
+        world.environment = Some(Environment::Map(EnvironmentMap::new(image)));
+        let mean = mean_of_paths(&world, 500);
+        assert!(
+            approx_eq(mean, Color::color(0.5, 0.5, 0.5), 0.02),
+            "{:?}",
+            mean
+        );
+    }
+
+    #[test]
+    fn metal_reflects_the_environment() {
+        let mut world = World::new();
+        world.environment = Some(Environment::Gradient {
+            bottom: Color::color(0., 0., 0.),
+            top: Color::color(1., 1., 1.),
+        });
+        let mut sphere = Sphere::new();
+        sphere.material.model = ShadingModel::MetallicRoughness {
+            metallic: 1.,
+            roughness: 0.,
+        };
+        world.objects.push(sphere);
+        // A mirror at the front of the sphere reflects the horizon behind the camera
+        let mean = mean_of_paths(&world, 100);
+        assert!(
+            approx_eq(mean, Color::color(0.5, 0.5, 0.5), 0.02),
+            "{:?}",
+            mean
+        );
+    }
+
+    #[test]
+    fn mirror_reflects_an_emissive_quad() {
+        // A flat emissive square behind the camera, seen only in the mirror
+        let mut world = World::new();
+        let mut quad = Sphere::new();
+        quad.transformation = Matrix::new_identity()
+            .scale(3., 3., 0.01)
+            .translate(0., 0., -10.);
+        quad.material.emission = Color::color(1., 1., 1.);
+        world.objects.push(quad);
+        let mut mirror = Sphere::new();
+        mirror.material.model = ShadingModel::MetallicRoughness {
+            metallic: 1.,
+            roughness: 0.,
+        };
+        world.objects.push(mirror);
+
+        // Found by following the path, where sampling the light rarely hits the narrow
+        // reflection
+        let mean = mean_of_paths(&world, 200);
+        assert!(
+            approx_eq(mean, Color::color(1., 1., 1.), 0.05),
+            "{:?}",
+            mean
+        );
+    }
+
+    #[test]
+    fn mirrors_and_glass_match_the_whitted_shading() {
+        let mut world = World::new();
+        world.environment = Some(Environment::Gradient {
+            bottom: Color::color(0., 0., 0.),
+            top: Color::color(1., 1., 1.),
+        });
+        // Without a diffuse or specular surface only the mirror and the glass are seen
+        let mut sphere = Sphere::new();
+        sphere.material.ambient = 0.;
+        sphere.material.diffuse = 0.;
+        sphere.material.specular = 0.;
+        sphere.material.reflective = 0.5;
+        world.objects.push(sphere);
+        let ray = Ray::new(Tuple::point(0., 0., -5.), Tuple::vector(0., 0., 1.));
+
+        let mean = mean_of_paths(&world, 1000);
+        let whitted = world.color_at(&ray);
+        assert!(approx_eq(mean, whitted, 0.02), "{:?} {:?}", mean, whitted);
+
+        world.objects[0].material.transparency = 1.;
+        world.objects[0].material.refractive_index = 1.5;
+        let mean = mean_of_paths(&world, 10000);
+        let whitted = world.color_at(&ray);
+        assert!(approx_eq(mean, whitted, 0.02), "{:?} {:?}", mean, whitted);
+    }
+}
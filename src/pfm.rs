@@ -0,0 +1,178 @@
+use crate::canvas::{Canvas, DecodeError};
+use crate::color::Color;
+use crate::ppm::{malformed, Parser};
+use std::fs::File;
+use std::io::{self, prelude::*, BufWriter};
+use std::path::Path;
+
+// Module to read and write Portable Float Map (PFM) images.
+//
+// A PFM file stores the colors as 32-bit floats without clamping, so the full dynamic
+// range of a render is preserved. The header is "PF" (color) or "Pf" (grayscale), the
+// width and height, and a scale factor whose sign gives the byte order: negative means
+// little-endian. Note that the rows are stored from the bottom to the top.
+
+pub fn write<W: Write>(canvas: &Canvas, writer: &mut W) -> io::Result<()> {
+    write!(writer, "PF\n{} {}\n-1.0\n", canvas.width, canvas.height)?;
+
+    let mut row = Vec::with_capacity(canvas.width * 12);
+    for pixels in canvas.rows().rev() {
+        row.clear();
+        for color in pixels {
+            for part in [color.red, color.green, color.blue] {
+                row.extend_from_slice(&(part as f32).to_le_bytes());
+            }
+        }
+        writer.write_all(&row)?;
+    }
+    return Ok(());
+}
+
+// Write the canvas to a file. An existing file is overwritten.
+pub fn write_file<P: AsRef<Path>>(canvas: &Canvas, path: P) -> io::Result<()> {
+    let mut file = BufWriter::new(File::create(path)?);
+    write(canvas, &mut file)?;
+    return file.flush();
+}
+
+pub fn read<R: Read>(reader: &mut R) -> Result<Canvas, DecodeError> {
+    let mut data = Vec::new();
+    reader.read_to_end(&mut data)?;
+    let mut parser = Parser::new(&data);
+
+    let channels = match parser.token() {
+        Some(b"PF") => 3,
+        Some(b"Pf") => 1,
+        Some(magic) => {
+            let magic = String::from_utf8_lossy(magic);
+            return Err(malformed(format!("unsupported magic number '{}'", magic)));
+        }
+        None => return Err(malformed("missing magic number".to_string())),
+    };
+    let width: usize = parser.number("width")?;
+    let height: usize = parser.number("height")?;
+    let scale: f64 = parser.number("scale")?;
+    if width == 0 || height == 0 {
+        return Err(malformed(format!("invalid size {}x{}", width, height)));
+    }
+    if scale == 0.0 || !scale.is_finite() {
+        return Err(malformed(format!("invalid scale {}", scale)));
+    }
+    let little_endian = scale < 0.0;
+
+    // A single whitespace character separates the header from the pixel data
+    let start = parser.pos + 1;
+    let size = width
+        .checked_mul(height)
+        .and_then(|pixels| pixels.checked_mul(channels * 4))
+        .ok_or_else(|| malformed(format!("invalid size {}x{}", width, height)))?;
+    if data.len().saturating_sub(start) < size {
+        return Err(malformed("pixel data is truncated".to_string()));
+    }
+
+    let mut samples = data[start..start + size].chunks_exact(4).map(|bytes| {
+        let bytes: [u8; 4] = bytes.try_into().unwrap();
+        if little_endian {
+            f32::from_le_bytes(bytes) as f64
+        } else {
+            f32::from_be_bytes(bytes) as f64
+        }
+    });
+
+    let mut canvas = Canvas::new(width, height);
+    for y in (0..height).rev() {
+        for x in 0..width {
+            let color = if channels == 3 {
+                let red = samples.next().unwrap();
+                let green = samples.next().unwrap();
+                let blue = samples.next().unwrap();
+                Color::color(red, green, blue)
+            } else {
+                let gray = samples.next().unwrap();
+                Color::color(gray, gray, gray)
+            };
+            canvas.write_pixel(x, y, color);
+        }
+    }
+    return Ok(canvas);
+}
+
+pub fn read_file<P: AsRef<Path>>(path: P) -> Result<Canvas, DecodeError> {
+    let mut file = io::BufReader::new(File::open(path)?);
+    return read(&mut file);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn write_pfm_header_and_bottom_up_rows() {
+        let mut canvas = Canvas::new(2, 2);
+        canvas.write_pixel(0, 0, Color::color(4.0, 0.5, -1.0));
+        canvas.write_pixel(1, 1, Color::color(0.25, 0.0, 100.0));
+        let mut output: Vec<u8> = Vec::new();
+        write(&canvas, &mut output).unwrap();
+
+        let header = b"PF\n2 2\n-1.0\n";
+        assert_eq!(&output[..header.len()], header);
+        let pixels = &output[header.len()..];
+        assert_eq!(pixels.len(), 2 * 2 * 3 * 4);
+        // The bottom row is stored first, so the last pixel of the first row is (1, 1)
+        assert_eq!(&pixels[12..16], &0.25f32.to_le_bytes());
+        assert_eq!(&pixels[20..24], &100.0f32.to_le_bytes());
+        assert_eq!(&pixels[24..28], &4.0f32.to_le_bytes());
+    }
+
+    #[test]
+    fn pfm_round_trip_preserves_high_dynamic_range() {
+        let mut canvas = Canvas::new(3, 2);
+        canvas.write_pixel(0, 0, Color::color(12.5, 0.5, 0.001));
+        canvas.write_pixel(2, 1, Color::color(1000.0, 2.0, 0.0));
+        let mut output: Vec<u8> = Vec::new();
+        write(&canvas, &mut output).unwrap();
+
+        let decoded = read(&mut output.as_slice()).unwrap();
+        assert_eq!(decoded.width, 3);
+        assert_eq!(decoded.height, 2);
+        assert_eq!(
+            decoded.read_pixel(0, 0),
+            Some(Color::color(12.5, 0.5, 0.001))
+        );
+        assert_eq!(
+            decoded.read_pixel(2, 1),
+            Some(Color::color(1000.0, 2.0, 0.0))
+        );
+    }
+
+    #[test]
+    fn read_big_endian_grayscale() {
+        let mut data = b"Pf\n1 2\n1.0\n".to_vec();
+        data.extend_from_slice(&2.0f32.to_be_bytes());
+        data.extend_from_slice(&0.5f32.to_be_bytes());
+        let canvas = read(&mut data.as_slice()).unwrap();
+        assert_eq!(canvas.read_pixel(0, 1), Some(Color::color(2.0, 2.0, 2.0)));
+        assert_eq!(canvas.read_pixel(0, 0), Some(Color::color(0.5, 0.5, 0.5)));
+    }
+
+    #[test]
+    fn read_reports_malformed_files() {
+        assert!(matches!(
+            read(&mut b"P6 1 1 255 abc".as_slice()),
+            Err(DecodeError::Malformed(_))
+        ));
+        assert!(matches!(
+            read(&mut b"PF 1 1 0 ".as_slice()),
+            Err(DecodeError::Malformed(_))
+        ));
+        assert!(matches!(
+            read(&mut b"PF 1 1 -1.0 abc".as_slice()),
+            Err(DecodeError::Malformed(_))
+        ));
+        // The size overflows when multiplied
+        assert!(matches!(
+            read(&mut b"PF 4611686018427387904 2 -1.0 abc".as_slice()),
+            Err(DecodeError::Malformed(_))
+        ));
+    }
+}
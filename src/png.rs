@@ -0,0 +1,582 @@
+use crate::canvas::Canvas;
+use std::fs::File;
+use std::io::{self, prelude::*, BufWriter};
+use std::path::Path;
+
+// Module to encode a canvas as a PNG image without any external dependencies.
+//
+// The image data is filtered row by row, compressed with a simple deflate encoder
+// (LZ77 with fixed Huffman codes) and wrapped in a zlib stream. Each chunk in the
+// file is protected by a CRC32 checksum.
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BitDepth {
+    Eight,
+    Sixteen,
+}
+
+const SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1a, b'\n'];
+const COLOR_TYPE_RGB: u8 = 2;
+const COLOR_TYPE_RGBA: u8 = 6;
+
+pub fn write<W: Write>(canvas: &Canvas, depth: BitDepth, writer: &mut W) -> io::Result<()> {
+    writer.write_all(&SIGNATURE)?;
+
+    let mut header = Vec::with_capacity(13);
+    header.extend_from_slice(&(canvas.width as u32).to_be_bytes());
+    header.extend_from_slice(&(canvas.height as u32).to_be_bytes());
+    header.push(match depth {
+        BitDepth::Eight => 8,
+        BitDepth::Sixteen => 16,
+    });
+    // The alpha channel is only stored if the canvas has transparent pixels
+    let with_alpha = canvas.has_transparency();
+    header.push(if with_alpha {
+        COLOR_TYPE_RGBA
+    } else {
+        COLOR_TYPE_RGB
+    });
+    header.push(0); // Compression method: deflate
+    header.push(0); // Filter method: adaptive filtering with five filter types
+    header.push(0); // No interlace
+    write_chunk(writer, b"IHDR", &header)?;
+
+    write_chunk(
+        writer,
+        b"IDAT",
+        &zlib_compress(&filtered_image_data(canvas, depth, with_alpha)),
+    )?;
+    return write_chunk(writer, b"IEND", &[]);
+}
+
+// Write the canvas to a file. An existing file is overwritten.
+pub fn write_file<P: AsRef<Path>>(canvas: &Canvas, depth: BitDepth, path: P) -> io::Result<()> {
+    let mut file = BufWriter::new(File::create(path)?);
+    write(canvas, depth, &mut file)?;
+    return file.flush();
+}
+
+// A chunk consists of the length of the data, the chunk type, the data and a CRC
+// calculated over the chunk type and the data.
+fn write_chunk<W: Write>(writer: &mut W, chunk_type: &[u8; 4], data: &[u8]) -> io::Result<()> {
+    writer.write_all(&(data.len() as u32).to_be_bytes())?;
+    writer.write_all(chunk_type)?;
+    writer.write_all(data)?;
+    let crc = crc32_update(crc32_update(0xffff_ffff, chunk_type), data) ^ 0xffff_ffff;
+    return writer.write_all(&crc.to_be_bytes());
+}
+
+fn sample_bytes(value: f64, depth: BitDepth, bytes: &mut Vec<u8>) {
+    let value = value.clamp(0.0, 1.0);
+    match depth {
+        BitDepth::Eight => bytes.push((value * 255.0).round() as u8),
+        BitDepth::Sixteen => {
+            bytes.extend_from_slice(&((value * 65535.0).round() as u16).to_be_bytes())
+        }
+    }
+}
+
+// Each row is prefixed with the filter type that gives the smallest sum of absolute
+// differences, which is the heuristic recommended by the PNG specification.
+fn filtered_image_data(canvas: &Canvas, depth: BitDepth, with_alpha: bool) -> Vec<u8> {
+    let samples_per_pixel = if with_alpha { 4 } else { 3 };
+    let bytes_per_pixel = match depth {
+        BitDepth::Eight => samples_per_pixel,
+        BitDepth::Sixteen => samples_per_pixel * 2,
+    };
+    let row_len = canvas.width * bytes_per_pixel;
+    let mut data = Vec::with_capacity((row_len + 1) * canvas.height);
+    let mut previous = vec![0u8; row_len];
+    let mut current = Vec::with_capacity(row_len);
+    let mut candidate = vec![0u8; row_len];
+    let mut best = vec![0u8; row_len];
+
+    let mut alpha = canvas.alpha();
+    for pixels in canvas.rows() {
+        current.clear();
+        for color in pixels {
+            sample_bytes(color.red, depth, &mut current);
+            sample_bytes(color.green, depth, &mut current);
+            sample_bytes(color.blue, depth, &mut current);
+            let alpha = alpha.next().unwrap();
+            if with_alpha {
+                sample_bytes(*alpha, depth, &mut current);
+            }
+        }
+
+        let mut best_filter = 0;
+        let mut best_sum = u64::MAX;
+        for filter in 0..5 {
+            apply_filter(filter, &current, &previous, bytes_per_pixel, &mut candidate);
+            let sum = candidate
+                .iter()
+                .map(|b| (*b as i8).unsigned_abs() as u64)
+                .sum();
+            if sum < best_sum {
+                best_sum = sum;
+                best_filter = filter;
+                best.copy_from_slice(&candidate);
+            }
+        }
+        data.push(best_filter);
+        data.extend_from_slice(&best);
+        previous.copy_from_slice(&current);
+    }
+    return data;
+}
+
+fn apply_filter(filter: u8, row: &[u8], previous: &[u8], bpp: usize, output: &mut [u8]) {
+    for i in 0..row.len() {
+        let left = if i >= bpp { row[i - bpp] } else { 0 };
+        let up = previous[i];
+        let up_left = if i >= bpp { previous[i - bpp] } else { 0 };
+        let predictor = match filter {
+            0 => 0,
+            1 => left,
+            2 => up,
+            3 => ((left as u16 + up as u16) / 2) as u8,
+            _ => paeth(left, up, up_left),
+        };
+        output[i] = row[i].wrapping_sub(predictor);
+    }
+}
+
+fn paeth(a: u8, b: u8, c: u8) -> u8 {
+    let p = a as i16 + b as i16 - c as i16;
+    let pa = (p - a as i16).abs();
+    let pb = (p - b as i16).abs();
+    let pc = (p - c as i16).abs();
+    if pa <= pb && pa <= pc {
+        a
+    } else if pb <= pc {
+        b
+    } else {
+        c
+    }
+}
+
+const fn crc32_table() -> [u32; 256] {
+    let mut table = [0u32; 256];
+    let mut n = 0;
+    while n < 256 {
+        let mut c = n as u32;
+        let mut k = 0;
+        while k < 8 {
+            c = if c & 1 != 0 {
+                0xedb8_8320 ^ (c >> 1)
+            } else {
+                c >> 1
+            };
+            k += 1;
+        }
+        table[n] = c;
+        n += 1;
+    }
+    table
+}
+
+const CRC32_TABLE: [u32; 256] = crc32_table();
+
+// Update a running CRC. Start with 0xffffffff and invert the bits of the final value.
+fn crc32_update(crc: u32, data: &[u8]) -> u32 {
+    let mut c = crc;
+    for byte in data {
+        c = CRC32_TABLE[((c ^ *byte as u32) & 0xff) as usize] ^ (c >> 8);
+    }
+    return c;
+}
+
+pub fn adler32(data: &[u8]) -> u32 {
+    const MOD_ADLER: u32 = 65521;
+    let mut a: u32 = 1;
+    let mut b: u32 = 0;
+
+    // 5552 is the largest number of bytes that can be summed before b overflows
+    for chunk in data.chunks(5552) {
+        for byte in chunk {
+            a += *byte as u32;
+            b += a;
+        }
+        a %= MOD_ADLER;
+        b %= MOD_ADLER;
+    }
+    return (b << 16) | a;
+}
+
+// A zlib stream is a two byte header, the deflate data and the adler32 checksum of
+// the uncompressed data.
+pub fn zlib_compress(data: &[u8]) -> Vec<u8> {
+    // CMF: deflate with a 32K window. FLG: no dictionary, check bits so that the
+    // header is a multiple of 31.
+    let mut output = vec![0x78, 0x01];
+    output.extend(deflate(data));
+    output.extend_from_slice(&adler32(data).to_be_bytes());
+    return output;
+}
+
+// Deflate bit streams are packed starting with the least significant bit of each byte
+struct BitWriter {
+    bytes: Vec<u8>,
+    buffer: u32,
+    count: u32,
+}
+
+impl BitWriter {
+    fn new() -> BitWriter {
+        BitWriter {
+            bytes: Vec::new(),
+            buffer: 0,
+            count: 0,
+        }
+    }
+
+    fn write_bits(&mut self, value: u32, count: u32) {
+        self.buffer |= value << self.count;
+        self.count += count;
+        while self.count >= 8 {
+            self.bytes.push(self.buffer as u8);
+            self.buffer >>= 8;
+            self.count -= 8;
+        }
+    }
+
+    // Huffman codes are stored starting with the most significant bit
+    fn write_code(&mut self, code: u32, length: u32) {
+        let mut reversed = 0;
+        for i in 0..length {
+            reversed |= ((code >> i) & 1) << (length - 1 - i);
+        }
+        self.write_bits(reversed, length);
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.count > 0 {
+            self.bytes.push(self.buffer as u8);
+        }
+        return self.bytes;
+    }
+}
+
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+const DISTANCE_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DISTANCE_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+
+const WINDOW_SIZE: usize = 32768;
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = 258;
+const MAX_CHAIN: usize = 64;
+const HASH_SIZE: usize = 1 << 15;
+
+// The fixed Huffman code for a literal/length symbol, see RFC 1951 section 3.2.6
+fn write_literal_length(writer: &mut BitWriter, symbol: u32) {
+    match symbol {
+        0..=143 => writer.write_code(0x30 + symbol, 8),
+        144..=255 => writer.write_code(0x190 + symbol - 144, 9),
+        256..=279 => writer.write_code(symbol - 256, 7),
+        _ => writer.write_code(0xc0 + symbol - 280, 8),
+    }
+}
+
+fn write_match(writer: &mut BitWriter, length: usize, distance: usize) {
+    let code = LENGTH_BASE
+        .iter()
+        .rposition(|base| *base as usize <= length)
+        .unwrap();
+    write_literal_length(writer, 257 + code as u32);
+    writer.write_bits(
+        (length - LENGTH_BASE[code] as usize) as u32,
+        LENGTH_EXTRA[code] as u32,
+    );
+
+    let code = DISTANCE_BASE
+        .iter()
+        .rposition(|base| *base as usize <= distance)
+        .unwrap();
+    writer.write_code(code as u32, 5);
+    writer.write_bits(
+        (distance - DISTANCE_BASE[code] as usize) as u32,
+        DISTANCE_EXTRA[code] as u32,
+    );
+}
+
+fn hash(data: &[u8], pos: usize) -> usize {
+    let value = (data[pos] as usize) << 16 | (data[pos + 1] as usize) << 8 | data[pos + 2] as usize;
+    return (value.wrapping_mul(2654435761) >> 8) & (HASH_SIZE - 1);
+}
+
+// Compress the data as a single block with fixed Huffman codes. Repeated sequences are
+// found with a hash chain over the last 32K bytes.
+fn deflate(data: &[u8]) -> Vec<u8> {
+    let mut writer = BitWriter::new();
+    writer.write_bits(1, 1); // Final block
+    writer.write_bits(1, 2); // Fixed Huffman codes
+
+    let mut head = vec![usize::MAX; HASH_SIZE];
+    let mut prev = vec![usize::MAX; WINDOW_SIZE];
+    let insert = |pos: usize, head: &mut Vec<usize>, prev: &mut Vec<usize>| {
+        if pos + MIN_MATCH <= data.len() {
+            let h = hash(data, pos);
+            prev[pos % WINDOW_SIZE] = head[h];
+            head[h] = pos;
+        }
+    };
+
+    let mut pos = 0;
+    while pos < data.len() {
+        let mut best_length = 0;
+        let mut best_distance = 0;
+
+        if pos + MIN_MATCH <= data.len() {
+            let max_length = MAX_MATCH.min(data.len() - pos);
+            let mut candidate = head[hash(data, pos)];
+            let mut chain = 0;
+            while candidate != usize::MAX && pos - candidate <= WINDOW_SIZE && chain < MAX_CHAIN {
+                let length = data[candidate..]
+                    .iter()
+                    .zip(&data[pos..pos + max_length])
+                    .take_while(|(a, b)| a == b)
+                    .count();
+                if length > best_length {
+                    best_length = length;
+                    best_distance = pos - candidate;
+                    if length == max_length {
+                        break;
+                    }
+                }
+                let next = prev[candidate % WINDOW_SIZE];
+                if next == usize::MAX || next >= candidate {
+                    break;
+                }
+                candidate = next;
+                chain += 1;
+            }
+        }
+
+        if best_length >= MIN_MATCH {
+            write_match(&mut writer, best_length, best_distance);
+            for p in pos..pos + best_length {
+                insert(p, &mut head, &mut prev);
+            }
+            pos += best_length;
+        } else {
+            write_literal_length(&mut writer, data[pos] as u32);
+            insert(pos, &mut head, &mut prev);
+            pos += 1;
+        }
+    }
+
+    write_literal_length(&mut writer, 256); // End of block
+    return writer.finish();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::color::Color;
+
+    // Minimal inflate for streams with fixed Huffman blocks, used to verify the encoder
+    fn inflate(data: &[u8]) -> Vec<u8> {
+        let mut pos = 0;
+        let mut bit = |count: u32| -> u32 {
+            let mut value = 0;
+            for i in 0..count {
+                value |= ((data[pos / 8] >> (pos % 8)) as u32 & 1) << i;
+                pos += 1;
+            }
+            value
+        };
+        let mut output: Vec<u8> = Vec::new();
+        assert_eq!(bit(1), 1);
+        assert_eq!(bit(2), 1);
+        loop {
+            // Read the code one bit at a time, most significant bit first
+            let mut code = 0;
+            let mut length = 0;
+            let symbol = loop {
+                code = (code << 1) | bit(1);
+                length += 1;
+                match (length, code) {
+                    (7, 0..=0x17) => break code + 256,
+                    (8, 0x30..=0xbf) => break code - 0x30,
+                    (8, 0xc0..=0xc7) => break code - 0xc0 + 280,
+                    (9, 0x190..=0x1ff) => break code - 0x190 + 144,
+                    _ => (),
+                }
+            };
+            match symbol {
+                0..=255 => output.push(symbol as u8),
+                256 => break,
+                _ => {
+                    let index = (symbol - 257) as usize;
+                    let length =
+                        LENGTH_BASE[index] as usize + bit(LENGTH_EXTRA[index] as u32) as usize;
+                    let mut code = 0;
+                    for _ in 0..5 {
+                        code = (code << 1) | bit(1);
+                    }
+                    let index = code as usize;
+                    let distance =
+                        DISTANCE_BASE[index] as usize + bit(DISTANCE_EXTRA[index] as u32) as usize;
+                    for _ in 0..length {
+                        output.push(output[output.len() - distance]);
+                    }
+                }
+            }
+        }
+        return output;
+    }
+
+    fn crc32(data: &[u8]) -> u32 {
+        return crc32_update(0xffff_ffff, data) ^ 0xffff_ffff;
+    }
+
+    #[test]
+    fn crc32_of_known_data() {
+        assert_eq!(crc32(b"IEND"), 0xae42_6082);
+        assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+    }
+
+    #[test]
+    fn adler32_of_known_data() {
+        assert_eq!(adler32(b""), 1);
+        assert_eq!(adler32(b"Wikipedia"), 0x11e6_0398);
+    }
+
+    #[test]
+    fn zlib_header_is_valid() {
+        let stream = zlib_compress(b"abc");
+        assert_eq!((stream[0] as u32 * 256 + stream[1] as u32) % 31, 0);
+        assert_eq!(&stream[stream.len() - 4..], &adler32(b"abc").to_be_bytes());
+    }
+
+    #[test]
+    fn deflate_round_trip() {
+        let mut data: Vec<u8> = b"The quick brown fox jumps over the lazy dog. ".repeat(50);
+        data.extend((0..2000).map(|i| (i * 7 % 251) as u8));
+        data.extend(vec![0u8; 1000]);
+        let compressed = deflate(&data);
+        assert!(compressed.len() < data.len());
+        assert_eq!(inflate(&compressed), data);
+    }
+
+    #[test]
+    fn paeth_predictor() {
+        assert_eq!(paeth(10, 20, 10), 20);
+        assert_eq!(paeth(20, 10, 10), 20);
+        assert_eq!(paeth(10, 10, 20), 10);
+    }
+
+    #[test]
+    fn filters_are_reversible() {
+        let previous = [10, 20, 30, 40, 50, 60];
+        let row = [200, 3, 17, 255, 0, 128];
+        let mut filtered = [0u8; 6];
+        for filter in 0..5 {
+            apply_filter(filter, &row, &previous, 3, &mut filtered);
+            // Reconstruct the row from the filtered bytes
+            let mut reconstructed = [0u8; 6];
+            for i in 0..6 {
+                let left = if i >= 3 { reconstructed[i - 3] } else { 0 };
+                let up_left = if i >= 3 { previous[i - 3] } else { 0 };
+                let predictor = match filter {
+                    0 => 0,
+                    1 => left,
+                    2 => previous[i],
+                    3 => ((left as u16 + previous[i] as u16) / 2) as u8,
+                    _ => paeth(left, previous[i], up_left),
+                };
+                reconstructed[i] = filtered[i].wrapping_add(predictor);
+            }
+            assert_eq!(reconstructed, row);
+        }
+    }
+
+    fn chunks(png: &[u8]) -> Vec<(String, Vec<u8>)> {
+        let mut chunks = Vec::new();
+        let mut pos = SIGNATURE.len();
+        while pos < png.len() {
+            let length = u32::from_be_bytes(png[pos..pos + 4].try_into().unwrap()) as usize;
+            let chunk_type = String::from_utf8(png[pos + 4..pos + 8].to_vec()).unwrap();
+            let data = png[pos + 8..pos + 8 + length].to_vec();
+            let crc =
+                u32::from_be_bytes(png[pos + 8 + length..pos + 12 + length].try_into().unwrap());
+            assert_eq!(crc, crc32(&png[pos + 4..pos + 8 + length]));
+            chunks.push((chunk_type, data));
+            pos += 12 + length;
+        }
+        return chunks;
+    }
+
+    #[test]
+    fn png_file_structure() {
+        let mut canvas = Canvas::new(3, 2);
+        canvas.write_pixel(0, 0, Color::color(1.0, 0.0, 0.0));
+        canvas.write_pixel(2, 1, Color::color(0.0, 0.5, 1.5));
+        let mut output: Vec<u8> = Vec::new();
+        write(&canvas, BitDepth::Eight, &mut output).unwrap();
+
+        assert_eq!(&output[..8], &SIGNATURE);
+        let chunks = chunks(&output);
+        let names: Vec<&str> = chunks.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names, vec!["IHDR", "IDAT", "IEND"]);
+        assert_eq!(chunks[0].1, vec![0, 0, 0, 3, 0, 0, 0, 2, 8, 2, 0, 0, 0]);
+
+        // Unfilter the image data and check the pixels
+        let zlib = &chunks[1].1;
+        let raw = inflate(&zlib[2..zlib.len() - 4]);
+        assert_eq!(raw.len(), 2 * (1 + 3 * 3));
+        assert_eq!(&zlib[zlib.len() - 4..], &adler32(&raw).to_be_bytes());
+    }
+
+    #[test]
+    fn png_16_bit_samples() {
+        let mut canvas = Canvas::new(1, 1);
+        canvas.write_pixel(0, 0, Color::color(1.0, 0.5, 0.0));
+        let mut output: Vec<u8> = Vec::new();
+        write(&canvas, BitDepth::Sixteen, &mut output).unwrap();
+
+        let chunks = chunks(&output);
+        assert_eq!(chunks[0].1[8], 16);
+        let zlib = &chunks[1].1;
+        let raw = inflate(&zlib[2..zlib.len() - 4]);
+        // A single row with filter type 0 since there is no previous row or left pixel
+        assert_eq!(raw, vec![0, 0xff, 0xff, 0x80, 0x00, 0x00, 0x00]);
+    }
+
+    #[test]
+    fn png_with_alpha_channel() {
+        let mut canvas = Canvas::new_transparent(2, 1);
+        canvas.write_pixel_alpha(1, 0, Color::color(1.0, 1.0, 1.0), 0.5);
+        let mut output: Vec<u8> = Vec::new();
+        write(&canvas, BitDepth::Eight, &mut output).unwrap();
+
+        let chunks = chunks(&output);
+        assert_eq!(chunks[0].1[9], COLOR_TYPE_RGBA);
+        let zlib = &chunks[1].1;
+        let raw = inflate(&zlib[2..zlib.len() - 4]);
+        assert_eq!(raw.len(), 1 + 2 * 4);
+        // Undo the filter of the single row, the previous row is all zeros
+        let mut row = vec![0u8; 8];
+        for i in 0..8 {
+            let left = if i >= 4 { row[i - 4] } else { 0 };
+            row[i] = raw[1 + i].wrapping_add(match raw[0] {
+                0 | 2 => 0,
+                1 | 4 => left,
+                _ => left / 2,
+            });
+        }
+        assert_eq!(row, vec![0, 0, 0, 0, 0xff, 0xff, 0xff, 0x80]);
+    }
+}
@@ -1,111 +1,424 @@
-use crate::{canvas::Canvas, color::Color};
-pub(crate) use std::fs::File;
-use std::io::prelude::*;
+use crate::canvas::{Canvas, DecodeError};
+use crate::color::Color;
+use std::fs::File;
+use std::io::{self, prelude::*, BufWriter};
 use std::path::Path;
+use std::str::FromStr;
 
-pub struct Ppm {
-    filename: String,
-    pub lines: Vec<String>,
+// The PPM variants that can be written.
+// P3 stores the pixel values as ASCII text and P6 stores them as raw bytes.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    P3,
+    P6,
 }
 
 const MAX_LINELEN: usize = 70;
 
-impl Ppm {
-    pub fn new(filename: &String) -> Ppm {
-        let ppm = Ppm {
-            filename : filename.to_string(),
-            lines: vec![],
-        };
-        return ppm;
+fn color_to_ppm_string(color: Color) -> String {
+    let n_color = color.normalize_u8();
+    return format!("{0} {1} {2}", n_color.red, n_color.green, n_color.blue);
+}
+
+// Stream the canvas to the writer without building the whole image in memory first.
+pub fn write<W: Write>(canvas: &Canvas, format: Format, writer: &mut W) -> io::Result<()> {
+    match format {
+        Format::P3 => write_p3(canvas, writer),
+        Format::P6 => write_p6(canvas, writer),
     }
+}
 
-    pub fn add_canvas(&mut self, canvas: Canvas) {
-        self.add_header(canvas.width, canvas.height);
-        let mut pixels: Vec<String> = vec![];
-        let mut linelen = 0;
+// Write the canvas to a file. An existing file is overwritten.
+pub fn write_file<P: AsRef<Path>>(canvas: &Canvas, format: Format, path: P) -> io::Result<()> {
+    let mut file = BufWriter::new(File::create(path)?);
+    write(canvas, format, &mut file)?;
+    return file.flush();
+}
 
-        for pixel in canvas {
-            let pixel_string = self.color_to_ppm_string(pixel);
-            if linelen + pixel_string.len() > MAX_LINELEN {
-                self.lines.push(pixels.join(" "));
-                pixels.clear();
-                linelen = 0;
-            }
-            linelen = linelen + pixel_string.len() + 1;
-            pixels.push(pixel_string);
+fn write_p3<W: Write>(canvas: &Canvas, writer: &mut W) -> io::Result<()> {
+    write!(writer, "P3\n{} {}\n255\n", canvas.width, canvas.height)?;
+
+    // Pixels are never split over two lines, so a line is wrapped when the next pixel
+    // does not fit within the maximum line length.
+    let mut linelen = 0;
+    for pixel in canvas.pixels() {
+        let pixel_string = color_to_ppm_string(*pixel);
+        if linelen > 0 && linelen + pixel_string.len() > MAX_LINELEN {
+            writer.write_all(b"\n")?;
+            linelen = 0;
+        }
+        if linelen > 0 {
+            writer.write_all(b" ")?;
+        }
+        writer.write_all(pixel_string.as_bytes())?;
+        linelen += pixel_string.len() + 1;
+    }
+    return writer.write_all(b"\n");
+}
+
+fn write_p6<W: Write>(canvas: &Canvas, writer: &mut W) -> io::Result<()> {
+    write!(writer, "P6\n{} {}\n255\n", canvas.width, canvas.height)?;
+
+    let mut row = Vec::with_capacity(canvas.width * 3);
+    for pixels in canvas.rows() {
+        row.clear();
+        for pixel in pixels {
+            let color = pixel.normalize_u8();
+            row.extend_from_slice(&[color.red, color.green, color.blue]);
         }
-        self.lines.push(pixels.join(" "));
+        writer.write_all(&row)?;
     }
+    return Ok(());
+}
+
+// Read a P3 or P6 image. Comments and any amount of whitespace are allowed in the header
+// and the pixel values are scaled by the max color value so that it maps to 1.0.
+pub fn read<R: Read>(reader: &mut R) -> Result<Canvas, DecodeError> {
+    let mut data = Vec::new();
+    reader.read_to_end(&mut data)?;
+    let mut parser = Parser::new(&data);
 
-    fn color_to_ppm_string(&mut self, color: Color) -> String {
-        let n_color = color.normalize_u8();
-        return format!("{0} {1} {2}", n_color.red, n_color.green, n_color.blue);
+    let format = match parser.token() {
+        Some(b"P3") => Format::P3,
+        Some(b"P6") => Format::P6,
+        Some(magic) => {
+            let magic = String::from_utf8_lossy(magic);
+            return Err(malformed(format!("unsupported magic number '{}'", magic)));
+        }
+        None => return Err(malformed("missing magic number".to_string())),
+    };
+    let width: usize = parser.number("width")?;
+    let height: usize = parser.number("height")?;
+    let maxval: usize = parser.number("max color value")?;
+    if width == 0 || height == 0 {
+        return Err(malformed(format!("invalid size {}x{}", width, height)));
+    }
+    if maxval == 0 || maxval > 65535 {
+        return Err(malformed(format!("invalid max color value {}", maxval)));
     }
 
-    // The header is three lines long
-    // "P3" - Magic Number
-    // Width Height
-    // Max color value
-    fn add_header(&mut self, x: usize, y: usize) {
-        self.lines.push("P3".to_string());
-        self.lines.push(format!("{0} {1}", x, y).to_string());
-        self.lines.push("255".to_string());
+    // A single whitespace character separates the header from the binary pixel data
+    if format == Format::P6 {
+        parser.pos += 1;
     }
 
-    // Write the lines to file
-    pub fn write_file(&self) {
-        let path = Path::new(&self.filename);
-        let display = path.display();
+    // The size in the header is checked against the data before the canvas is
+    // allocated, so a small file cannot ask for a huge canvas
+    let samples = width
+        .checked_mul(height)
+        .and_then(|pixels| pixels.checked_mul(3))
+        .ok_or_else(|| malformed(format!("invalid size {}x{}", width, height)))?;
+    let remaining = data.len().saturating_sub(parser.pos);
+    let fits = match format {
+        // One or two bytes for each sample
+        Format::P6 => samples
+            .checked_mul(if maxval > 255 { 2 } else { 1 })
+            .is_some_and(|size| size <= remaining),
+        // At least one digit and a separator for each sample except the last
+        Format::P3 => samples <= remaining / 2 + 1,
+    };
+    if !fits {
+        return Err(malformed(format!(
+            "the pixel data is too short for the size {}x{}",
+            width, height
+        )));
+    }
 
-        let mut file = match File::create(&path) {
-            Err(why) => panic!("couldn't create {}: {}", display, why),
-            Ok(file) => file,
-        };
+    let mut canvas = Canvas::new(width, height);
+    let mut sample = || match format {
+        Format::P3 => parser.number("color value"),
+        Format::P6 => parser.binary_sample(maxval > 255),
+    };
 
-        for line in &self.lines {
-            match file.write_all(line.as_bytes()) {
-                Err(why) => panic!("couldn't write to {}: {}", display, why),
-                Ok(_) => (),
+    for y in 0..height {
+        for x in 0..width {
+            let mut parts = [0.0; 3];
+            for part in parts.iter_mut() {
+                let value = sample()?;
+                if value > maxval {
+                    return Err(malformed(format!(
+                        "color value {} exceeds {}",
+                        value, maxval
+                    )));
+                }
+                *part = value as f64 / maxval as f64;
             }
-            match file.write("\n".as_bytes()) {
-                Err(why) => panic!("couldn't write to {}: {}", display, why),
-                Ok(_) => (),
+            canvas.write_pixel(x, y, Color::color(parts[0], parts[1], parts[2]));
+        }
+    }
+    return Ok(canvas);
+}
+
+// Read a P3 or P6 image from a file
+pub fn read_file<P: AsRef<Path>>(path: P) -> Result<Canvas, DecodeError> {
+    let mut file = io::BufReader::new(File::open(path)?);
+    return read(&mut file);
+}
+
+pub(crate) fn malformed(reason: String) -> DecodeError {
+    DecodeError::Malformed(reason)
+}
+
+// Parser for the header and ASCII data of the Netpbm family of formats
+pub(crate) struct Parser<'a> {
+    pub(crate) data: &'a [u8],
+    pub(crate) pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    pub(crate) fn new(data: &'a [u8]) -> Parser<'a> {
+        Parser { data, pos: 0 }
+    }
+
+    // Skip whitespace and comments. A comment starts with '#' and ends at the end of the line.
+    fn skip_whitespace(&mut self) {
+        while self.pos < self.data.len() {
+            match self.data[self.pos] {
+                b'#' => {
+                    while self.pos < self.data.len() && self.data[self.pos] != b'\n' {
+                        self.pos += 1;
+                    }
+                }
+                c if c.is_ascii_whitespace() => self.pos += 1,
+                _ => break,
             }
         }
     }
+
+    pub(crate) fn token(&mut self) -> Option<&'a [u8]> {
+        self.skip_whitespace();
+        let start = self.pos;
+        while self.pos < self.data.len()
+            && !self.data[self.pos].is_ascii_whitespace()
+            && self.data[self.pos] != b'#'
+        {
+            self.pos += 1;
+        }
+        if start == self.pos {
+            None
+        } else {
+            Some(&self.data[start..self.pos])
+        }
+    }
+
+    pub(crate) fn number<T: FromStr>(&mut self, name: &str) -> Result<T, DecodeError> {
+        let token = match self.token() {
+            Some(token) => token,
+            None => return Err(malformed(format!("missing {}", name))),
+        };
+        let text = String::from_utf8_lossy(token);
+        return text
+            .parse::<T>()
+            .map_err(|_| malformed(format!("invalid {} '{}'", name, text)));
+    }
+
+    // Binary samples are one byte, or two bytes in big-endian order if the max value is above 255
+    fn binary_sample(&mut self, wide: bool) -> Result<usize, DecodeError> {
+        let size = if wide { 2 } else { 1 };
+        if self.pos + size > self.data.len() {
+            return Err(malformed("pixel data is truncated".to_string()));
+        }
+        let bytes = &self.data[self.pos..self.pos + size];
+        self.pos += size;
+        return Ok(bytes
+            .iter()
+            .fold(0, |value, byte| value << 8 | *byte as usize));
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
-    #[test]
-    fn ppm_empty_after_creation() {
-        let p = Ppm::new(&"test".to_string());
-        assert!(p.lines.len() == 0);
+    fn test_canvas() -> Canvas {
+        let mut canvas = Canvas::new(5, 3);
+        canvas.write_pixel(0, 0, Color::color(1.5, 0.0, 0.0));
+        canvas.write_pixel(2, 1, Color::color(0.0, 0.5, 0.0));
+        canvas.write_pixel(4, 2, Color::color(-0.5, 0.0, 1.5));
+        return canvas;
+    }
+
+    fn p3_lines(canvas: &Canvas) -> Vec<String> {
+        let mut output: Vec<u8> = Vec::new();
+        write(canvas, Format::P3, &mut output).unwrap();
+        let text = String::from_utf8(output).unwrap();
+        assert!(text.ends_with('\n'));
+        return text.lines().map(|line| line.to_string()).collect();
     }
 
     #[test]
     fn ppm_create_canvas_header() {
-        let canvas = Canvas::new(5, 3);
-        let mut p = Ppm::new(&"test".to_string());
-        p.add_canvas(canvas);
-        assert!(p.lines[0] == "P3"); // Magic number
-        assert!(p.lines[1] == "5 3"); // Width and height
-        assert!(p.lines[2] == "255"); // Max color value
+        let lines = p3_lines(&Canvas::new(5, 3));
+        assert!(lines[0] == "P3"); // Magic number
+        assert!(lines[1] == "5 3"); // Width and height
+        assert!(lines[2] == "255"); // Max color value
     }
 
     #[test]
     fn ppm_create_canvas_pixel_data() {
-        let mut canvas = Canvas::new(5, 3);
-        canvas.write_pixel(0, 0, Color::color(1.5, 0.0, 0.0));
-        canvas.write_pixel(2, 1, Color::color(0.0, 0.5, 0.0));
-        canvas.write_pixel(4, 2, Color::color(-0.5, 0.0, 1.5));
-        let mut p = Ppm::new(&"test".to_string());
-        p.add_canvas(canvas);
+        let lines = p3_lines(&test_canvas());
         assert!(
-            p.lines[3] == "255 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 128 0 0 0 0 0 0 0 0 0 0"
+            lines[3] == "255 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 128 0 0 0 0 0 0 0 0 0 0"
+        );
+        assert!(lines[4] == "0 0 0 0 0 0 0 0 0 0 0 255");
+        assert_eq!(lines.len(), 5);
+    }
+
+    #[test]
+    fn streamed_p3_wraps_long_lines() {
+        let mut canvas = Canvas::new(10, 2);
+        for y in 0..2 {
+            for x in 0..10 {
+                canvas.write_pixel(x, y, Color::color(1.0, 0.8, 0.6));
+            }
+        }
+        let mut output: Vec<u8> = Vec::new();
+        write(&canvas, Format::P3, &mut output).unwrap();
+        let text = String::from_utf8(output).unwrap();
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(
+            lines[3],
+            "255 204 153 255 204 153 255 204 153 255 204 153 255 204 153"
+        );
+        assert!(lines.iter().all(|line| line.len() <= MAX_LINELEN));
+        assert!(text.ends_with('\n'));
+    }
+
+    #[test]
+    fn p6_header_and_binary_pixel_data() {
+        let mut output: Vec<u8> = Vec::new();
+        write(&test_canvas(), Format::P6, &mut output).unwrap();
+
+        let header = b"P6\n5 3\n255\n";
+        assert_eq!(&output[..header.len()], header);
+        let pixels = &output[header.len()..];
+        assert_eq!(pixels.len(), 5 * 3 * 3);
+        assert_eq!(&pixels[0..3], &[255, 0, 0]);
+        assert_eq!(&pixels[(5 + 2) * 3..(5 + 2) * 3 + 3], &[0, 128, 0]);
+        assert_eq!(&pixels[(2 * 5 + 4) * 3..], &[0, 0, 255]);
+    }
+
+    fn read_str(text: &str) -> Result<Canvas, DecodeError> {
+        return read(&mut text.as_bytes());
+    }
+
+    #[test]
+    fn read_p3_with_comments_and_whitespace() {
+        let canvas =
+            read_str("P3\n# A comment\n2   1 # trailing comment\n\n255\n255 0 0\t0 128\n 255\n")
+                .unwrap();
+        assert_eq!(canvas.width, 2);
+        assert_eq!(canvas.height, 1);
+        assert_eq!(canvas.read_pixel(0, 0), Some(Color::color(1.0, 0.0, 0.0)));
+        assert_eq!(
+            canvas.read_pixel(1, 0),
+            Some(Color::color(0.0, 128.0 / 255.0, 1.0))
         );
-        assert!(p.lines[4] == "0 0 0 0 0 0 0 0 0 0 0 255");
+    }
+
+    #[test]
+    fn read_p3_scales_by_max_color_value() {
+        let canvas = read_str("P3 1 2 100 100 50 0 25 75 10").unwrap();
+        assert_eq!(canvas.read_pixel(0, 0), Some(Color::color(1.0, 0.5, 0.0)));
+        assert_eq!(canvas.read_pixel(0, 1), Some(Color::color(0.25, 0.75, 0.1)));
+    }
+
+    #[test]
+    fn read_p6_with_16_bit_samples() {
+        let mut data = b"P6\n1 1\n65535\n".to_vec();
+        data.extend_from_slice(&[0xff, 0xff, 0x80, 0x00, 0x00, 0x00]);
+        let canvas = read(&mut data.as_slice()).unwrap();
+        assert_eq!(
+            canvas.read_pixel(0, 0),
+            Some(Color::color(1.0, 32768.0 / 65535.0, 0.0))
+        );
+    }
+
+    #[test]
+    fn written_images_can_be_read_back() {
+        for format in [Format::P3, Format::P6] {
+            let mut output: Vec<u8> = Vec::new();
+            write(&test_canvas(), format, &mut output).unwrap();
+            let canvas = read(&mut output.as_slice()).unwrap();
+            assert_eq!(canvas.width, 5);
+            assert_eq!(canvas.height, 3);
+            assert_eq!(canvas.read_pixel(0, 0), Some(Color::color(1.0, 0.0, 0.0)));
+            assert_eq!(
+                canvas.read_pixel(2, 1),
+                Some(Color::color(0.0, 128.0 / 255.0, 0.0))
+            );
+            assert_eq!(canvas.read_pixel(4, 2), Some(Color::color(0.0, 0.0, 1.0)));
+        }
+    }
+
+    #[test]
+    fn read_reports_malformed_headers() {
+        assert!(matches!(read_str(""), Err(DecodeError::Malformed(_))));
+        assert!(matches!(
+            read_str("P5 1 1 255 0"),
+            Err(DecodeError::Malformed(_))
+        ));
+        assert!(matches!(
+            read_str("P3 1 x 255 0 0 0"),
+            Err(DecodeError::Malformed(_))
+        ));
+        assert!(matches!(
+            read_str("P3 0 1 255"),
+            Err(DecodeError::Malformed(_))
+        ));
+        assert!(matches!(
+            read_str("P3 1 1 0 0 0 0"),
+            Err(DecodeError::Malformed(_))
+        ));
+        assert!(matches!(
+            read_str("P3 1 1 70000 0 0 0"),
+            Err(DecodeError::Malformed(_))
+        ));
+    }
+
+    #[test]
+    fn read_reports_bad_pixel_data() {
+        assert!(matches!(
+            read_str("P3 2 1 255 0 0 0 0 0"),
+            Err(DecodeError::Malformed(_))
+        ));
+        assert!(matches!(
+            read_str("P3 1 1 15 0 16 0"),
+            Err(DecodeError::Malformed(_))
+        ));
+        assert!(matches!(
+            read_str("P6 2 1 255 \u{1}\u{2}\u{3}"),
+            Err(DecodeError::Malformed(_))
+        ));
+    }
+
+    #[test]
+    fn read_checks_the_size_before_allocating() {
+        let message = |result: Result<Canvas, DecodeError>| match result {
+            Err(DecodeError::Malformed(message)) => message,
+            _ => panic!("expected a malformed image"),
+        };
+        // The sizes overflow when multiplied
+        assert!(message(read_str("P3 18446744073709551615 2 255 0 0 0")).contains("invalid size"));
+        assert!(message(read_str("P6 6148914691236517206 1 255 \u{0}")).contains("invalid size"));
+        // A huge canvas with almost no pixel data
+        assert!(message(read_str("P6\n100000 100000\n255\n\u{1}\u{2}\u{3}")).contains("too short"));
+        assert!(message(read_str("P6 1 1 65535 \u{0}\u{1}\u{2}")).contains("too short"));
+        assert!(message(read_str("P3 100000 100000 255 0 0 0")).contains("too short"));
+    }
+
+    #[test]
+    fn write_errors_are_returned() {
+        struct FailingWriter;
+        impl Write for FailingWriter {
+            fn write(&mut self, _buf: &[u8]) -> io::Result<usize> {
+                Err(io::Error::other("disk full"))
+            }
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+        assert!(write(&test_canvas(), Format::P6, &mut FailingWriter).is_err());
+        assert!(write(&test_canvas(), Format::P3, &mut FailingWriter).is_err());
     }
 }
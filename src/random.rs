@@ -0,0 +1,156 @@
+use crate::tuple::{orthonormal_basis, Tuple};
+use std::f64::consts::PI;
+
+// A small pseudo random number generator (SplitMix64). It is fast, has no
+// dependencies and gives the same sequence for the same seed, so renders can be
+// reproduced and do not depend on how the rows are split between threads.
+#[derive(Debug, Clone)]
+pub struct Random {
+    state: u64,
+}
+
+impl Random {
+    pub fn new(seed: u64) -> Random {
+        return Random { state: seed };
+    }
+
+    // A generator seeded from the coordinates of a point, used where the same point
+    // must get the same random numbers, for example when a light is sampled both for
+    // shading and for the shadow test
+    pub fn from_point(point: &Tuple, salt: u64) -> Random {
+        let mut seed = salt;
+        for coordinate in [point.x, point.y, point.z] {
+            seed = mix(seed ^ coordinate.to_bits());
+        }
+        return Random::new(seed);
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        return mix(self.state);
+    }
+
+    // A number in the range 0.0 - 1.0, excluding 1.0
+    pub fn next_f64(&mut self) -> f64 {
+        return (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64;
+    }
+
+    // A random direction within the cone around the axis where the cosine of the angle
+    // to the axis is at least cos_max, where every direction is equally likely
+    pub fn uniform_cone(&mut self, axis: &Tuple, cos_max: f64) -> Tuple {
+        let z = 1.0 - self.next_f64() * (1.0 - cos_max);
+        let r = (1.0 - z * z).max(0.0).sqrt();
+        let phi = 2.0 * PI * self.next_f64();
+        let (tangent, bitangent) = orthonormal_basis(axis);
+        return tangent * (r * phi.cos()) + bitangent * (r * phi.sin()) + *axis * z;
+    }
+
+    // A random direction in the hemisphere around the normal, where the probability is
+    // proportional to the cosine of the angle to the normal. Directions near the
+    // normal, where a diffuse surface gets most of its light from, are more likely.
+    pub fn cosine_hemisphere(&mut self, normal: &Tuple) -> Tuple {
+        let u = self.next_f64();
+        let r = u.sqrt();
+        let z = (1.0 - u).sqrt();
+        let phi = 2.0 * PI * self.next_f64();
+        let (tangent, bitangent) = orthonormal_basis(normal);
+        return tangent * (r * phi.cos()) + bitangent * (r * phi.sin()) + *normal * z;
+    }
+}
+
+fn mix(value: u64) -> u64 {
+    let mut z = value;
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    return z ^ (z >> 31);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    impl Random {
+        // A random direction in the hemisphere around the normal, where every direction
+        // is equally likely
+        pub fn uniform_hemisphere(&mut self, normal: &Tuple) -> Tuple {
+            return self.uniform_cone(normal, 0.0);
+        }
+    }
+
+    #[test]
+    fn same_seed_gives_same_sequence() {
+        let mut a = Random::new(42);
+        let mut b = Random::new(42);
+        for _ in 0..10 {
+            assert_eq!(a.next_u64(), b.next_u64());
+        }
+        assert_ne!(Random::new(1).next_u64(), Random::new(2).next_u64());
+    }
+
+    #[test]
+    fn numbers_are_in_the_unit_interval() {
+        let mut random = Random::new(7);
+        let numbers: Vec<f64> = (0..1000).map(|_| random.next_f64()).collect();
+        assert!(numbers.iter().all(|n| (0.0..1.0).contains(n)));
+        let mean = numbers.iter().sum::<f64>() / numbers.len() as f64;
+        assert!((mean - 0.5).abs() < 0.05);
+    }
+
+    #[test]
+    fn seed_from_point() {
+        let point = Tuple::point(1.0, 2.0, 3.0);
+        assert_eq!(
+            Random::from_point(&point, 0).next_u64(),
+            Random::from_point(&point, 0).next_u64()
+        );
+        assert_ne!(
+            Random::from_point(&point, 0).next_u64(),
+            Random::from_point(&point, 1).next_u64()
+        );
+        assert_ne!(
+            Random::from_point(&point, 0).next_u64(),
+            Random::from_point(&Tuple::point(1.0, 2.0, 3.5), 0).next_u64()
+        );
+    }
+
+    #[test]
+    fn directions_in_the_hemisphere() {
+        let mut random = Random::new(3);
+        let normal = Tuple::vector(0.0, 1.0, 0.0);
+        let mut mean_height = 0.0;
+        for _ in 0..1000 {
+            let direction = random.uniform_hemisphere(&normal);
+            assert!((direction.magnitude() - 1.0).abs() < 0.00001);
+            assert!(direction.y >= 0.0);
+            mean_height += direction.y / 1000.0;
+        }
+        // The mean of the cosine over the hemisphere is 1/2
+        assert!((mean_height - 0.5).abs() < 0.05);
+    }
+
+    #[test]
+    fn cosine_weighted_directions() {
+        let mut random = Random::new(5);
+        let normal = Tuple::vector(1.0, 0.0, 0.0);
+        let mut mean_height = 0.0;
+        for _ in 0..1000 {
+            let direction = random.cosine_hemisphere(&normal);
+            assert!((direction.magnitude() - 1.0).abs() < 0.00001);
+            assert!(direction.x >= 0.0);
+            mean_height += direction.x / 1000.0;
+        }
+        // With cosine weighting the mean of the cosine is 2/3
+        assert!((mean_height - 2.0 / 3.0).abs() < 0.05);
+    }
+
+    #[test]
+    fn directions_in_a_cone() {
+        let mut random = Random::new(11);
+        let axis = Tuple::vector(0.0, 0.0, -1.0);
+        for _ in 0..1000 {
+            let direction = random.uniform_cone(&axis, 0.9);
+            assert!((direction.magnitude() - 1.0).abs() < 0.00001);
+            assert!(-direction.z >= 0.9 - 0.00001);
+        }
+    }
+}
@@ -1,8 +1,10 @@
 use crate::matrices::{to_matrix, to_tuple, Matrix};
 use crate::shapes::Normal;
-use crate::shapes::Sphere;
-use crate::tuple::{dot, Tuple};
+use crate::shapes::{Material, Sphere};
+use crate::tuple::{dot, reflect, Tuple};
 use crate::world::World;
+use std::borrow::Cow;
+use std::ptr;
 
 #[derive(Debug)]
 pub struct Ray {
@@ -24,21 +26,32 @@ pub struct Computation<'a> {
     pub eyev: Tuple,
     pub normalv: Tuple,
     pub inside: bool,
+    // The point moved slightly along the normal, so rays cast from it towards the
+    // lights do not hit the surface itself because of rounding errors
+    pub over_point: Tuple,
+    // The point moved slightly below the surface, where refracted rays start
+    pub under_point: Tuple,
+    // The direction of the ray reflected by the surface
+    pub reflectv: Tuple,
+    // The refractive indices on the side the ray comes from and the side it enters
+    pub n1: f64,
+    pub n2: f64,
+    // The material of the object at the point, with the color of its texture
+    pub material: Cow<'a, Material>,
 }
 
+// Offset used to move points away from a surface
+pub const EPSILON: f64 = 0.00001;
+
 // Return a reference to the intersection with the lowest non-negative t value
 pub fn hit<'a>(intersections: &'a Vec<Intersection<'a>>) -> Option<&'a Intersection<'a>> {
     if intersections.is_empty() {
         None
     } else {
-        let mut min_t = None;
+        let mut min_t: Option<&Intersection> = None;
         for intersection in intersections {
-            if intersection.t >= 0.0 {
-                if min_t.is_none() {
-                    min_t = Some(intersection);
-                } else if intersection.t < min_t.unwrap().t {
-                    min_t = Some(intersection);
-                }
+            if intersection.t >= 0.0 && (min_t.is_none() || intersection.t < min_t.unwrap().t) {
+                min_t = Some(intersection);
             }
         }
         min_t
@@ -107,21 +120,140 @@ impl Ray {
             inside = true;
             normalv = -normalv;
         }
+        // The point is only needed in object space for a texture
+        let object = intersection.object;
+        let material = match object.material.texture {
+            None => Cow::Borrowed(&object.material),
+            Some(_) => object.material.at(&object.object_point(&position)),
+        };
+        // Rays towards the lights leave from the geometric surface, and the normal is
+        // only changed by the bump map after that
+        let over_point = position + normalv * EPSILON;
+        let under_point = position - normalv * EPSILON;
+        if let Some(bump) = &object.material.bump {
+            let geometric = normalv;
+            normalv = bump.perturb(object, &position, &normalv);
+            // A steep bump can turn the normal away from the eye, which shades the
+            // surface as if it was seen from behind. The part towards the back is
+            // removed, which leaves the normal along the silhouette.
+            let facing = dot(&normalv, &eyev);
+            if facing < 0. {
+                let projected = normalv - eyev * facing;
+                normalv = if projected.magnitude() < EPSILON {
+                    geometric
+                } else {
+                    projected.normalize()
+                };
+            }
+        }
+        let reflectv = reflect(&self.direction, &normalv);
+        // Without the other intersections the object is taken to be surrounded by air
+        let index = object.material.refractive_index;
+        let (n1, n2) = if inside { (index, 1.0) } else { (1.0, index) };
         Computation {
+            material,
             t: intersection.t,
             object: intersection.object,
             point: position,
             eyev,
             normalv,
             inside,
+            over_point,
+            under_point,
+            reflectv,
+            n1,
+            n2,
+        }
+    }
+
+    // Like prepare_computation, with the refractive indices from all the intersections
+    // of the ray, so a ray can go from one transparent object into another one that
+    // it is inside of
+    pub fn prepare_computation_in<'a>(
+        &self,
+        intersection: &Intersection<'a>,
+        intersections: &[Intersection<'a>],
+    ) -> Computation<'a> {
+        let mut computation = self.prepare_computation(intersection);
+        // The objects the ray is inside of, the last one entered at the end
+        let mut containers: Vec<&Sphere> = Vec::new();
+        let index = |containers: &Vec<&Sphere>| match containers.last() {
+            Some(object) => object.material.refractive_index,
+            None => 1.0,
+        };
+        for i in intersections {
+            let is_hit = i.t == intersection.t && ptr::eq(i.object, intersection.object);
+            if is_hit {
+                computation.n1 = index(&containers);
+            }
+            match containers
+                .iter()
+                .position(|object| ptr::eq(*object, i.object))
+            {
+                Some(position) => {
+                    containers.remove(position);
+                }
+                None => containers.push(i.object),
+            }
+            if is_hit {
+                computation.n2 = index(&containers);
+                break;
+            }
+        }
+        return computation;
+    }
+}
+
+impl Computation<'_> {
+    // The direction of the refracted ray by Snell's law, or None for total internal
+    // reflection
+    pub fn refractv(&self) -> Option<Tuple> {
+        let n_ratio = self.n1 / self.n2;
+        let cos_i = dot(&self.eyev, &self.normalv);
+        let sin2_t = n_ratio * n_ratio * (1.0 - cos_i * cos_i);
+        if sin2_t > 1.0 {
+            return None;
+        }
+        let cos_t = (1.0 - sin2_t).sqrt();
+        return Some(self.normalv * (n_ratio * cos_i - cos_t) - self.eyev * n_ratio);
+    }
+
+    // The fraction of the light that is reflected, by Schlick's approximation of the
+    // Fresnel equations
+    pub fn schlick(&self) -> f64 {
+        let mut cos = dot(&self.eyev, &self.normalv);
+        if self.n1 > self.n2 {
+            let n_ratio = self.n1 / self.n2;
+            let sin2_t = n_ratio * n_ratio * (1.0 - cos * cos);
+            if sin2_t > 1.0 {
+                return 1.0;
+            }
+            cos = (1.0 - sin2_t).sqrt();
+        }
+        let r0 = ((self.n1 - self.n2) / (self.n1 + self.n2)).powi(2);
+        return r0 + (1.0 - r0) * (1.0 - cos).powi(5);
+    }
+
+    // How much of the reflected and of the refracted color is added to the color of
+    // the surface. A material that is both reflective and transparent reflects more
+    // at grazing angles, like glass.
+    pub fn specular_weights(&self) -> (f64, f64) {
+        let (reflective, transparency) = (self.material.reflective, self.material.transparency);
+        if reflective > 0.0 && transparency > 0.0 {
+            let reflectance = self.schlick();
+            return (reflective * reflectance, transparency * (1.0 - reflectance));
         }
+        return (reflective, transparency);
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::ptr;
+    use crate::bump::Bump;
+    use crate::canvas::Canvas;
+    use crate::color::Color;
+    use crate::texture::{Filter, Mapping, Texture};
 
     // The referenced object must live at least as long as the intersection object
     impl<'a> Intersection<'a> {
@@ -326,4 +458,178 @@ mod tests {
         // Normal vector is inverted since the intersection is on the inside
         assert_eq!(computation.normalv, Tuple::vector(0., 0., -1.));
     }
+
+    #[test]
+    fn hit_offsets_the_point() {
+        let r = Ray::new(Tuple::point(0.0, 0.0, -5.0), Tuple::vector(0.0, 0.0, 1.0));
+        let mut s = Sphere::new();
+        s.transformation = Matrix::new_identity().translate(0.0, 0.0, 1.0);
+        let computation = r.prepare_computation(&Intersection::new(5.0, &s));
+        assert!(computation.over_point.z < -EPSILON / 2.0);
+        assert!(computation.point.z > computation.over_point.z);
+    }
+
+    fn glass_sphere() -> Sphere {
+        let mut sphere = Sphere::new();
+        sphere.material.transparency = 1.0;
+        sphere.material.refractive_index = 1.5;
+        return sphere;
+    }
+
+    #[test]
+    fn refractive_indices_of_nested_objects() {
+        let mut a = glass_sphere();
+        a.transformation = Matrix::new_identity().scale(2., 2., 2.);
+        let mut b = glass_sphere();
+        b.transformation = Matrix::new_identity().translate(0., 0., -0.25);
+        b.material.refractive_index = 2.0;
+        let mut c = glass_sphere();
+        c.transformation = Matrix::new_identity().translate(0., 0., 0.25);
+        c.material.refractive_index = 2.5;
+        let ray = Ray::new(Tuple::point(0., 0., -4.), Tuple::vector(0., 0., 1.));
+        let intersections = vec![
+            Intersection::new(2., &a),
+            Intersection::new(2.75, &b),
+            Intersection::new(3.25, &c),
+            Intersection::new(4.75, &b),
+            Intersection::new(5.25, &c),
+            Intersection::new(6., &a),
+        ];
+        let expected = [
+            (1.0, 1.5),
+            (1.5, 2.0),
+            (2.0, 2.5),
+            (2.5, 2.5),
+            (2.5, 1.5),
+            (1.5, 1.0),
+        ];
+        for (intersection, (n1, n2)) in intersections.iter().zip(expected) {
+            let computation = ray.prepare_computation_in(intersection, &intersections);
+            assert_eq!((computation.n1, computation.n2), (n1, n2));
+        }
+    }
+
+    #[test]
+    fn hit_offsets_the_point_under_the_surface() {
+        let ray = Ray::new(Tuple::point(0., 0., -5.), Tuple::vector(0., 0., 1.));
+        let mut shape = glass_sphere();
+        shape.transformation = Matrix::new_identity().translate(0., 0., 1.);
+        let computation = ray.prepare_computation(&Intersection::new(5., &shape));
+        assert!(computation.under_point.z > EPSILON / 2.0);
+        assert!(computation.point.z < computation.under_point.z);
+        assert_eq!((computation.n1, computation.n2), (1.0, 1.5));
+    }
+
+    #[test]
+    fn refraction_and_total_internal_reflection() {
+        let shape = glass_sphere();
+        // Straight through the middle the ray is not bent
+        let ray = Ray::new(Tuple::point(0., 0., -5.), Tuple::vector(0., 0., 1.));
+        let computation = ray.prepare_computation(&Intersection::new(4., &shape));
+        assert_eq!(computation.refractv(), Some(Tuple::vector(0., 0., 1.)));
+        assert!((computation.schlick() - 0.04).abs() < 1e-5);
+
+        // From inside the glass at a grazing angle all the light is reflected
+        let half = 2f64.sqrt() / 2.;
+        let ray = Ray::new(Tuple::point(0., 0., half), Tuple::vector(0., 1., 0.));
+        let intersections = vec![
+            Intersection::new(-half, &shape),
+            Intersection::new(half, &shape),
+        ];
+        let computation = ray.prepare_computation_in(&intersections[1], &intersections);
+        assert_eq!(computation.refractv(), None);
+        assert_eq!(computation.schlick(), 1.0);
+    }
+
+    #[test]
+    fn reflective_glass_reflects_more_at_grazing_angles() {
+        let mut shape = glass_sphere();
+        shape.material.reflective = 1.0;
+        let ray = Ray::new(Tuple::point(0., 0.99, -2.), Tuple::vector(0., 0., 1.));
+        let intersections = ray.intersects(&shape);
+        let computation = ray.prepare_computation_in(&intersections[0], &intersections);
+        let (reflective, transparency) = computation.specular_weights();
+        assert!((reflective - 0.48881).abs() < 1e-4);
+        assert!((reflective + transparency - 1.0).abs() < 1e-9);
+
+        // Without reflections only the transparency counts
+        shape.material.reflective = 0.0;
+        let computation = ray.prepare_computation(&Intersection::new(1., &shape));
+        assert_eq!(computation.specular_weights(), (0.0, 1.0));
+    }
+
+    #[test]
+    fn computation_has_the_color_of_the_texture() {
+        // Red on the left half of the image and green on the right half
+        let mut image = Canvas::new(2, 1);
+        image.write_pixel(0, 0, Color::color(1.0, 0.0, 0.0));
+        image.write_pixel(1, 0, Color::color(0.0, 1.0, 0.0));
+        let mut s = Sphere::new();
+        s.transformation = Matrix::new_identity().translate(5.0, 0.0, 0.0);
+        s.material.color = Color::color(0.5, 0.5, 0.5);
+        s.material.texture = Texture::new(image, Mapping::Spherical, Filter::Nearest);
+        let r = Ray::new(Tuple::point(5.5, 0.0, -5.0), Tuple::vector(0.0, 0.0, 1.0));
+        let computation = r.prepare_computation(&Intersection::new(5.0 - 0.75_f64.sqrt(), &s));
+        assert_eq!(computation.material.color, Color::color(0.0, 0.5, 0.0));
+        assert_eq!(computation.material.texture, None);
+        assert_eq!(computation.material.diffuse, s.material.diffuse);
+
+        let r = Ray::new(Tuple::point(4.5, 0.0, -5.0), Tuple::vector(0.0, 0.0, 1.0));
+        let computation = r.prepare_computation(&Intersection::new(5.0 - 0.75_f64.sqrt(), &s));
+        assert_eq!(computation.material.color, Color::color(0.5, 0.0, 0.0));
+    }
+
+    #[test]
+    fn computation_has_the_normal_of_the_bump_map() {
+        // A normal map that tilts every normal up
+        let mut image = Canvas::new(1, 1);
+        image.write_pixel(0, 0, Color::color(0.5, 1.0, 1.0));
+        let texture = Texture::new(image, Mapping::Spherical, Filter::Nearest).unwrap();
+        let mut s = Sphere::new();
+        s.material.bump = Some(Bump::NormalMap {
+            texture,
+            strength: 1.0,
+        });
+        let r = Ray::new(Tuple::point(0.0, 0.0, -5.0), Tuple::vector(0.0, 0.0, 1.0));
+        let computation = r.prepare_computation(&Intersection::new(4.0, &s));
+        let half = 0.5_f64.sqrt();
+        assert_eq!(computation.normalv, Tuple::vector(0.0, half, -half));
+        // The point is still moved off the surface along the geometric normal
+        assert!(computation.over_point.z < -1.0);
+        assert_eq!(computation.over_point.y, 0.0);
+
+        // Inside the sphere the normal is turned towards the eye before it is tilted
+        let r = Ray::new(Tuple::point(0.0, 0.0, 0.0), Tuple::vector(0.0, 0.0, -1.0));
+        let computation = r.prepare_computation(&Intersection::new(1.0, &s));
+        assert!(computation.inside);
+        assert!(computation.normalv.z > 0.0);
+    }
+
+    #[test]
+    fn steep_normal_map_still_faces_the_eye() {
+        // The texel tilts the normal up and then backwards, away from the eye
+        let mut image = Canvas::new(1, 1);
+        image.write_pixel(0, 0, Color::color(0.5, 1.0, 0.0));
+        let texture = Texture::new(image, Mapping::Spherical, Filter::Nearest).unwrap();
+        let mut s = Sphere::new();
+        s.material.bump = Some(Bump::NormalMap {
+            texture,
+            strength: 1.0,
+        });
+        let r = Ray::new(Tuple::point(0.0, 0.0, -5.0), Tuple::vector(0.0, 0.0, 1.0));
+        let computation = r.prepare_computation(&Intersection::new(4.0, &s));
+        assert!(dot(&computation.normalv, &computation.eyev) >= 0.0);
+        assert_eq!(computation.normalv, Tuple::vector(0.0, 1.0, 0.0));
+
+        // A normal pointing straight back falls back to the geometric normal
+        let mut image = Canvas::new(1, 1);
+        image.write_pixel(0, 0, Color::color(0.5, 0.5, 0.0));
+        let texture = Texture::new(image, Mapping::Spherical, Filter::Nearest).unwrap();
+        s.material.bump = Some(Bump::NormalMap {
+            texture,
+            strength: 1.0,
+        });
+        let computation = r.prepare_computation(&Intersection::new(4.0, &s));
+        assert_eq!(computation.normalv, Tuple::vector(0.0, 0.0, -1.0));
+    }
 }
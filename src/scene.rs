@@ -0,0 +1,1698 @@
+use crate::bump::Bump;
+use crate::camera::Camera;
+use crate::canvas::Canvas;
+use crate::color::Color;
+use crate::environment::{CubeMap, Environment, EnvironmentMap};
+use crate::lights::{AreaLight, Attenuation, DirectionalLight, Light, PointLight, SpotLight};
+use crate::matrices::Matrix;
+use crate::output;
+use crate::shapes::{Material, ShadingModel, Sphere};
+use crate::texture::{Filter, Mapping, Texture};
+use crate::tuple::Tuple;
+use crate::world::World;
+use crate::yaml::{self, Node, Value};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::{error, fmt, fs, io, mem};
+
+// The errors in a scene file are reported with the line and column
+pub use crate::yaml::ParseError;
+
+// Module to build a camera and a world from a scene description.
+//
+// A scene file is a YAML sequence of commands in the format used in the Ray Tracer
+// Challenge community. Each command is a mapping with an "add" key that tells what to
+// add to the scene:
+//
+//   - add: camera
+//     width: 600
+//     height: 300
+//     field-of-view: 1.047
+//     from: [0, 1.5, -5]
+//     to: [0, 1, 0]
+//     up: [0, 1, 0]
+//
+//   - add: light
+//     at: [-10, 10, -10]
+//     intensity: [1, 1, 1]
+//
+//   - add: sphere
+//     material:
+//       color: [0.1, 1, 0.5]
+//       diffuse: 0.7
+//     transform:
+//       - [scale, 0.5, 0.5, 0.5]
+//       - [translate, 1.5, 0.5, -0.5]
+//
+// The transformations are applied in the order they are listed. Angles are given in
+// radians.
+//
+// An area light, which gives soft shadows, is a rectangle given by a corner and two
+// edges, each divided into a number of steps:
+//
+//   - add: light
+//     corner: [-1, 2, 4]
+//     uvec: [2, 0, 0]
+//     usteps: 8
+//     vvec: [0, 2, 0]
+//     vsteps: 8
+//     jitter: true
+//     intensity: [1.5, 1.5, 1.5]
+//
+// A spot light shines from "at" in a direction, with full intensity inside the inner
+// angle and fading out towards the outer angle. A light with a direction but no
+// position is a directional light, like the sun:
+//
+//   - add: light
+//     at: [0, 5, 0]
+//     direction: [0, -1, 0]
+//     inner-angle: 0.3
+//     outer-angle: 0.5
+//     intensity: [1, 1, 1]
+//
+//   - add: light
+//     direction: [1, -1, 1]
+//     intensity: [0.5, 0.5, 0.5]
+//
+// Point, spot and area lights can get dimmer with the distance with "attenuation":
+// none (the default), linear, inverse-square or a list of the constant, linear and
+// quadratic coefficients, [1, 0.1, 0.01].
+//
+// The shading model of a material is selected with "shading": phong (the default),
+// blinn-phong, lambert (diffuse only), oren-nayar (rough diffuse) or
+// metallic-roughness. A material with "metallic" or "roughness" uses the physically
+// based metallic-roughness model, with the color as base color, unless it is
+// oren-nayar, where the roughness is the angle of the facets in radians. Both go from
+// 0 to 1, a material with only one of them is a dielectric (metallic 0) or has a
+// roughness of 0.5:
+//
+//   - add: sphere
+//     material:
+//       color: [1, 0.77, 0.34]
+//       metallic: 1
+//       roughness: 0.3
+//
+// A material with a "texture" takes its color from an image file relative to the scene
+// file, multiplied by the color of the material. The image is wrapped around the shape
+// with a spherical (the default), planar, cylindrical or cube mapping and is sampled
+// with nearest or bilinear (the default) filtering:
+//
+//   - add: sphere
+//     material:
+//       texture: earth.ppm
+//
+//   - add: sphere
+//     material:
+//       texture:
+//         file: dice.ppm
+//         mapping: cube
+//         filter: nearest
+//
+// The normal used for shading can be changed to add detail to the surface. "bump" adds
+// random bumps of the given size ("scale", 0.1 by default) and steepness ("strength",
+// 0.5 by default). "normal-map" reads the normals from an image in tangent space, given
+// like a texture with an optional "strength" that scales the tilt of the normals:
+//
+//   - add: sphere
+//     material:
+//       bump: { scale: 0.05, strength: 0.3 }
+//
+//   - add: sphere
+//     material:
+//       normal-map:
+//         file: tiles-normal.ppm
+//         mapping: cube
+//         strength: 0.8
+//
+// A "reflective" material, from 0 to 1, adds the color seen in the mirror direction,
+// the environment where the reflected ray misses every object:
+//
+//   - add: sphere
+//     material:
+//       reflective: 0.9
+//
+// A "transparency", from 0 to 1, adds the color seen through the surface, bent by the
+// "refractive-index" of the material (1 by default, 1.5 for glass). A material that is
+// both reflective and transparent reflects more at grazing angles:
+//
+//   - add: sphere
+//     material:
+//       reflective: 1
+//       transparency: 1
+//       refractive-index: 1.5
+//
+// The environment is the background, seen where a ray misses every object, and a light
+// that surrounds the scene when it is path traced. It is a color, a vertical gradient
+// from the bottom to the top or an equirectangular image (.hdr, .pfm or .ppm) relative
+// to the scene file:
+//
+//   - add: environment
+//     bottom: [1, 1, 1]
+//     top: [0.4, 0.6, 1]
+//
+//   - add: environment
+//     file: sky.hdr
+//
+// or a cube map, either one image in the horizontal cross layout or six images, one for
+// each face:
+//
+//   - add: environment
+//     cube-map: skybox.ppm
+//
+//   - add: environment
+//     cube-map:
+//       right: right.ppm
+//       left: left.ppm
+//       up: up.ppm
+//       down: down.ppm
+//       front: front.ppm
+//       back: back.ppm
+//
+// Materials and transformations can be given a name with "define" and then be used by
+// name. A definition can "extend" an earlier one and override some of its attributes,
+// and a material can also be extended where it is used:
+//
+//   - define: wall
+//     value:
+//       color: [1, 0.9, 0.9]
+//       specular: 0
+//
+//   - define: blue-wall
+//     extend: wall
+//     value:
+//       color: [0, 0, 1]
+//
+//   - add: sphere
+//     material:
+//       extend: blue-wall
+//       ambient: 0.2
+//
+// With "include: <file>" the commands of another file, for example a library of
+// materials, are added to the scene. The path is relative to the including file.
+
+#[derive(Debug)]
+pub struct Scene {
+    pub camera: Camera,
+    pub world: World,
+}
+
+// Errors that can occur when a scene file is loaded
+#[derive(Debug)]
+pub enum SceneError {
+    Io(io::Error),
+    Parse(ParseError),
+}
+
+impl fmt::Display for SceneError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SceneError::Io(err) => write!(f, "{}", err),
+            SceneError::Parse(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl error::Error for SceneError {}
+
+impl From<io::Error> for SceneError {
+    fn from(err: io::Error) -> SceneError {
+        SceneError::Io(err)
+    }
+}
+
+impl From<ParseError> for SceneError {
+    fn from(err: ParseError) -> SceneError {
+        SceneError::Parse(err)
+    }
+}
+
+pub fn load<P: AsRef<Path>>(path: P) -> Result<Scene, SceneError> {
+    let path = path.as_ref();
+    let text = fs::read_to_string(path)?;
+    let directory = path.parent().unwrap_or(Path::new("."));
+    return Ok(Builder::new(directory).build(&text)?);
+}
+
+// Parse a scene. Included files are relative to the current directory.
+pub fn parse(text: &str) -> Result<Scene, ParseError> {
+    return Builder::new(Path::new(".")).build(text);
+}
+
+// Included files may include other files, but not without limit, which also stops a
+// file that includes itself
+const MAX_INCLUDE_DEPTH: usize = 16;
+
+struct Builder {
+    camera: Option<Camera>,
+    world: World,
+    // The values of the defined names. References to other definitions are already
+    // resolved, so a definition can only refer to the ones before it.
+    definitions: HashMap<String, Value>,
+    // The directory that included files are relative to
+    directory: PathBuf,
+    include_depth: usize,
+}
+
+impl Builder {
+    fn new(directory: &Path) -> Builder {
+        Builder {
+            camera: None,
+            world: World::new(),
+            definitions: HashMap::new(),
+            directory: directory.to_path_buf(),
+            include_depth: 0,
+        }
+    }
+
+    fn build(mut self, text: &str) -> Result<Scene, ParseError> {
+        let document = yaml::parse(text)?;
+        self.add_commands(&document)?;
+        let Some(camera) = self.camera else {
+            return Err(document.error("the scene has no camera".to_string()));
+        };
+        return Ok(Scene {
+            camera,
+            world: self.world,
+        });
+    }
+
+    fn add_commands(&mut self, document: &Value) -> Result<(), ParseError> {
+        for command in document.as_sequence()? {
+            if let Some(add) = command.get("add") {
+                self.add(command, add)?;
+            } else if let Some(name) = command.get("define") {
+                self.define(command, name)?;
+            } else if let Some(file) = command.get("include") {
+                check_attributes(command, &["include"])?;
+                self.include(file)?;
+            } else {
+                return Err(
+                    command.error("expected an 'add', 'define' or 'include' command".to_string())
+                );
+            }
+        }
+        return Ok(());
+    }
+
+    fn add(&mut self, command: &Value, add: &Value) -> Result<(), ParseError> {
+        match add.as_str()? {
+            "camera" => {
+                if self.camera.is_some() {
+                    return Err(add.error("the scene has more than one camera".to_string()));
+                }
+                self.camera = Some(parse_camera(command)?);
+            }
+            "light" => self.world.lights.push(parse_light(command)?),
+            "environment" => {
+                if self.world.environment.is_some() {
+                    return Err(add.error("the scene has more than one environment".to_string()));
+                }
+                self.world.environment = Some(self.environment(command)?);
+            }
+            "sphere" => {
+                let sphere = self.sphere(command)?;
+                self.world.objects.push(sphere);
+            }
+            other => return Err(add.error(format!("unknown object '{}'", other))),
+        }
+        return Ok(());
+    }
+
+    // A definition gives a name to a value, typically a material or a list of
+    // transformations. With "extend" the value is based on an earlier definition: the
+    // attributes of a material are overridden and transformations are appended.
+    fn define(&mut self, command: &Value, name: &Value) -> Result<(), ParseError> {
+        check_attributes(command, &["define", "extend", "value"])?;
+        let mut value = self.resolve(required(command, "value")?)?;
+        if let Some(base) = command.get("extend") {
+            value = extend(self.lookup(base)?, &value)?;
+        }
+        if self
+            .definitions
+            .insert(name.as_str()?.to_string(), value)
+            .is_some()
+        {
+            return Err(name.error(format!("'{}' is already defined", name.as_str()?)));
+        }
+        return Ok(());
+    }
+
+    // Add the commands of another scene file, typically a library of definitions
+    fn include(&mut self, file: &Value) -> Result<(), ParseError> {
+        if self.include_depth >= MAX_INCLUDE_DEPTH {
+            return Err(file.error("too many nested includes".to_string()));
+        }
+        let path = self.directory.join(file.as_str()?);
+        let text = fs::read_to_string(&path)
+            .map_err(|err| file.error(format!("could not read {}: {}", path.display(), err)))?;
+
+        let directory = path.parent().unwrap_or(Path::new(".")).to_path_buf();
+        let directory = mem::replace(&mut self.directory, directory);
+        self.include_depth += 1;
+        let result = yaml::parse(&text).and_then(|document| self.add_commands(&document));
+        self.include_depth -= 1;
+        self.directory = directory;
+
+        // Errors in the included file are reported at the include command
+        return result.map_err(|err| file.error(format!("{}: {}", path.display(), err)));
+    }
+
+    // An environment is a color, a gradient from the bottom to the top, an image file or
+    // a cube map
+    fn environment(&self, command: &Value) -> Result<Environment, ParseError> {
+        if let Some(file) = command.get("file") {
+            check_attributes(command, &["file"])?;
+            return Ok(Environment::Map(EnvironmentMap::new(load_image(
+                &self.directory,
+                file,
+            )?)));
+        }
+        if let Some(cube) = command.get("cube-map") {
+            check_attributes(command, &["cube-map"])?;
+            return Ok(Environment::CubeMap(self.cube_map(cube)?));
+        }
+        if let Some(color) = command.get("color") {
+            check_attributes(command, &["color"])?;
+            return Ok(Environment::Constant(parse_color(color)?));
+        }
+        check_attributes(command, &["bottom", "top"])?;
+        return Ok(Environment::Gradient {
+            bottom: parse_color(required(command, "bottom")?)?,
+            top: parse_color(required(command, "top")?)?,
+        });
+    }
+
+    // A cube map is one image in the cross layout or a mapping with an image per face
+    fn cube_map(&self, cube: &Value) -> Result<CubeMap, ParseError> {
+        if let Node::Scalar(_) = cube.node {
+            return CubeMap::from_cross(&load_image(&self.directory, cube)?).ok_or_else(|| {
+                cube.error("a cube map image must be four faces wide and three high".to_string())
+            });
+        }
+        let names = ["right", "left", "up", "down", "front", "back"];
+        check_attributes(cube, &names)?;
+        let mut faces = Vec::new();
+        for name in names {
+            faces.push(load_image(&self.directory, required(cube, name)?)?);
+        }
+        let faces: [Canvas; 6] = faces.try_into().unwrap();
+        return CubeMap::new(faces)
+            .ok_or_else(|| cube.error("the faces of a cube map must not be empty".to_string()));
+    }
+
+    fn lookup(&self, name: &Value) -> Result<&Value, ParseError> {
+        let key = name.as_str()?;
+        return self
+            .definitions
+            .get(key)
+            .ok_or_else(|| name.error(format!("unknown definition '{}'", key)));
+    }
+
+    // Replace the references to definitions in a value: a name is replaced by its
+    // definition, a mapping with an "extend" attribute is merged with the definition it
+    // extends, and names in a sequence are replaced by the items of their definitions.
+    fn resolve(&self, value: &Value) -> Result<Value, ParseError> {
+        match &value.node {
+            Node::Scalar(_) => return Ok(self.lookup(value)?.clone()),
+            Node::Mapping(entries) => {
+                let Some(base) = value.get("extend") else {
+                    return Ok(value.clone());
+                };
+                let own = Node::Mapping(
+                    entries
+                        .iter()
+                        .filter(|(key, _)| key.as_str() != Ok("extend"))
+                        .cloned()
+                        .collect(),
+                );
+                let own = Value {
+                    node: own,
+                    ..value.clone()
+                };
+                return extend(self.lookup(base)?, &own);
+            }
+            Node::Sequence(items) => {
+                let mut resolved = Vec::new();
+                for item in items {
+                    if let Node::Scalar(_) = item.node {
+                        let definition = self.lookup(item)?;
+                        let Node::Sequence(definition_items) = &definition.node else {
+                            return Err(item.error(format!(
+                                "'{}' is not defined as a sequence",
+                                item.as_str()?
+                            )));
+                        };
+                        resolved.extend(definition_items.iter().cloned());
+                    } else {
+                        resolved.push(item.clone());
+                    }
+                }
+                return Ok(Value {
+                    node: Node::Sequence(resolved),
+                    ..value.clone()
+                });
+            }
+            Node::Null => return Ok(value.clone()),
+        }
+    }
+
+    fn sphere(&self, command: &Value) -> Result<Sphere, ParseError> {
+        check_attributes(command, &["material", "transform"])?;
+        let mut sphere = Sphere::new();
+        if let Some(material) = command.get("material") {
+            parse_material(
+                &self.resolve(material)?,
+                &mut sphere.material,
+                &self.directory,
+            )?;
+        }
+        if let Some(transform) = command.get("transform") {
+            sphere.transformation = parse_transform(&self.resolve(transform)?)?;
+        }
+        return Ok(sphere);
+    }
+}
+
+// Extend a definition: the entries of a mapping override the ones of the base and the
+// items of a sequence are appended to the ones of the base
+fn extend(base: &Value, value: &Value) -> Result<Value, ParseError> {
+    let node = match (&base.node, &value.node) {
+        (Node::Mapping(base_entries), Node::Mapping(entries)) => {
+            let mut merged: Vec<(Value, Value)> = base_entries
+                .iter()
+                .filter(|(key, _)| value.get(key.as_str().unwrap_or_default()).is_none())
+                .cloned()
+                .collect();
+            merged.extend(entries.iter().cloned());
+            Node::Mapping(merged)
+        }
+        (Node::Sequence(base_items), Node::Sequence(items)) => {
+            Node::Sequence(base_items.iter().chain(items).cloned().collect())
+        }
+        _ => {
+            return Err(value.error("the value does not match the extended definition".to_string()))
+        }
+    };
+    return Ok(Value {
+        node,
+        ..value.clone()
+    });
+}
+
+// Check that the command only has the given attributes, to catch misspelled names
+fn check_attributes(command: &Value, allowed: &[&str]) -> Result<(), ParseError> {
+    for (key, _) in command.as_mapping()? {
+        let name = key.as_str()?;
+        if name != "add" && !allowed.contains(&name) {
+            return Err(key.error(format!("unknown attribute '{}'", name)));
+        }
+    }
+    return Ok(());
+}
+
+fn required<'a>(command: &'a Value, name: &str) -> Result<&'a Value, ParseError> {
+    return command
+        .get(name)
+        .ok_or_else(|| command.error(format!("missing attribute '{}'", name)));
+}
+
+fn parse_numbers<const N: usize>(value: &Value) -> Result<[f64; N], ParseError> {
+    let items = value.as_sequence()?;
+    if items.len() != N {
+        return Err(value.error(format!("expected {} numbers", N)));
+    }
+    let mut numbers = [0.0; N];
+    for (number, item) in numbers.iter_mut().zip(items) {
+        *number = item.as_f64()?;
+    }
+    return Ok(numbers);
+}
+
+fn parse_point(value: &Value) -> Result<Tuple, ParseError> {
+    let [x, y, z] = parse_numbers(value)?;
+    return Ok(Tuple::point(x, y, z));
+}
+
+fn parse_vector(value: &Value) -> Result<Tuple, ParseError> {
+    let [x, y, z] = parse_numbers(value)?;
+    return Ok(Tuple::vector(x, y, z));
+}
+
+// A vector that is normalized, so it must not be zero
+fn parse_direction(value: &Value) -> Result<Tuple, ParseError> {
+    let vector = parse_vector(value)?;
+    if vector.magnitude() == 0.0 {
+        return Err(value.error("the direction must not be zero".to_string()));
+    }
+    return Ok(vector);
+}
+
+fn parse_color(value: &Value) -> Result<Color, ParseError> {
+    let [red, green, blue] = parse_numbers(value)?;
+    return Ok(Color::color(red, green, blue));
+}
+
+fn parse_camera(command: &Value) -> Result<Camera, ParseError> {
+    check_attributes(
+        command,
+        &["width", "height", "field-of-view", "from", "to", "up"],
+    )?;
+    let width = required(command, "width")?.as_usize()?;
+    let height = required(command, "height")?.as_usize()?;
+    if width == 0 || height == 0 {
+        return Err(command.error(format!("invalid camera size {}x{}", width, height)));
+    }
+    let mut camera = Camera::new(width, height, required(command, "field-of-view")?.as_f64()?);
+    let up = match command.get("up") {
+        Some(up) => parse_vector(up)?,
+        None => Tuple::vector(0., 1., 0.),
+    };
+    let from = parse_point(required(command, "from")?)?;
+    let to = parse_point(required(command, "to")?)?;
+    if from == to {
+        return Err(command.error("the camera looks from and to the same point".to_string()));
+    }
+    camera.set_view_transformation(&from, &to, &up);
+    if camera.transform.invert().is_none() {
+        return Err(command.error("'up' is in the direction the camera looks".to_string()));
+    }
+    return Ok(camera);
+}
+
+// The kind of light is given by the attributes: a point light has a position, a spot
+// light also has a direction, a directional light only has a direction and an area
+// light has a corner and two edges
+fn parse_light(command: &Value) -> Result<Light, ParseError> {
+    if command.get("corner").is_some() {
+        return parse_area_light(command);
+    }
+    match (command.get("at"), command.get("direction")) {
+        (_, None) => {
+            check_attributes(command, &["at", "intensity", "attenuation"])?;
+            let mut light = PointLight::new(
+                parse_point(required(command, "at")?)?,
+                parse_color(required(command, "intensity")?)?,
+            );
+            light.attenuation = parse_attenuation(command)?;
+            return Ok(light.into());
+        }
+        (None, Some(direction)) => {
+            check_attributes(command, &["direction", "intensity"])?;
+            return Ok(DirectionalLight::new(
+                parse_direction(direction)?,
+                parse_color(required(command, "intensity")?)?,
+            )
+            .into());
+        }
+        (Some(at), Some(direction)) => {
+            check_attributes(
+                command,
+                &[
+                    "at",
+                    "direction",
+                    "inner-angle",
+                    "outer-angle",
+                    "intensity",
+                    "attenuation",
+                ],
+            )?;
+            let inner_angle = required(command, "inner-angle")?.as_f64()?;
+            let outer = required(command, "outer-angle")?;
+            let outer_angle = outer.as_f64()?;
+            if outer_angle < inner_angle {
+                return Err(
+                    outer.error("'outer-angle' must not be smaller than 'inner-angle'".to_string())
+                );
+            }
+            let mut light = SpotLight::new(
+                parse_point(at)?,
+                parse_direction(direction)?,
+                inner_angle,
+                outer_angle,
+                parse_color(required(command, "intensity")?)?,
+            );
+            light.attenuation = parse_attenuation(command)?;
+            return Ok(light.into());
+        }
+    }
+}
+
+fn parse_area_light(command: &Value) -> Result<Light, ParseError> {
+    check_attributes(
+        command,
+        &[
+            "corner",
+            "uvec",
+            "usteps",
+            "vvec",
+            "vsteps",
+            "jitter",
+            "intensity",
+            "attenuation",
+        ],
+    )?;
+    let (usteps, vsteps) = (required(command, "usteps")?, required(command, "vsteps")?);
+    let light = AreaLight::new(
+        parse_point(required(command, "corner")?)?,
+        parse_vector(required(command, "uvec")?)?,
+        usteps.as_usize()?,
+        parse_vector(required(command, "vvec")?)?,
+        vsteps.as_usize()?,
+        parse_color(required(command, "intensity")?)?,
+    );
+    // The light is only invalid without steps in a direction
+    let Some(mut light) = light else {
+        let (value, name) = match usteps.as_usize()? {
+            0 => (usteps, "usteps"),
+            _ => (vsteps, "vsteps"),
+        };
+        return Err(value.error(format!("'{}' must be at least 1", name)));
+    };
+    if let Some(jitter) = command.get("jitter") {
+        light.jitter = jitter.as_bool()?;
+    }
+    light.attenuation = parse_attenuation(command)?;
+    return Ok(light.into());
+}
+
+// The attenuation is a name or a list of the constant, linear and quadratic
+// coefficients. Lights are not attenuated by default.
+fn parse_attenuation(command: &Value) -> Result<Attenuation, ParseError> {
+    let value = match command.get("attenuation") {
+        Some(value) => value,
+        None => return Ok(Attenuation::None),
+    };
+    if let Node::Sequence(_) = value.node {
+        let [constant, linear, quadratic] = parse_numbers(value)?;
+        if constant < 0.0 || linear < 0.0 || quadratic < 0.0 || constant + linear + quadratic == 0.0
+        {
+            return Err(
+                value.error("the coefficients must not be negative or all zero".to_string())
+            );
+        }
+        return Ok(Attenuation::Coefficients {
+            constant,
+            linear,
+            quadratic,
+        });
+    }
+    match value.as_str()? {
+        "none" => Ok(Attenuation::None),
+        "linear" => Ok(Attenuation::Linear),
+        "inverse-square" => Ok(Attenuation::InverseSquare),
+        other => Err(value.error(format!("unknown attenuation '{}'", other))),
+    }
+}
+
+// Load an image file relative to the directory of the scene file
+fn load_image(directory: &Path, file: &Value) -> Result<Canvas, ParseError> {
+    let path = directory.join(file.as_str()?);
+    return output::load(&path)
+        .map_err(|err| file.error(format!("could not read {}: {}", path.display(), err)));
+}
+
+// A texture is an image file, or a mapping with the file and optionally the mapping and
+// the filter. The default is a spherical mapping with bilinear filtering. The mapping
+// can also have the other attributes given, which are read by the caller.
+fn parse_texture(value: &Value, directory: &Path, others: &[&str]) -> Result<Texture, ParseError> {
+    if let Node::Scalar(_) = value.node {
+        return parse_texture_file(value, Mapping::Spherical, Filter::Bilinear, directory);
+    }
+    let mut allowed = vec!["file", "mapping", "filter"];
+    allowed.extend_from_slice(others);
+    check_attributes(value, &allowed)?;
+    let mut mapping = Mapping::Spherical;
+    if let Some(value) = value.get("mapping") {
+        let name = value.as_str()?;
+        mapping = Mapping::from_name(name)
+            .ok_or_else(|| value.error(format!("unknown mapping '{}'", name)))?;
+    }
+    let mut filter = Filter::Bilinear;
+    if let Some(value) = value.get("filter") {
+        let name = value.as_str()?;
+        filter = Filter::from_name(name)
+            .ok_or_else(|| value.error(format!("unknown filter '{}'", name)))?;
+    }
+    return parse_texture_file(required(value, "file")?, mapping, filter, directory);
+}
+
+fn parse_texture_file(
+    file: &Value,
+    mapping: Mapping,
+    filter: Filter,
+    directory: &Path,
+) -> Result<Texture, ParseError> {
+    return Texture::new(load_image(directory, file)?, mapping, filter)
+        .ok_or_else(|| file.error("the texture image is empty".to_string()));
+}
+
+// Set the attributes given in the scene. The other attributes keep their values.
+fn parse_material(
+    value: &Value,
+    material: &mut Material,
+    directory: &Path,
+) -> Result<(), ParseError> {
+    for (key, attribute) in value.as_mapping()? {
+        match key.as_str()? {
+            "color" => material.color = parse_color(attribute)?,
+            "ambient" => material.ambient = attribute.as_f64()?,
+            "diffuse" => material.diffuse = attribute.as_f64()?,
+            "specular" => material.specular = attribute.as_f64()?,
+            "shininess" => material.shininess = attribute.as_f64()?,
+            "emission" => material.emission = parse_color(attribute)?,
+            "reflective" => material.reflective = attribute.as_f64()?,
+            "transparency" => material.transparency = attribute.as_f64()?,
+            "refractive-index" => material.refractive_index = attribute.as_f64()?,
+            "texture" => material.texture = Some(parse_texture(attribute, directory, &[])?),
+            "bump" => {
+                check_attributes(attribute, &["scale", "strength"])?;
+                let scale = match attribute.get("scale") {
+                    Some(scale) => scale.as_f64()?,
+                    None => Bump::DEFAULT_NOISE_SCALE,
+                };
+                if scale <= 0.0 {
+                    return Err(
+                        attribute.error("the scale of the bumps must be positive".to_string())
+                    );
+                }
+                let strength = match attribute.get("strength") {
+                    Some(strength) => strength.as_f64()?,
+                    None => Bump::DEFAULT_NOISE_STRENGTH,
+                };
+                material.bump = Some(Bump::Noise { scale, strength });
+            }
+            "normal-map" => {
+                let texture = parse_texture(attribute, directory, &["strength"])?;
+                let strength = match attribute.get("strength") {
+                    Some(strength) => strength.as_f64()?,
+                    None => 1.0,
+                };
+                material.bump = Some(Bump::NormalMap { texture, strength });
+            }
+            name @ ("metallic" | "roughness") => {
+                let value = attribute.as_f64()?;
+                if !(0.0..=1.0).contains(&value) {
+                    return Err(attribute.error(format!("'{}' must be between 0 and 1", name)));
+                }
+                // The roughness of Oren-Nayar is kept, any other model becomes
+                // metallic-roughness
+                let model = material.model;
+                let roughness = model.roughness().unwrap_or(ShadingModel::DEFAULT_ROUGHNESS);
+                material.model = match (name, model) {
+                    ("roughness", ShadingModel::OrenNayar { .. }) => {
+                        ShadingModel::OrenNayar { roughness: value }
+                    }
+                    ("roughness", ShadingModel::MetallicRoughness { metallic, .. }) => {
+                        ShadingModel::MetallicRoughness {
+                            metallic,
+                            roughness: value,
+                        }
+                    }
+                    ("roughness", _) => ShadingModel::MetallicRoughness {
+                        metallic: 0.0,
+                        roughness: value,
+                    },
+                    _ => ShadingModel::MetallicRoughness {
+                        metallic: value,
+                        roughness,
+                    },
+                };
+            }
+            "shading" => {
+                let name = attribute.as_str()?;
+                let roughness = material
+                    .model
+                    .roughness()
+                    .unwrap_or(ShadingModel::DEFAULT_ROUGHNESS);
+                let model = ShadingModel::from_name(name, roughness)
+                    .ok_or_else(|| attribute.error(format!("unknown shading model '{}'", name)))?;
+                // A metallic-roughness material keeps how metallic it is
+                if !matches!(
+                    (model, material.model),
+                    (
+                        ShadingModel::MetallicRoughness { .. },
+                        ShadingModel::MetallicRoughness { .. }
+                    )
+                ) {
+                    material.model = model;
+                }
+            }
+            other => return Err(key.error(format!("unknown material attribute '{}'", other))),
+        }
+    }
+    return Ok(());
+}
+
+// A transform is a list of operations, each written as a sequence with the name of the
+// operation followed by its arguments
+fn parse_transform(value: &Value) -> Result<Matrix<4, 4>, ParseError> {
+    let mut transform = Matrix::new_identity();
+    for operation in value.as_sequence()? {
+        let items = operation.as_sequence()?;
+        let Some((name, arguments)) = items.split_first() else {
+            return Err(operation.error("empty transformation".to_string()));
+        };
+        let arguments = arguments
+            .iter()
+            .map(|argument| argument.as_f64())
+            .collect::<Result<Vec<f64>, ParseError>>()?;
+        let expected = match name.as_str()? {
+            "translate" | "scale" => 3,
+            "rotate-x" | "rotate-y" | "rotate-z" => 1,
+            "shear" => 6,
+            other => return Err(name.error(format!("unknown transformation '{}'", other))),
+        };
+        if arguments.len() != expected {
+            return Err(operation.error(format!(
+                "'{}' takes {} arguments",
+                name.as_str()?,
+                expected
+            )));
+        }
+        let a = &arguments;
+        transform = match name.as_str()? {
+            "translate" => transform.translate(a[0], a[1], a[2]),
+            "scale" => transform.scale(a[0], a[1], a[2]),
+            "rotate-x" => transform.rotate_x(a[0]),
+            "rotate-y" => transform.rotate_y(a[0]),
+            "rotate-z" => transform.rotate_z(a[0]),
+            _ => transform.shear(a[0], a[1], a[2], a[3], a[4], a[5]),
+        };
+    }
+    // Rays are moved into object space with the inverse, so a transform that squashes
+    // the shape flat, like a scale by 0, cannot be rendered
+    if transform.invert().is_none() {
+        return Err(value.error("the transformation cannot be inverted".to_string()));
+    }
+    return Ok(transform);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rays::Ray;
+    use std::f64;
+
+    const SCENE: &str = "
+- add: camera
+  width: 100
+  height: 50
+  field-of-view: 1.0471975512
+  from: [0, 1.5, -5]
+  to: [0, 1, 0]
+  up: [0, 1, 0]
+
+- add: light
+  at: [-10, 10, -10]
+  intensity: [1, 1, 1]
+
+- add: sphere
+  material:
+    color: [0.1, 1, 0.5]
+    diffuse: 0.7
+    specular: 0.3
+  transform:
+    - [scale, 0.5, 0.5, 0.5]
+    - [translate, 1.5, 0.5, -0.5]
+";
+
+    const CAMERA: &str =
+        "- add: camera\n  width: 10\n  height: 10\n  field-of-view: 1\n  from: [0, 0, -5]\n  to: [0, 0, 0]\n";
+
+    // A directory for the files of a test, removed when it goes out of scope, also when
+    // an assertion fails
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new(name: &str) -> TempDir {
+            let path = std::env::temp_dir().join(format!("scene-{}-{}", name, std::process::id()));
+            fs::create_dir_all(&path).unwrap();
+            return TempDir(path);
+        }
+
+        fn join<P: AsRef<Path>>(&self, path: P) -> PathBuf {
+            return self.0.join(path);
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    #[test]
+    fn parse_scene() {
+        let scene = parse(SCENE).unwrap();
+        assert_eq!(scene.camera.hsize, 100);
+        assert_eq!(scene.camera.vsize, 50);
+        assert!((scene.camera.field_of_view - f64::consts::PI / 3.).abs() < 0.00001);
+        let mut camera = Camera::new(100, 50, f64::consts::PI / 3.);
+        camera.set_view_transformation(
+            &Tuple::point(0., 1.5, -5.),
+            &Tuple::point(0., 1., 0.),
+            &Tuple::vector(0., 1., 0.),
+        );
+        assert_eq!(scene.camera.transform, camera.transform);
+
+        assert_eq!(
+            scene.world.lights,
+            vec![PointLight::new(Tuple::point(-10., 10., -10.), Color::color(1., 1., 1.)).into()]
+        );
+
+        let mut sphere = Sphere::new();
+        sphere.material.color = Color::color(0.1, 1., 0.5);
+        sphere.material.diffuse = 0.7;
+        sphere.material.specular = 0.3;
+        sphere.transformation = Matrix::new_identity()
+            .scale(0.5, 0.5, 0.5)
+            .translate(1.5, 0.5, -0.5);
+        assert_eq!(scene.world.objects, vec![sphere]);
+    }
+
+    #[test]
+    fn all_transformations() {
+        let value =
+            yaml::parse("[[rotate-x, 1], [rotate-y, 2], [rotate-z, 3], [shear, 1, 0, 0, 0, 0, 1]]")
+                .unwrap();
+        assert_eq!(
+            parse_transform(&value).unwrap(),
+            Matrix::new_identity()
+                .rotate_x(1.)
+                .rotate_y(2.)
+                .rotate_z(3.)
+                .shear(1., 0., 0., 0., 0., 1.)
+        );
+    }
+
+    fn error_at(text: &str) -> (usize, usize, String) {
+        let error = parse(text).unwrap_err();
+        return (error.line, error.column, error.message);
+    }
+
+    #[test]
+    fn parse_area_light() {
+        let scene = parse(&format!(
+            "{}- add: light\n  corner: [-1, 2, 4]\n  uvec: [2, 0, 0]\n  usteps: 4\n  vvec: [0, 2, 0]\n  vsteps: 2\n  jitter: false\n  intensity: [1.5, 1.5, 1.5]\n",
+            CAMERA
+        ))
+        .unwrap();
+        let mut light = AreaLight::new(
+            Tuple::point(-1., 2., 4.),
+            Tuple::vector(2., 0., 0.),
+            4,
+            Tuple::vector(0., 2., 0.),
+            2,
+            Color::color(1.5, 1.5, 1.5),
+        )
+        .unwrap();
+        light.jitter = false;
+        assert_eq!(scene.world.lights, vec![light.into()]);
+
+        let light = "- add: light\n  corner: [-1, 2, 4]\n  uvec: [2, 0, 0]\n  vvec: [0, 2, 0]\n  intensity: [1, 1, 1]\n";
+        assert_eq!(
+            error_at(&format!("{}  usteps: 0\n  vsteps: 2\n", light)),
+            (6, 11, "'usteps' must be at least 1".to_string())
+        );
+        assert_eq!(
+            error_at(&format!("{}  usteps: 3\n  vsteps: 0\n", light)),
+            (7, 11, "'vsteps' must be at least 1".to_string())
+        );
+        assert_eq!(
+            error_at(&format!(
+                "{}  usteps: 1\n  vsteps: 1\n  jitter: yes\n",
+                light
+            )),
+            (8, 11, "expected true or false".to_string())
+        );
+        assert_eq!(
+            error_at(&format!(
+                "{}  usteps: 1\n  vsteps: 1\n  at: [0, 0, 0]\n",
+                light
+            )),
+            (8, 3, "unknown attribute 'at'".to_string())
+        );
+    }
+
+    #[test]
+    fn parse_spot_and_directional_lights() {
+        let scene = parse(&format!(
+            "{}{}{}",
+            CAMERA,
+            "- add: light\n  at: [0, 5, 0]\n  direction: [0, -2, 0]\n  inner-angle: 0.3\n  outer-angle: 0.5\n  intensity: [1, 1, 1]\n",
+            "- add: light\n  direction: [1, -1, 1]\n  intensity: [0.5, 0.5, 0.5]\n",
+        ))
+        .unwrap();
+        assert_eq!(
+            scene.world.lights,
+            vec![
+                SpotLight::new(
+                    Tuple::point(0., 5., 0.),
+                    Tuple::vector(0., -1., 0.),
+                    0.3,
+                    0.5,
+                    Color::color(1., 1., 1.)
+                )
+                .into(),
+                DirectionalLight::new(Tuple::vector(1., -1., 1.), Color::color(0.5, 0.5, 0.5))
+                    .into()
+            ]
+        );
+
+        assert_eq!(
+            error_at("- add: light\n  direction: [0, 0, 0]\n  intensity: [1, 1, 1]\n"),
+            (2, 14, "the direction must not be zero".to_string())
+        );
+        assert_eq!(
+            error_at("- add: light\n  at: [0, 0, 0]\n  direction: [0, 1, 0]\n  inner-angle: 0.5\n  outer-angle: 0.3\n  intensity: [1, 1, 1]\n"),
+            (5, 16, "'outer-angle' must not be smaller than 'inner-angle'".to_string())
+        );
+        assert_eq!(
+            error_at("- add: light\n  direction: [0, 1, 0]\n  inner-angle: 0.5\n  intensity: [1, 1, 1]\n"),
+            (3, 3, "unknown attribute 'inner-angle'".to_string())
+        );
+    }
+
+    #[test]
+    fn parse_light_attenuation() {
+        let light = "- add: light\n  at: [0, 5, 0]\n  intensity: [1, 1, 1]\n";
+        for (attenuation, expected) in [
+            ("none", Attenuation::None),
+            ("linear", Attenuation::Linear),
+            ("inverse-square", Attenuation::InverseSquare),
+            (
+                "[1, 0.1, 0.01]",
+                Attenuation::Coefficients {
+                    constant: 1.,
+                    linear: 0.1,
+                    quadratic: 0.01,
+                },
+            ),
+        ] {
+            let text = format!("{}{}  attenuation: {}\n", CAMERA, light, attenuation);
+            match &parse(&text).unwrap().world.lights[0] {
+                Light::Point(light) => assert_eq!(light.attenuation, expected),
+                other => panic!("expected a point light, got {:?}", other),
+            }
+        }
+
+        assert_eq!(
+            error_at(&format!("{}  attenuation: cubic\n", light)),
+            (4, 16, "unknown attenuation 'cubic'".to_string())
+        );
+        assert_eq!(
+            error_at(&format!("{}  attenuation: [0, 0, 0]\n", light)),
+            (
+                4,
+                16,
+                "the coefficients must not be negative or all zero".to_string()
+            )
+        );
+        assert_eq!(
+            error_at("- add: light\n  direction: [0, 1, 0]\n  intensity: [1, 1, 1]\n  attenuation: linear\n"),
+            (4, 3, "unknown attribute 'attenuation'".to_string())
+        );
+    }
+
+    #[test]
+    fn parse_emissive_material() {
+        let scene = parse(&format!(
+            "{}- add: sphere\n  material:\n    emission: [2, 1.5, 1]\n",
+            CAMERA
+        ))
+        .unwrap();
+        let material = &scene.world.objects[0].material;
+        assert_eq!(material.emission, Color::color(2., 1.5, 1.));
+        assert!(material.is_emissive());
+        assert!(!Material::new().is_emissive());
+    }
+
+    #[test]
+    fn parse_reflective_material() {
+        let scene = parse(&format!(
+            "{}- add: sphere\n  material:\n    reflective: 0.8\n",
+            CAMERA
+        ))
+        .unwrap();
+        assert_eq!(scene.world.objects[0].material.reflective, 0.8);
+    }
+
+    #[test]
+    fn parse_transparent_material() {
+        let scene = parse(&format!(
+            "{}- add: sphere\n  material:\n    transparency: 0.9\n    refractive-index: 1.5\n",
+            CAMERA
+        ))
+        .unwrap();
+        let material = &scene.world.objects[0].material;
+        assert_eq!(material.transparency, 0.9);
+        assert_eq!(material.refractive_index, 1.5);
+    }
+
+    #[test]
+    fn parse_metallic_roughness_material() {
+        let scene = parse(&format!(
+            "{}- add: sphere\n  material:\n    metallic: 1\n    roughness: 0.25\n- add: sphere\n  material:\n    roughness: 0.8\n- add: sphere\n",
+            CAMERA
+        ))
+        .unwrap();
+        let models: Vec<ShadingModel> = scene
+            .world
+            .objects
+            .iter()
+            .map(|sphere| sphere.material.model)
+            .collect();
+        assert_eq!(
+            models,
+            vec![
+                ShadingModel::MetallicRoughness {
+                    metallic: 1.,
+                    roughness: 0.25
+                },
+                ShadingModel::MetallicRoughness {
+                    metallic: 0.,
+                    roughness: 0.8
+                },
+                ShadingModel::Phong,
+            ]
+        );
+        assert_eq!(
+            error_at(&format!(
+                "{}- add: sphere\n  material:\n    metallic: 2\n",
+                CAMERA
+            )),
+            (9, 15, "'metallic' must be between 0 and 1".to_string())
+        );
+    }
+
+    #[test]
+    fn parse_shading_models() {
+        let text = format!(
+            "{}{}{}{}{}",
+            CAMERA,
+            "- add: sphere\n  material:\n    shading: blinn-phong\n",
+            "- add: sphere\n  material:\n    shading: oren-nayar\n    roughness: 0.3\n",
+            "- add: sphere\n  material:\n    metallic: 1\n    shading: metallic-roughness\n",
+            "- add: sphere\n  material:\n    roughness: 0.2\n    shading: lambert\n"
+        );
+        let scene = parse(&text).unwrap();
+        let models: Vec<ShadingModel> = scene
+            .world
+            .objects
+            .iter()
+            .map(|sphere| sphere.material.model)
+            .collect();
+        assert_eq!(
+            models,
+            vec![
+                ShadingModel::BlinnPhong,
+                ShadingModel::OrenNayar { roughness: 0.3 },
+                ShadingModel::MetallicRoughness {
+                    metallic: 1.,
+                    roughness: 0.5
+                },
+                ShadingModel::Lambert,
+            ]
+        );
+        assert_eq!(
+            error_at(&format!(
+                "{}- add: sphere\n  material:\n    shading: toon\n",
+                CAMERA
+            )),
+            (9, 14, "unknown shading model 'toon'".to_string())
+        );
+    }
+
+    #[test]
+    fn scene_errors_point_at_the_problem() {
+        assert!(parse(CAMERA).is_ok());
+        // Characters of more than one byte are reported, not a crash
+        assert!(parse("- add: camera\n  name: café\n").is_err());
+
+        assert_eq!(
+            error_at(&format!("{}- add: cube\n", CAMERA)),
+            (7, 8, "unknown object 'cube'".to_string())
+        );
+        assert_eq!(
+            error_at(&format!("{}- add: light\n  at: [1, 2]\n", CAMERA)),
+            (8, 7, "expected 3 numbers".to_string())
+        );
+        assert_eq!(
+            error_at(&format!(
+                "{}- add: sphere\n  material:\n    colour: [1, 0, 0]\n",
+                CAMERA
+            )),
+            (9, 5, "unknown material attribute 'colour'".to_string())
+        );
+        assert_eq!(
+            error_at(&format!(
+                "{}- add: sphere\n  transform:\n    - [scale, 1, x, 1]\n",
+                CAMERA
+            )),
+            (9, 18, "expected a number".to_string())
+        );
+        assert_eq!(
+            error_at(&format!(
+                "{}- add: sphere\n  transform:\n    - [translate, 1]\n",
+                CAMERA
+            )),
+            (9, 7, "'translate' takes 3 arguments".to_string())
+        );
+        assert_eq!(
+            error_at(&format!(
+                "{}- add: sphere\n  transform:\n    - [scale, 0, 1, 1]\n",
+                CAMERA
+            )),
+            (9, 5, "the transformation cannot be inverted".to_string())
+        );
+        assert_eq!(
+            error_at(&format!(
+                "{}- add: sphere\n  transform: [[rotate-x, 1], [scale, 1, 1, 0]]\n",
+                CAMERA
+            )),
+            (8, 14, "the transformation cannot be inverted".to_string())
+        );
+        assert_eq!(
+            error_at(
+                "- add: camera\n  width: 10\n  height: 10\n  field-of-view: 1\n  from: [0, 0, -5]\n  to: [0, 0, -5]\n"
+            ),
+            (1, 3, "the camera looks from and to the same point".to_string())
+        );
+        assert_eq!(
+            error_at(&format!("{}  up: [0, 0, 1]\n", CAMERA)),
+            (
+                1,
+                3,
+                "'up' is in the direction the camera looks".to_string()
+            )
+        );
+        assert_eq!(
+            error_at(
+                "- add: camera\n  width: 10\n  height: 10\n  from: [0, 0, -5]\n  to: [0, 0, 0]\n"
+            ),
+            (1, 3, "missing attribute 'field-of-view'".to_string())
+        );
+        assert_eq!(
+            error_at("- add: light\n  at: [1, 2, 3]\n  intensity: [1, 1, 1]\n"),
+            (1, 1, "the scene has no camera".to_string())
+        );
+        assert_eq!(
+            error_at("- add: light\n  at: [1, 2, 3]\n  brightness: 1\n"),
+            (3, 3, "unknown attribute 'brightness'".to_string())
+        );
+    }
+
+    #[test]
+    fn defined_materials_can_be_extended() {
+        let text = format!(
+            "{}
+- define: wall
+  value:
+    color: [1, 0.9, 0.9]
+    specular: 0
+- define: blue-wall
+  extend: wall
+  value:
+    color: [0, 0, 1]
+- add: sphere
+  material: wall
+- add: sphere
+  material: blue-wall
+- add: sphere
+  material:
+    extend: blue-wall
+    ambient: 0.5
+",
+            CAMERA
+        );
+        let scene = parse(&text).unwrap();
+        let materials: Vec<&Material> = scene
+            .world
+            .objects
+            .iter()
+            .map(|sphere| &sphere.material)
+            .collect();
+        let mut wall = Material::new();
+        wall.color = Color::color(1., 0.9, 0.9);
+        wall.specular = 0.;
+        assert_eq!(materials[0], &wall);
+        let mut blue_wall = wall.clone();
+        blue_wall.color = Color::color(0., 0., 1.);
+        assert_eq!(materials[1], &blue_wall);
+        blue_wall.ambient = 0.5;
+        assert_eq!(materials[2], &blue_wall);
+    }
+
+    #[test]
+    fn defined_transformations_are_expanded() {
+        let text = format!(
+            "{}
+- define: standard-transform
+  value:
+    - [translate, 1, -1, 1]
+    - [scale, 0.5, 0.5, 0.5]
+- define: large-object
+  value:
+    - standard-transform
+    - [scale, 3.5, 3.5, 3.5]
+- define: moved-large-object
+  extend: large-object
+  value:
+    - [rotate-y, 1]
+- add: sphere
+  transform:
+    - moved-large-object
+    - [translate, 8.5, 1.5, -0.5]
+",
+            CAMERA
+        );
+        let scene = parse(&text).unwrap();
+        assert_eq!(
+            scene.world.objects[0].transformation,
+            Matrix::new_identity()
+                .translate(1., -1., 1.)
+                .scale(0.5, 0.5, 0.5)
+                .scale(3.5, 3.5, 3.5)
+                .rotate_y(1.)
+                .translate(8.5, 1.5, -0.5)
+        );
+    }
+
+    #[test]
+    fn definition_errors() {
+        assert_eq!(
+            error_at(&format!("{}- add: sphere\n  material: metal\n", CAMERA)),
+            (8, 13, "unknown definition 'metal'".to_string())
+        );
+        assert_eq!(
+            error_at(&format!(
+                "{}- define: a\n  value: {{ diffuse: 1 }}\n- define: a\n  value: {{ diffuse: 2 }}\n",
+                CAMERA
+            )),
+            (9, 11, "'a' is already defined".to_string())
+        );
+        assert_eq!(
+            error_at(&format!(
+                "{}- define: a\n  value: {{ diffuse: 1 }}\n- add: sphere\n  transform: [a]\n",
+                CAMERA
+            )),
+            (10, 15, "'a' is not defined as a sequence".to_string())
+        );
+        // Errors in a definition are found where it is used, but point at the definition
+        assert_eq!(
+            error_at(&format!(
+                "{}- define: a\n  value: {{ diffuse: x }}\n- add: sphere\n  material: a\n",
+                CAMERA
+            )),
+            (8, 21, "expected a number".to_string())
+        );
+    }
+
+    #[test]
+    fn include_definitions_from_another_file() {
+        let directory = TempDir::new("include");
+        fs::create_dir_all(directory.join("lib")).unwrap();
+        // The included file includes a file relative to its own directory
+        fs::write(
+            directory.join("lib/materials.yml"),
+            "- include: base.yml\n- define: red\n  extend: base\n  value:\n    color: [1, 0, 0]\n",
+        )
+        .unwrap();
+        fs::write(
+            directory.join("lib/base.yml"),
+            "- define: base\n  value:\n    diffuse: 0.5\n",
+        )
+        .unwrap();
+        fs::write(
+            directory.join("scene.yml"),
+            format!(
+                "{}- include: lib/materials.yml\n- add: sphere\n  material: red\n",
+                CAMERA
+            ),
+        )
+        .unwrap();
+        fs::write(directory.join("bad.yml"), "- define: x\n  valeu: 1\n").unwrap();
+        fs::write(directory.join("loop.yml"), "- include: loop.yml\n").unwrap();
+
+        let scene = load(directory.join("scene.yml"));
+        let bad = parse(&format!(
+            "{}- include: {}\n",
+            CAMERA,
+            directory.join("bad.yml").display()
+        ));
+        let endless = load(directory.join("loop.yml"));
+
+        let material = &scene.unwrap().world.objects[0].material;
+        assert_eq!(material.color, Color::color(1., 0., 0.));
+        assert_eq!(material.diffuse, 0.5);
+
+        // Errors in an included file are reported at the include command
+        let error = bad.unwrap_err();
+        assert_eq!((error.line, error.column), (7, 12));
+        assert!(error
+            .message
+            .ends_with("bad.yml: line 2, column 3: unknown attribute 'valeu'"));
+
+        let Err(SceneError::Parse(error)) = endless else {
+            panic!("expected a parse error");
+        };
+        assert!(error.message.ends_with("too many nested includes"));
+    }
+
+    #[test]
+    fn parse_environments() {
+        let color = parse(&format!(
+            "{}- add: environment\n  color: [0.1, 0.2, 0.3]\n",
+            CAMERA
+        ))
+        .unwrap();
+        assert_eq!(
+            color.world.color_at(&Ray::new(
+                Tuple::point(0., 0., 0.),
+                Tuple::vector(0., 0., 1.)
+            )),
+            Color::color(0.1, 0.2, 0.3)
+        );
+        let gradient = parse(&format!(
+            "{}- add: environment\n  bottom: [0, 0, 0]\n  top: [1, 1, 1]\n",
+            CAMERA
+        ))
+        .unwrap();
+        assert_eq!(
+            gradient.world.color_at(&Ray::new(
+                Tuple::point(0., 0., 0.),
+                Tuple::vector(0., 1., 0.)
+            )),
+            Color::color(1., 1., 1.)
+        );
+
+        let directory = TempDir::new("environment");
+        let mut image = Canvas::new(2, 1);
+        image.write_pixel(1, 0, Color::color(4., 2., 1.));
+        output::save(&image, directory.join("sky.pfm")).unwrap();
+        fs::write(
+            directory.join("scene.yml"),
+            format!("{}- add: environment\n  file: sky.pfm\n", CAMERA),
+        )
+        .unwrap();
+        let map = load(directory.join("scene.yml"));
+        let Some(Environment::Map(map)) = map.unwrap().world.environment else {
+            panic!("expected an environment map");
+        };
+        assert_eq!(map.image().read_pixel(1, 0), Some(Color::color(4., 2., 1.)));
+
+        assert_eq!(
+            error_at(&format!(
+                "{}- add: environment\n  color: [1, 1, 1]\n- add: environment\n  color: [1, 1, 1]\n",
+                CAMERA
+            )),
+            (9, 8, "the scene has more than one environment".to_string())
+        );
+        assert!(
+            error_at(&format!("{}- add: environment\n  file: none.hdr\n", CAMERA))
+                .2
+                .starts_with("could not read")
+        );
+    }
+
+    #[test]
+    fn parse_cube_maps() {
+        let directory = TempDir::new("cube-map");
+        let mut cross = Canvas::new(8, 6);
+        // The back face
+        for (x, y) in [(6, 2), (7, 2), (6, 3), (7, 3)] {
+            cross.write_pixel(x, y, Color::color(0., 0., 1.));
+        }
+        output::save(&cross, directory.join("cross.pfm")).unwrap();
+        let names = ["right", "left", "up", "down", "front", "back"];
+        for (index, name) in names.iter().enumerate() {
+            let mut face = Canvas::new(1, 1);
+            face.write_pixel(0, 0, Color::color(index as f64, 0., 0.));
+            output::save(&face, directory.join(format!("{}.pfm", name))).unwrap();
+        }
+        let faces: String = names
+            .iter()
+            .map(|name| format!("    {}: {}.pfm\n", name, name))
+            .collect();
+        fs::write(
+            directory.join("cross.yml"),
+            format!("{}- add: environment\n  cube-map: cross.pfm\n", CAMERA),
+        )
+        .unwrap();
+        fs::write(
+            directory.join("faces.yml"),
+            format!("{}- add: environment\n  cube-map:\n{}", CAMERA, faces),
+        )
+        .unwrap();
+        fs::write(
+            directory.join("missing.yml"),
+            format!(
+                "{}- add: environment\n  cube-map:\n    right: right.pfm\n",
+                CAMERA
+            ),
+        )
+        .unwrap();
+        fs::write(
+            directory.join("wrong.yml"),
+            format!("{}- add: environment\n  cube-map: right.pfm\n", CAMERA),
+        )
+        .unwrap();
+        let cross = load(directory.join("cross.yml"));
+        let faces = load(directory.join("faces.yml"));
+        let missing = load(directory.join("missing.yml"));
+        let wrong = load(directory.join("wrong.yml"));
+
+        let back = Ray::new(Tuple::point(0., 0., 0.), Tuple::vector(0., 0., -1.));
+        assert_eq!(
+            cross.unwrap().world.color_at(&back),
+            Color::color(0., 0., 1.)
+        );
+        let world = faces.unwrap().world;
+        assert_eq!(world.color_at(&back), Color::color(5., 0., 0.));
+        let up = Ray::new(Tuple::point(0., 0., 0.), Tuple::vector(0., 1., 0.));
+        assert_eq!(world.color_at(&up), Color::color(2., 0., 0.));
+        assert_eq!(
+            missing.unwrap_err().to_string(),
+            "line 9, column 5: missing attribute 'left'"
+        );
+        assert!(wrong
+            .unwrap_err()
+            .to_string()
+            .ends_with("a cube map image must be four faces wide and three high"));
+    }
+
+    #[test]
+    fn parse_textures() {
+        let directory = TempDir::new("texture");
+        let mut image = Canvas::new(2, 1);
+        image.write_pixel(1, 0, Color::color(0., 1., 0.));
+        output::save(&image, directory.join("image.pfm")).unwrap();
+        let scenes = [
+            "  material:\n    texture: image.pfm\n",
+            "  material:\n    color: [0.5, 0.5, 0.5]\n    texture:\n      file: image.pfm\n      mapping: planar\n      filter: nearest\n",
+            "  material:\n    texture:\n      file: image.pfm\n      mapping: conical\n",
+            "  material:\n    texture:\n      mapping: cube\n",
+        ];
+        for (index, sphere) in scenes.iter().enumerate() {
+            fs::write(
+                directory.join(format!("{}.yml", index)),
+                format!("{}- add: sphere\n{}", CAMERA, sphere),
+            )
+            .unwrap();
+        }
+        let scenes: Vec<_> = (0..scenes.len())
+            .map(|index| load(directory.join(format!("{}.yml", index))))
+            .collect();
+        let mut scenes = scenes.into_iter();
+
+        let material = &scenes.next().unwrap().unwrap().world.objects[0].material;
+        let texture = material.texture.as_ref().unwrap();
+        assert_eq!(texture.mapping, Mapping::Spherical);
+        assert_eq!(texture.filter, Filter::Bilinear);
+        assert_eq!(
+            texture.image().read_pixel(1, 0),
+            Some(Color::color(0., 1., 0.))
+        );
+
+        let material = &scenes.next().unwrap().unwrap().world.objects[0].material;
+        let texture = material.texture.as_ref().unwrap();
+        assert_eq!(texture.mapping, Mapping::Planar);
+        assert_eq!(texture.filter, Filter::Nearest);
+        assert_eq!(
+            material.at(&Tuple::point(0.75, 0., 0.)).color,
+            Color::color(0., 0.5, 0.)
+        );
+
+        assert_eq!(
+            scenes.next().unwrap().unwrap_err().to_string(),
+            "line 11, column 16: unknown mapping 'conical'"
+        );
+        assert_eq!(
+            scenes.next().unwrap().unwrap_err().to_string(),
+            "line 10, column 7: missing attribute 'file'"
+        );
+    }
+
+    #[test]
+    fn parse_bumps() {
+        let noise = parse(&format!(
+            "{}- add: sphere\n  material:\n    bump: {{}}\n- add: sphere\n  material:\n    bump: {{ scale: 0.5, strength: 2 }}\n",
+            CAMERA
+        ))
+        .unwrap();
+        assert_eq!(
+            noise.world.objects[0].material.bump,
+            Some(Bump::Noise {
+                scale: Bump::DEFAULT_NOISE_SCALE,
+                strength: Bump::DEFAULT_NOISE_STRENGTH
+            })
+        );
+        assert_eq!(
+            noise.world.objects[1].material.bump,
+            Some(Bump::Noise {
+                scale: 0.5,
+                strength: 2.
+            })
+        );
+        assert_eq!(
+            error_at(&format!(
+                "{}- add: sphere\n  material:\n    bump: {{ scale: 0 }}\n",
+                CAMERA
+            )),
+            (9, 11, "the scale of the bumps must be positive".to_string())
+        );
+
+        let directory = TempDir::new("normal-map");
+        let mut image = Canvas::new(1, 1);
+        image.write_pixel(0, 0, Color::color(0.5, 0.5, 1.));
+        output::save(&image, directory.join("normals.pfm")).unwrap();
+        fs::write(
+            directory.join("scene.yml"),
+            format!(
+                "{}- add: sphere\n  material:\n    normal-map:\n      file: normals.pfm\n      mapping: cube\n      strength: 0.5\n",
+                CAMERA
+            ),
+        )
+        .unwrap();
+        let scene = load(directory.join("scene.yml"));
+        let Some(Bump::NormalMap { texture, strength }) =
+            &scene.unwrap().world.objects[0].material.bump
+        else {
+            panic!("expected a normal map");
+        };
+        assert_eq!(*strength, 0.5);
+        assert_eq!(texture.mapping, Mapping::Cube);
+        assert_eq!(
+            texture.image().read_pixel(0, 0),
+            Some(Color::color(0.5, 0.5, 1.))
+        );
+    }
+
+    #[test]
+    fn example_scene_file() {
+        let scene = load("scenes/world.yml").unwrap();
+        assert_eq!(scene.camera.hsize, 600);
+        assert_eq!(scene.world.lights.len(), 1);
+        assert_eq!(scene.world.objects.len(), 6);
+    }
+}
@@ -1,21 +1,107 @@
+use crate::bump::Bump;
 use crate::color::Color;
 use crate::matrices::{to_matrix, to_tuple, Matrix};
-use crate::tuple::Tuple;
+use crate::texture::{Mapping, Texture};
+use crate::tuple::{dot, Tuple};
+use std::borrow::Cow;
 
 pub trait Normal {
     fn normal_at(&self, point: &Tuple) -> Tuple;
+
+    // A unit vector perpendicular to the normal at a point, in the direction in which u
+    // of the texture mapping increases. With the normal it gives the tangent space of
+    // normal maps.
+    fn tangent_at(&self, point: &Tuple, mapping: Mapping) -> Tuple;
 }
 
-#[derive(Debug, PartialEq)]
+// How the light that reaches a surface is reflected
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ShadingModel {
+    // The Phong reflection model with the ambient, diffuse, specular and shininess
+    // attributes of the material
+    Phong,
+    // Like Phong, but the highlight depends on the angle between the normal and the
+    // half vector between the light and the eye. The highlights are larger for the
+    // same shininess, about four times the shininess gives the Phong highlight.
+    BlinnPhong,
+    // Only the diffuse reflection of Phong, without highlights
+    Lambert,
+    // Diffuse reflection of a rough surface like clay or cloth, which is brighter
+    // towards the light and flatter than Lambert. The roughness is the standard
+    // deviation of the angle of the facets in radians, 0.0 is Lambert.
+    OrenNayar { roughness: f64 },
+    // A physically based model where the color is the base color. A metallic surface
+    // (1.0) reflects only specular light tinted by the color, a dielectric (0.0) has
+    // a diffuse color and a white highlight. The roughness goes from mirror like
+    // (0.0) to fully rough (1.0).
+    MetallicRoughness { metallic: f64, roughness: f64 },
+}
+
+impl ShadingModel {
+    pub const DEFAULT_ROUGHNESS: f64 = 0.5;
+
+    // Select the model by name: phong, blinn-phong, lambert, oren-nayar or
+    // metallic-roughness. The roughness is used by the models that have one.
+    pub fn from_name(name: &str, roughness: f64) -> Option<ShadingModel> {
+        match name {
+            "phong" => Some(ShadingModel::Phong),
+            "blinn-phong" => Some(ShadingModel::BlinnPhong),
+            "lambert" => Some(ShadingModel::Lambert),
+            "oren-nayar" => Some(ShadingModel::OrenNayar { roughness }),
+            "metallic-roughness" => Some(ShadingModel::MetallicRoughness {
+                metallic: 0.0,
+                roughness,
+            }),
+            _ => None,
+        }
+    }
+
+    // The roughness of the models that have one
+    pub fn roughness(&self) -> Option<f64> {
+        match self {
+            ShadingModel::OrenNayar { roughness }
+            | ShadingModel::MetallicRoughness { roughness, .. } => Some(*roughness),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub struct Material {
     pub color: Color,
     pub ambient: f64,
     pub diffuse: f64,
     pub specular: f64,
     pub shininess: f64,
+    // Light given off by the surface. It is added to the color whether the surface is
+    // lit or not, and a shape with emission also lights the other objects.
+    pub emission: Color,
+    // The part of the color seen in the mirror direction that is added to the color,
+    // from 0.0 for a matte surface to 1.0 for a mirror
+    pub reflective: f64,
+    // The part of the color seen through the surface that is added to the color, from
+    // 0.0 for an opaque surface to 1.0 for clear glass, and how much the rays bend
+    // when they go through it, 1.0 for air and about 1.5 for glass
+    pub transparency: f64,
+    pub refractive_index: f64,
+    pub model: ShadingModel,
+    // An image that gives the color at each point, multiplied by the color above
+    pub texture: Option<Texture>,
+    // Changes the normal used for shading, to add detail to the surface
+    pub bump: Option<Bump>,
+}
+
+impl Default for Material {
+    fn default() -> Material {
+        return Material::new();
+    }
 }
 
 impl Material {
+    pub fn is_emissive(&self) -> bool {
+        return self.emission.red > 0.0 || self.emission.green > 0.0 || self.emission.blue > 0.0;
+    }
+
     pub fn new() -> Material {
         Material {
             color: Color::color(1.0, 1.0, 1.0),
@@ -23,6 +109,28 @@ impl Material {
             diffuse: 0.9,
             specular: 0.9,
             shininess: 200.0,
+            emission: Color::color(0.0, 0.0, 0.0),
+            reflective: 0.0,
+            transparency: 0.0,
+            refractive_index: 1.0,
+            model: ShadingModel::Phong,
+            texture: None,
+            bump: None,
+        }
+    }
+
+    // The material at a point of the shape in object space, with the color of the
+    // texture there. The material itself is used when it has no texture.
+    pub fn at(&self, object_point: &Tuple) -> Cow<'_, Material> {
+        match &self.texture {
+            None => return Cow::Borrowed(self),
+            Some(texture) => {
+                return Cow::Owned(Material {
+                    color: self.color * texture.color_at(object_point),
+                    texture: None,
+                    ..self.clone()
+                })
+            }
         }
     }
 }
@@ -35,6 +143,12 @@ pub struct Sphere {
     pub material: Material,
 }
 
+impl Default for Sphere {
+    fn default() -> Sphere {
+        return Sphere::new();
+    }
+}
+
 impl Sphere {
     pub fn new() -> Sphere {
         Sphere {
@@ -44,6 +158,11 @@ impl Sphere {
             material: Material::new(),
         }
     }
+
+    // The point in the space of the sphere, where it is the unit sphere at the origin
+    pub fn object_point(&self, point: &Tuple) -> Tuple {
+        return to_tuple(&(&self.transformation.invert().unwrap() * &to_matrix(point)));
+    }
 }
 
 impl Normal for Sphere {
@@ -55,6 +174,24 @@ impl Normal for Sphere {
         );
         return world_normal.normalize();
     }
+
+    fn tangent_at(&self, point: &Tuple, mapping: Mapping) -> Tuple {
+        let normal = self.normal_at(point);
+        let object_tangent = mapping.tangent(&self.object_point(point));
+        let tangent = to_tuple(&(&self.transformation * &to_matrix(&object_tangent)));
+        // Only the part perpendicular to the normal is kept. At the poles there is no
+        // direction of increasing u, so any perpendicular direction is used.
+        let tangent = tangent - normal * dot(&tangent, &normal);
+        if tangent.magnitude() > 1e-9 {
+            return tangent.normalize();
+        }
+        let axis = if normal.x.abs() < 0.9 {
+            Tuple::vector(1.0, 0.0, 0.0)
+        } else {
+            Tuple::vector(0.0, 1.0, 0.0)
+        };
+        return (axis - normal * dot(&axis, &normal)).normalize();
+    }
 }
 
 #[cfg(test)]
@@ -130,6 +267,7 @@ mod tests {
     }
 
     #[test]
+    #[allow(clippy::approx_constant)]
     fn normal_on_translated_sphere() {
         let mut s = Sphere::new();
         s.transformation = Matrix::new_identity().translate(0.0, 1.0, 0.0);
@@ -153,6 +291,37 @@ mod tests {
         ));
     }
 
+    #[test]
+    fn tangent_on_sphere() {
+        let mut s = Sphere::new();
+        // Along the equator, u of the spherical mapping increases towards the east
+        assert!(approx_eq(
+            s.tangent_at(&Tuple::point(0.0, 0.0, -1.0), Mapping::Spherical),
+            Tuple::vector(1.0, 0.0, 0.0),
+            EPSILON
+        ));
+        assert!(approx_eq(
+            s.tangent_at(&Tuple::point(1.0, 0.0, 0.0), Mapping::Spherical),
+            Tuple::vector(0.0, 0.0, 1.0),
+            EPSILON
+        ));
+        // Any direction perpendicular to the normal at a pole
+        let tangent = s.tangent_at(&Tuple::point(0.0, 1.0, 0.0), Mapping::Spherical);
+        assert!((tangent.magnitude() - 1.0).abs() < EPSILON && tangent.y.abs() < EPSILON);
+
+        // The tangent follows the transformation and stays perpendicular to the normal
+        s.transformation = Matrix::new_identity()
+            .scale(2.0, 1.0, 1.0)
+            .rotate_y(std::f64::consts::PI / 2.0)
+            .translate(0.0, 0.0, 5.0);
+        // The object point (0, √2/2, -√2/2), where the object x axis becomes -z
+        let half = 0.5_f64.sqrt();
+        let point = Tuple::point(-half, half, 5.0);
+        let tangent = s.tangent_at(&point, Mapping::Planar);
+        assert!(approx_eq(tangent, Tuple::vector(0.0, 0.0, -1.0), EPSILON));
+        assert!(dot(&tangent, &s.normal_at(&point)).abs() < EPSILON);
+    }
+
     #[test]
     fn default_material() {
         let m = Material::new();
@@ -0,0 +1,326 @@
+use crate::canvas::Canvas;
+use crate::color::Color;
+use crate::tuple::Tuple;
+use std::f64::consts::PI;
+use std::sync::Arc;
+
+// An image texture gives the color of a surface from an image. A point of the shape,
+// in object space, is mapped to texture coordinates (u, v) from 0.0 to 1.0, with (0, 0)
+// the top left corner of the image and u going right, and the image is sampled there.
+// The color of the material is multiplied by the color of the texture, so a white
+// material shows the image as it is.
+
+// How a point of a shape is mapped to texture coordinates
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Mapping {
+    // An equirectangular image around a sphere centered at the origin. The middle of
+    // the image faces -z, the top is at +y.
+    Spherical,
+    // The image repeats every unit on the xz plane, with the top towards +z
+    Planar,
+    // The image goes once around a cylinder along the y axis, with the middle of the
+    // image facing -z, and repeats every unit of height
+    Cylindrical,
+    // Six faces of a cube centered at the origin in the horizontal cross layout, four
+    // faces wide and three high, seen from outside: the face towards -z is in the
+    // middle, with +x on its right, +z next to it and -x on its left, and +y above
+    // and -y below it.
+    Cube,
+}
+
+impl Mapping {
+    pub fn from_name(name: &str) -> Option<Mapping> {
+        match name {
+            "spherical" => Some(Mapping::Spherical),
+            "planar" => Some(Mapping::Planar),
+            "cylindrical" => Some(Mapping::Cylindrical),
+            "cube" => Some(Mapping::Cube),
+            _ => None,
+        }
+    }
+
+    // The texture coordinates of a point in object space
+    pub fn uv(&self, point: &Tuple) -> (f64, f64) {
+        let (x, y, z) = (point.x, point.y, point.z);
+        match self {
+            Mapping::Spherical => {
+                let radius = (x * x + y * y + z * z).sqrt();
+                if radius == 0.0 {
+                    return (0.5, 0.5);
+                }
+                let u = 0.5 + x.atan2(-z) / (2.0 * PI);
+                let v = (y / radius).clamp(-1.0, 1.0).acos() / PI;
+                return (u, v);
+            }
+            Mapping::Planar => return (x.rem_euclid(1.0), (-z).rem_euclid(1.0)),
+            Mapping::Cylindrical => {
+                let u = 0.5 + x.atan2(-z) / (2.0 * PI);
+                return (u, (-y).rem_euclid(1.0));
+            }
+            Mapping::Cube => {
+                let (ax, ay, az) = (x.abs(), y.abs(), z.abs());
+                let major = ax.max(ay).max(az);
+                if major == 0.0 {
+                    return (0.5, 0.5);
+                }
+                let (x, y, z) = (x / major, y / major, z / major);
+                // The column and row of the face in the cross, and the position on it
+                let (column, row, u, v) = if ax >= ay && ax >= az {
+                    if x > 0.0 {
+                        (2.0, 1.0, z + 1.0, 1.0 - y)
+                    } else {
+                        (0.0, 1.0, 1.0 - z, 1.0 - y)
+                    }
+                } else if ay >= az {
+                    if y > 0.0 {
+                        (1.0, 0.0, x + 1.0, 1.0 - z)
+                    } else {
+                        (1.0, 2.0, x + 1.0, z + 1.0)
+                    }
+                } else if z > 0.0 {
+                    (3.0, 1.0, 1.0 - x, 1.0 - y)
+                } else {
+                    (1.0, 1.0, x + 1.0, 1.0 - y)
+                };
+                return ((column + u / 2.0) / 4.0, (row + v / 2.0) / 3.0);
+            }
+        }
+    }
+
+    // The direction in object space in which u increases at a point, not normalized.
+    // It is zero at the poles of the spherical and cylindrical mappings.
+    pub fn tangent(&self, point: &Tuple) -> Tuple {
+        let (x, y, z) = (point.x, point.y, point.z);
+        match self {
+            Mapping::Spherical | Mapping::Cylindrical => return Tuple::vector(-z, 0.0, x),
+            Mapping::Planar => return Tuple::vector(1.0, 0.0, 0.0),
+            Mapping::Cube => {
+                let (ax, ay, az) = (x.abs(), y.abs(), z.abs());
+                if ax >= ay && ax >= az {
+                    return Tuple::vector(0.0, 0.0, x.signum());
+                } else if ay >= az {
+                    return Tuple::vector(1.0, 0.0, 0.0);
+                }
+                return Tuple::vector(-z.signum(), 0.0, 0.0);
+            }
+        }
+    }
+}
+
+// How the image is sampled between the centers of its pixels
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Filter {
+    // The color of the pixel the coordinates are in
+    Nearest,
+    // A blend of the four nearest pixels, which is smoother when the image is magnified
+    Bilinear,
+}
+
+impl Filter {
+    pub fn from_name(name: &str) -> Option<Filter> {
+        match name {
+            "nearest" => Some(Filter::Nearest),
+            "bilinear" => Some(Filter::Bilinear),
+            _ => None,
+        }
+    }
+}
+
+// The image is shared, so materials with the same texture can be cloned cheaply
+#[derive(Debug, Clone)]
+pub struct Texture {
+    image: Arc<Canvas>,
+    pub mapping: Mapping,
+    pub filter: Filter,
+}
+
+// Two textures are the same when they use the same image, the images are not compared
+// pixel by pixel
+impl PartialEq for Texture {
+    fn eq(&self, other: &Texture) -> bool {
+        return Arc::ptr_eq(&self.image, &other.image)
+            && self.mapping == other.mapping
+            && self.filter == other.filter;
+    }
+}
+
+impl Texture {
+    // Returns None if the image is empty
+    pub fn new(image: Canvas, mapping: Mapping, filter: Filter) -> Option<Texture> {
+        if image.width == 0 || image.height == 0 {
+            return None;
+        }
+        return Some(Texture {
+            image: Arc::new(image),
+            mapping,
+            filter,
+        });
+    }
+
+    pub fn image(&self) -> &Canvas {
+        return &self.image;
+    }
+
+    // The color at a point of the shape in object space
+    pub fn color_at(&self, point: &Tuple) -> Color {
+        let (u, v) = self.mapping.uv(point);
+        return self.sample(u, v);
+    }
+
+    // The color at texture coordinates. The image wraps around horizontally and is
+    // clamped vertically.
+    pub fn sample(&self, u: f64, v: f64) -> Color {
+        let (width, height) = (self.image.width as f64, self.image.height as f64);
+        match self.filter {
+            Filter::Nearest => {
+                return self.pixel((u * width).floor() as i64, (v * height).floor() as i64);
+            }
+            Filter::Bilinear => {
+                // The centers of the pixels are at half coordinates
+                let x = u * width - 0.5;
+                let y = v * height - 0.5;
+                let (x0, y0) = (x.floor(), y.floor());
+                let (fx, fy) = (x - x0, y - y0);
+                let (x0, y0) = (x0 as i64, y0 as i64);
+                let top = self.pixel(x0, y0) * (1.0 - fx) + self.pixel(x0 + 1, y0) * fx;
+                let bottom = self.pixel(x0, y0 + 1) * (1.0 - fx) + self.pixel(x0 + 1, y0 + 1) * fx;
+                return top * (1.0 - fy) + bottom * fy;
+            }
+        }
+    }
+
+    fn pixel(&self, x: i64, y: i64) -> Color {
+        let x = x.rem_euclid(self.image.width as i64) as usize;
+        let y = y.clamp(0, self.image.height as i64 - 1) as usize;
+        return self
+            .image
+            .read_pixel(x, y)
+            .unwrap_or(Color::color(0.0, 0.0, 0.0));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(lhs: (f64, f64), rhs: (f64, f64)) -> bool {
+        return (lhs.0 - rhs.0).abs() < 1e-6 && (lhs.1 - rhs.1).abs() < 1e-6;
+    }
+
+    // A 2x2 image: black and red on the top row, green and blue on the bottom row
+    fn image() -> Canvas {
+        let mut image = Canvas::new(2, 2);
+        image.write_pixel(1, 0, Color::color(1., 0., 0.));
+        image.write_pixel(0, 1, Color::color(0., 1., 0.));
+        image.write_pixel(1, 1, Color::color(0., 0., 1.));
+        return image;
+    }
+
+    #[test]
+    fn spherical_mapping() {
+        let mapping = Mapping::Spherical;
+        let half = 0.5_f64.sqrt();
+        for (point, uv) in [
+            (Tuple::point(0., 0., -1.), (0.5, 0.5)),
+            (Tuple::point(1., 0., 0.), (0.75, 0.5)),
+            (Tuple::point(-1., 0., 0.), (0.25, 0.5)),
+            // Just off the poles, where u is not defined
+            (Tuple::point(0., 1., -1e-9), (0.5, 0.0)),
+            (Tuple::point(0., -1., -1e-9), (0.5, 1.0)),
+            (Tuple::point(half, half, 0.), (0.75, 0.25)),
+            (Tuple::point(0., 0., 2.), (1.0, 0.5)),
+        ] {
+            assert!(close(mapping.uv(&point), uv), "{:?}", point);
+        }
+    }
+
+    #[test]
+    fn planar_and_cylindrical_mappings() {
+        for (point, uv) in [
+            (Tuple::point(0.25, 0., -0.5), (0.25, 0.5)),
+            (Tuple::point(1.25, 3., -1.75), (0.25, 0.75)),
+            (Tuple::point(-0.25, 0., 0.25), (0.75, 0.75)),
+        ] {
+            assert!(close(Mapping::Planar.uv(&point), uv), "{:?}", point);
+        }
+        for (point, uv) in [
+            (Tuple::point(0., 0., -1.), (0.5, 0.0)),
+            (Tuple::point(1., -0.25, 0.), (0.75, 0.25)),
+            (Tuple::point(-1., 1.5, 0.), (0.25, 0.5)),
+        ] {
+            assert!(close(Mapping::Cylindrical.uv(&point), uv), "{:?}", point);
+        }
+    }
+
+    #[test]
+    fn cube_mapping() {
+        // The centers of the faces
+        for (point, uv) in [
+            (Tuple::point(0., 0., -1.), (0.375, 0.5)),
+            (Tuple::point(1., 0., 0.), (0.625, 0.5)),
+            (Tuple::point(0., 0., 1.), (0.875, 0.5)),
+            (Tuple::point(-1., 0., 0.), (0.125, 0.5)),
+            (Tuple::point(0., 1., 0.), (0.375, 1. / 6.)),
+            (Tuple::point(0., -1., 0.), (0.375, 5. / 6.)),
+        ] {
+            assert!(close(Mapping::Cube.uv(&point), uv), "{:?}", point);
+        }
+        // Edges shared by neighbouring faces meet in the cross
+        let front = Mapping::Cube.uv(&Tuple::point(-0.999999999, 0.5, -1.));
+        let left = Mapping::Cube.uv(&Tuple::point(-1., 0.5, -0.999999999));
+        assert!(close(front, left));
+        let top = Mapping::Cube.uv(&Tuple::point(0.5, 1., -0.999999999));
+        let front = Mapping::Cube.uv(&Tuple::point(0.5, 0.999999999, -1.));
+        assert!(close(top, front));
+    }
+
+    #[test]
+    fn tangents_point_towards_increasing_u() {
+        let point = Tuple::point(0.3, -0.4, -0.5).normalize();
+        let point = Tuple::point(point.x, point.y, point.z);
+        for mapping in [
+            Mapping::Spherical,
+            Mapping::Planar,
+            Mapping::Cylindrical,
+            Mapping::Cube,
+        ] {
+            let tangent = mapping.tangent(&point).normalize();
+            let (u, v) = mapping.uv(&point);
+            let (next_u, next_v) = mapping.uv(&(point + tangent * 1e-6));
+            assert!(next_u > u, "{:?}", mapping);
+            assert!((next_v - v).abs() < 1e-9, "{:?}", mapping);
+        }
+        assert_eq!(
+            Mapping::Cube.tangent(&Tuple::point(0.2, 0.1, 1.0)),
+            Tuple::vector(-1.0, 0.0, 0.0)
+        );
+    }
+
+    #[test]
+    fn nearest_filter() {
+        let texture = Texture::new(image(), Mapping::Planar, Filter::Nearest).unwrap();
+        assert_eq!(texture.sample(0.75, 0.25), Color::color(1., 0., 0.));
+        assert_eq!(texture.sample(0.25, 0.75), Color::color(0., 1., 0.));
+        assert_eq!(texture.sample(0.99, 0.99), Color::color(0., 0., 1.));
+        // Wraps around horizontally and is clamped vertically
+        assert_eq!(texture.sample(1.75, 1.5), Color::color(0., 0., 1.));
+        assert_eq!(
+            texture.color_at(&Tuple::point(0.75, 0., -0.75)),
+            Color::color(0., 0., 1.)
+        );
+    }
+
+    #[test]
+    fn bilinear_filter() {
+        let texture = Texture::new(image(), Mapping::Planar, Filter::Bilinear).unwrap();
+        // At the center of a pixel
+        assert_eq!(texture.sample(0.75, 0.25), Color::color(1., 0., 0.));
+        // Halfway between the four pixels
+        assert_eq!(texture.sample(0.5, 0.5), Color::color(0.25, 0.25, 0.25));
+        // Between the two pixels of the top row
+        assert_eq!(texture.sample(0.5, 0.25), Color::color(0.5, 0., 0.));
+        // The left edge blends with the right edge, the top edge is clamped
+        assert_eq!(texture.sample(0.0, 0.0), Color::color(0.5, 0., 0.));
+        assert!(Texture::new(Canvas::new(0, 0), Mapping::Planar, Filter::Nearest).is_none());
+    }
+}
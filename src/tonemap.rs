@@ -0,0 +1,265 @@
+use crate::canvas::Canvas;
+use crate::color::Color;
+
+// Module for the output transform that is applied between a rendered canvas and the
+// image encoders.
+//
+// The rendered colors are linear and unbounded. Before they are stored in an image
+// format with 8 or 16 bits per part they are scaled by the exposure, compressed into
+// the 0.0 - 1.0 range by a tone mapping operator and, optionally, encoded with the sRGB
+// transfer function so that mid-tones are displayed correctly.
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Operator {
+    // Clamp each part to 1.0, the same as writing the canvas without a transform
+    Clamp,
+    // c / (1 + c), which compresses highlights smoothly and never reaches white
+    Reinhard,
+    // Reinhard with a white point: colors at or above the white point map to white. Use
+    // Operator::reinhard_extended to build it, which checks the white point.
+    ReinhardExtended { white: f64 },
+    // Filmic curve fitted to the ACES reference rendering transform
+    Filmic,
+}
+
+impl Operator {
+    // The white point of the extended Reinhard operator when it is selected by name
+    // without one
+    pub const DEFAULT_WHITE: f64 = 4.0;
+
+    // Select the operator by name, as given on the command line. The white point of the
+    // extended Reinhard operator may follow the name after a colon: reinhard-extended:8
+    pub fn from_name(name: &str) -> Option<Operator> {
+        let name = name.to_ascii_lowercase();
+        if let Some(white) = name.strip_prefix("reinhard-extended:") {
+            return Operator::reinhard_extended(white.parse().ok()?);
+        }
+        match name.as_str() {
+            "clamp" => Some(Operator::Clamp),
+            "reinhard" => Some(Operator::Reinhard),
+            "reinhard-extended" => Operator::reinhard_extended(Operator::DEFAULT_WHITE),
+            "filmic" => Some(Operator::Filmic),
+            _ => None,
+        }
+    }
+
+    // The white point must be a positive, finite value
+    pub fn reinhard_extended(white: f64) -> Option<Operator> {
+        if !(white > 0.0 && white.is_finite()) {
+            return None;
+        }
+        return Some(Operator::ReinhardExtended { white });
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct OutputTransform {
+    // Exposure adjustment in stops, i.e. the colors are multiplied by 2^exposure
+    pub exposure: f64,
+    pub operator: Operator,
+    pub srgb: bool,
+}
+
+impl Default for OutputTransform {
+    fn default() -> OutputTransform {
+        return OutputTransform::new();
+    }
+}
+
+impl OutputTransform {
+    // The identity transform keeps the colors as they are (apart from clamping)
+    pub fn new() -> OutputTransform {
+        OutputTransform {
+            exposure: 0.0,
+            operator: Operator::Clamp,
+            srgb: false,
+        }
+    }
+
+    pub fn apply(&self, color: Color) -> Color {
+        let exposed = color * 2f64.powf(self.exposure);
+        let map = |part: f64| {
+            let mapped = tone_map(self.operator, part.max(0.0)).clamp(0.0, 1.0);
+            if self.srgb {
+                srgb_encode(mapped)
+            } else {
+                mapped
+            }
+        };
+        return Color::color(map(exposed.red), map(exposed.green), map(exposed.blue));
+    }
+
+    // Apply only the exposure. Used for the floating point formats that keep the linear
+    // high-dynamic-range values.
+    pub fn apply_exposure(&self, color: Color) -> Color {
+        return color * 2f64.powf(self.exposure);
+    }
+
+    pub fn apply_canvas(&self, canvas: &Canvas) -> Canvas {
+        return map_canvas(canvas, |color| self.apply(color));
+    }
+
+    pub fn apply_exposure_canvas(&self, canvas: &Canvas) -> Canvas {
+        return map_canvas(canvas, |color| self.apply_exposure(color));
+    }
+}
+
+fn map_canvas<F: Fn(Color) -> Color>(canvas: &Canvas, f: F) -> Canvas {
+    let mut output = canvas.clone();
+    for pixel in output.pixels_mut() {
+        *pixel = f(*pixel);
+    }
+    return output;
+}
+
+fn tone_map(operator: Operator, value: f64) -> f64 {
+    match operator {
+        Operator::Clamp => value,
+        Operator::Reinhard => value / (1.0 + value),
+        // A white point built without the check falls back to plain Reinhard instead
+        // of dividing by zero
+        Operator::ReinhardExtended { white } if !(white > 0.0 && white.is_finite()) => {
+            value / (1.0 + value)
+        }
+        Operator::ReinhardExtended { white } => {
+            value * (1.0 + value / (white * white)) / (1.0 + value)
+        }
+        Operator::Filmic => {
+            // Krzysztof Narkowicz's fit of the ACES curve
+            let (a, b, c, d, e) = (2.51, 0.03, 2.43, 0.59, 0.14);
+            (value * (a * value + b)) / (value * (c * value + d) + e)
+        }
+    }
+}
+
+// The sRGB transfer function, from linear light to the encoded value
+pub fn srgb_encode(linear: f64) -> f64 {
+    if linear <= 0.0031308 {
+        12.92 * linear
+    } else {
+        1.055 * linear.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+// The inverse of the sRGB transfer function, from the encoded value to linear light
+pub fn srgb_decode(encoded: f64) -> f64 {
+    if encoded <= 0.04045 {
+        encoded / 12.92
+    } else {
+        ((encoded + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    const EPSILON: f64 = 0.0001;
+
+    #[test]
+    fn identity_transform_only_clamps() {
+        let transform = OutputTransform::new();
+        assert_eq!(
+            transform.apply(Color::color(0.25, 1.5, -0.5)),
+            Color::color(0.25, 1.0, 0.0)
+        );
+    }
+
+    #[test]
+    fn exposure_is_given_in_stops() {
+        let mut transform = OutputTransform::new();
+        transform.exposure = 1.0;
+        assert_eq!(
+            transform.apply(Color::color(0.25, 0.1, 0.0)),
+            Color::color(0.5, 0.2, 0.0)
+        );
+        transform.exposure = -2.0;
+        assert_eq!(
+            transform.apply_exposure(Color::color(8.0, 4.0, 1.0)),
+            Color::color(2.0, 1.0, 0.25)
+        );
+    }
+
+    #[test]
+    fn reinhard_compresses_highlights() {
+        assert!((tone_map(Operator::Reinhard, 1.0) - 0.5).abs() < EPSILON);
+        assert!((tone_map(Operator::Reinhard, 3.0) - 0.75).abs() < EPSILON);
+        assert!(tone_map(Operator::Reinhard, 1000.0) < 1.0);
+    }
+
+    #[test]
+    fn extended_reinhard_maps_white_point_to_white() {
+        let operator = Operator::reinhard_extended(4.0).unwrap();
+        assert!((tone_map(operator, 4.0) - 1.0).abs() < EPSILON);
+        assert!(tone_map(operator, 1.0) > tone_map(Operator::Reinhard, 1.0));
+    }
+
+    #[test]
+    fn operator_from_name() {
+        assert_eq!(Operator::from_name("clamp"), Some(Operator::Clamp));
+        assert_eq!(Operator::from_name("Reinhard"), Some(Operator::Reinhard));
+        assert_eq!(Operator::from_name("filmic"), Some(Operator::Filmic));
+        assert_eq!(Operator::from_name("aces"), None);
+        assert_eq!(
+            Operator::from_name("reinhard-extended"),
+            Operator::reinhard_extended(Operator::DEFAULT_WHITE)
+        );
+        assert_eq!(
+            Operator::from_name("Reinhard-Extended:2.5"),
+            Some(Operator::ReinhardExtended { white: 2.5 })
+        );
+        assert_eq!(Operator::from_name("reinhard-extended:0"), None);
+        assert_eq!(Operator::from_name("reinhard-extended:"), None);
+        assert_eq!(Operator::from_name("reinhard-extended:bright"), None);
+    }
+
+    #[test]
+    fn extended_reinhard_rejects_invalid_white_point() {
+        assert_eq!(Operator::reinhard_extended(0.0), None);
+        assert_eq!(Operator::reinhard_extended(-1.0), None);
+        assert_eq!(Operator::reinhard_extended(f64::NAN), None);
+        assert_eq!(Operator::reinhard_extended(f64::INFINITY), None);
+        for white in [0.0, -1.0, f64::NAN] {
+            let operator = Operator::ReinhardExtended { white };
+            assert_eq!(tone_map(operator, 3.0), tone_map(Operator::Reinhard, 3.0));
+            assert_eq!(tone_map(operator, 0.0), 0.0);
+        }
+    }
+
+    #[test]
+    fn filmic_curve() {
+        assert_eq!(tone_map(Operator::Filmic, 0.0), 0.0);
+        assert!((tone_map(Operator::Filmic, 1.0) - 0.80377).abs() < EPSILON);
+        let mut previous = 0.0;
+        for i in 1..100 {
+            let value = tone_map(Operator::Filmic, i as f64 * 0.1);
+            assert!(value > previous);
+            previous = value;
+        }
+    }
+
+    #[test]
+    fn srgb_transfer_function() {
+        assert_eq!(srgb_encode(0.0), 0.0);
+        assert!((srgb_encode(1.0) - 1.0).abs() < EPSILON);
+        assert!((srgb_encode(0.5) - 0.73536).abs() < EPSILON);
+        assert!((srgb_encode(0.001) - 0.01292).abs() < EPSILON);
+        for i in 0..=20 {
+            let value = i as f64 / 20.0;
+            assert!((srgb_decode(srgb_encode(value)) - value).abs() < EPSILON);
+        }
+    }
+
+    #[test]
+    fn transform_canvas() {
+        let mut canvas = Canvas::new(2, 1);
+        canvas.write_pixel(1, 0, Color::color(1.0, 3.0, 0.0));
+        let transform = OutputTransform {
+            exposure: 0.0,
+            operator: Operator::Reinhard,
+            srgb: false,
+        };
+        let output = transform.apply_canvas(&canvas);
+        assert_eq!(output.read_pixel(0, 0), Some(Color::color(0.0, 0.0, 0.0)));
+        assert_eq!(output.read_pixel(1, 0), Some(Color::color(0.5, 0.75, 0.0)));
+    }
+}
@@ -9,19 +9,19 @@ pub struct Tuple {
 }
 
 impl Tuple {
-    pub(crate) fn point(x: f64, y: f64, z: f64) -> Tuple {
+    pub fn point(x: f64, y: f64, z: f64) -> Tuple {
         return Tuple { x, y, z, w: 1.0 };
     }
 
-    pub(crate) fn vector(x: f64, y: f64, z: f64) -> Tuple {
+    pub fn vector(x: f64, y: f64, z: f64) -> Tuple {
         return Tuple { x, y, z, w: 0.0 };
     }
 
-    pub(crate) fn magnitude(&self) -> f64 {
+    pub fn magnitude(&self) -> f64 {
         (self.x.powf(2.0) + self.y.powf(2.0) + self.z.powf(2.0)).sqrt()
     }
 
-    pub(crate) fn normalize(&self) -> Tuple {
+    pub fn normalize(&self) -> Tuple {
         return Tuple {
             x: self.x / self.magnitude(),
             y: self.y / self.magnitude(),
@@ -117,10 +117,6 @@ impl PartialEq for Tuple {
             && (self.z - other.z).abs() < EPSILON
             && (self.w - other.w).abs() < EPSILON
     }
-
-    fn ne(&self, other: &Self) -> bool {
-        return !self.eq(other);
-    }
 }
 
 pub fn dot(lhs: &Tuple, rhs: &Tuple) -> f64 {
@@ -135,6 +131,19 @@ pub fn cross(lhs: &Tuple, rhs: &Tuple) -> Tuple {
     );
 }
 
+// Two vectors that together with the normalized vector form an orthonormal basis
+pub fn orthonormal_basis(normal: &Tuple) -> (Tuple, Tuple) {
+    // Start from the axis that is furthest from being parallel to the normal
+    let axis = if normal.x.abs() > 0.9 {
+        Tuple::vector(0.0, 1.0, 0.0)
+    } else {
+        Tuple::vector(1.0, 0.0, 0.0)
+    };
+    let tangent = cross(&axis, normal).normalize();
+    let bitangent = cross(normal, &tangent);
+    return (tangent, bitangent);
+}
+
 pub fn reflect(vector: &Tuple, normal: &Tuple) -> Tuple {
     return vector.sub(&(*normal * 2.0 * dot(vector, normal)));
 }
@@ -399,4 +408,20 @@ mod tests {
         let normal = Tuple::vector(2.0_f64.sqrt() / 2.0, 2.0_f64.sqrt() / 2.0, 0.0);
         assert_eq!(reflect(&v, &normal), Tuple::vector(1.0, 0.0, 0.0));
     }
+
+    #[test]
+    fn orthonormal_basis_around_a_vector() {
+        for normal in [
+            Tuple::vector(0.0, 0.0, 1.0),
+            Tuple::vector(1.0, 0.0, 0.0),
+            Tuple::vector(1.0, 2.0, -3.0).normalize(),
+        ] {
+            let (tangent, bitangent) = orthonormal_basis(&normal);
+            assert!((tangent.magnitude() - 1.0).abs() < 0.00001);
+            assert!((bitangent.magnitude() - 1.0).abs() < 0.00001);
+            assert!(dot(&tangent, &normal).abs() < 0.00001);
+            assert!(dot(&bitangent, &normal).abs() < 0.00001);
+            assert!(dot(&tangent, &bitangent).abs() < 0.00001);
+        }
+    }
 }
@@ -1,37 +1,258 @@
-use crate::lights::{lighting, PointLight};
-use crate::rays::{hit, Computation, Ray};
+use crate::environment::Environment;
+use crate::lights::{ambient_light, direct_light, Light, LightSource, ShapeLight};
+use crate::random::Random;
+use crate::rays::{hit, Computation, Ray, EPSILON};
 use crate::shapes::Sphere;
+use crate::tuple::Tuple;
 use crate::Color;
+use std::ptr;
+
+// Ambient occlusion darkens the ambient light in creases and corners, where nearby
+// objects hide a part of the sky. Rays in random directions are shot from each hit,
+// and the ambient light is scaled by the fraction of them that hit nothing within the
+// distance.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AmbientOcclusion {
+    pub samples: usize,
+    pub distance: f64,
+}
+
+impl Default for AmbientOcclusion {
+    fn default() -> AmbientOcclusion {
+        return AmbientOcclusion {
+            samples: 16,
+            distance: 1.0,
+        };
+    }
+}
 
 #[derive(Debug)]
 pub struct World {
     pub objects: Vec<Sphere>,
-    pub lights: Vec<PointLight>,
+    pub lights: Vec<Light>,
+    // The number of samples for each object with an emissive material when it lights
+    // another object
+    pub emission_samples: usize,
+    // No ambient occlusion when None
+    pub ambient_occlusion: Option<AmbientOcclusion>,
+    // The background, seen where a ray misses every object. Without an environment
+    // the background is transparent.
+    pub environment: Option<Environment>,
+}
+
+impl Default for World {
+    fn default() -> World {
+        return World::new();
+    }
 }
 
-// Only one light used in the calculations at the moment
 impl World {
+    // The number of reflections and refractions followed from a ray, so two mirrors
+    // facing each other do not reflect each other forever
+    pub const MAX_REFLECTIONS: usize = 5;
+
     pub fn new() -> World {
         World {
             objects: Vec::new(),
             lights: Vec::new(),
+            emission_samples: 16,
+            ambient_occlusion: None,
+            environment: None,
         }
     }
 
+    // The contributions of all the lights and all the other emissive objects are added
+    // to the emission of the object. The part of a light that is blocked by other
+    // objects gives a shadow.
     pub fn shade_hit(&self, computation: &Computation) -> Color {
-        lighting(
-            &computation.object.material,
-            &self.lights[0],
-            &computation.point,
-            &computation.eyev,
-            &computation.normalv,
-        )
+        return self.shade_hit_with_reflections(computation, World::MAX_REFLECTIONS);
+    }
+
+    // Shade a hit and add the reflection and the refraction, following at most the
+    // remaining reflections and refractions
+    fn shade_hit_with_reflections(&self, computation: &Computation, remaining: usize) -> Color {
+        let unoccluded = match &self.ambient_occlusion {
+            Some(settings) => self.unoccluded_fraction(computation, settings),
+            None => 1.0,
+        };
+        let mut color = computation.object.material.emission;
+        for light in &self.lights {
+            color = color + self.light_contribution(light, computation, unoccluded);
+        }
+        for light in self.shape_lights(computation.object) {
+            color = color + self.light_contribution(&light, computation, unoccluded);
+        }
+        return color
+            + self.reflected_color(computation, remaining)
+            + self.refracted_color(computation, remaining);
+    }
+
+    // The color seen in the mirror direction, scaled by how reflective the material is.
+    // A reflected ray that misses every object sees the environment.
+    pub fn reflected_color(&self, computation: &Computation, remaining: usize) -> Color {
+        let (reflective, _) = computation.specular_weights();
+        if remaining == 0 || reflective == 0.0 {
+            return Color::color(0., 0., 0.);
+        }
+        let ray = Ray::new(computation.over_point, computation.reflectv);
+        return self.color_with_reflections(&ray, remaining - 1) * reflective;
+    }
+
+    // The color seen through the surface, scaled by how transparent the material is.
+    // It is black when the light is totally reflected inside the object.
+    pub fn refracted_color(&self, computation: &Computation, remaining: usize) -> Color {
+        let (_, transparency) = computation.specular_weights();
+        if remaining == 0 || transparency == 0.0 {
+            return Color::color(0., 0., 0.);
+        }
+        let Some(direction) = computation.refractv() else {
+            return Color::color(0., 0., 0.);
+        };
+        let ray = Ray::new(computation.under_point, direction);
+        return self.color_with_reflections(&ray, remaining - 1) * transparency;
+    }
+
+    // The color seen along a ray, following at most the remaining reflections and
+    // refractions
+    fn color_with_reflections(&self, ray: &Ray, remaining: usize) -> Color {
+        let intersections = ray.intersections_in_world(self);
+        match hit(&intersections) {
+            Some(intersection) => self.shade_hit_with_reflections(
+                &ray.prepare_computation_in(intersection, &intersections),
+                remaining,
+            ),
+            None => self.background(ray).0,
+        }
+    }
+
+    // The objects with an emissive material used as lights, except the given object,
+    // which cannot light itself
+    pub fn shape_lights(&self, except: &Sphere) -> Vec<ShapeLight<'_>> {
+        let mut lights = Vec::new();
+        for (index, object) in self.objects.iter().enumerate() {
+            if object.material.is_emissive() && !ptr::eq(object, except) {
+                lights.push(ShapeLight {
+                    shape: object,
+                    samples: self.emission_samples,
+                    seed: index as u64 + 1,
+                });
+            }
+        }
+        return lights;
+    }
+
+    // The ambient light is scaled by the unoccluded fraction from ambient occlusion
+    fn light_contribution(
+        &self,
+        light: &dyn LightSource,
+        computation: &Computation,
+        unoccluded: f64,
+    ) -> Color {
+        let material = &computation.material;
+        let intensity = self.intensity_at(light, &computation.over_point);
+        return ambient_light(material, light) * unoccluded
+            + direct_light(
+                material,
+                light,
+                &computation.over_point,
+                &computation.eyev,
+                &computation.normalv,
+                intensity,
+            );
+    }
+
+    // The fraction of the cosine weighted rays from the hit that do not hit an object
+    // within the distance of the settings: 1.0 on an open surface and less in a
+    // corner. The rays are seeded from the point, so the result is the same every time.
+    pub fn unoccluded_fraction(
+        &self,
+        computation: &Computation,
+        settings: &AmbientOcclusion,
+    ) -> f64 {
+        if settings.samples == 0 {
+            return 1.0;
+        }
+        let point = &computation.over_point;
+        let mut random = Random::from_point(point, 0);
+        let mut unoccluded = 0;
+        for _ in 0..settings.samples {
+            let direction = random.cosine_hemisphere(&computation.normalv);
+            if !self.is_blocked(point, &direction, settings.distance) {
+                unoccluded += 1;
+            }
+        }
+        return unoccluded as f64 / settings.samples as f64;
+    }
+
+    // Check if an object is between the point and the light position
+    pub fn is_shadowed(&self, light_position: &Tuple, point: &Tuple) -> bool {
+        let vector = *light_position - *point;
+        return self.is_blocked(point, &vector.normalize(), vector.magnitude());
+    }
+
+    // Check if an object is within the distance from the point in the direction. An
+    // object just at the distance, like the surface a light sample is on, does not
+    // block it.
+    pub fn is_blocked(&self, point: &Tuple, direction: &Tuple, distance: f64) -> bool {
+        let ray = Ray::new(*point, *direction);
+        match hit(&ray.intersections_in_world(self)) {
+            Some(intersection) => intersection.t < distance - EPSILON,
+            None => false,
+        }
+    }
+
+    // The fraction of the light that reaches the point: 0.0 or 1.0 for a light with
+    // one sample and the fraction of the visible samples for an area light
+    pub fn intensity_at(&self, light: &dyn LightSource, point: &Tuple) -> f64 {
+        let samples = light.samples(point);
+        if samples.is_empty() {
+            return 0.0;
+        }
+        let visible = samples
+            .iter()
+            .filter(|sample| !self.is_blocked(point, &sample.direction, sample.distance))
+            .count();
+        return visible as f64 / samples.len() as f64;
     }
 
     pub fn color_at(&self, ray: &Ray) -> Color {
+        return self.color_with_alpha_at(ray).0;
+    }
+
+    // Like color_at, but also returns the alpha: a ray that misses every object gives
+    // a transparent black pixel, so the background of a render can be composited over
+    // other images, unless the world has an environment.
+    pub fn color_with_alpha_at(&self, ray: &Ray) -> (Color, f64) {
+        let intersections = ray.intersections_in_world(self);
+        match hit(&intersections) {
+            Some(intersection) => {
+                let computation = ray.prepare_computation_in(intersection, &intersections);
+                (self.shade_hit(&computation), 1.0)
+            }
+            None => self.background(ray),
+        }
+    }
+
+    // The color and alpha of a ray that misses every object
+    pub fn background(&self, ray: &Ray) -> (Color, f64) {
+        match &self.environment {
+            Some(environment) => (environment.color_at(&ray.direction.normalize()), 1.0),
+            None => (Color::color(0., 0., 0.), 0.0),
+        }
+    }
+
+    // The ambient occlusion pass: a gray level from black, fully occluded, to white,
+    // without lights and materials. It uses the ambient occlusion settings of the
+    // world, or the default settings if there are none.
+    pub fn occlusion_with_alpha_at(&self, ray: &Ray) -> (Color, f64) {
         match hit(&ray.intersections_in_world(self)) {
-            Some(intersection) => self.shade_hit(&ray.prepare_computation(intersection)),
-            None => Color::color(0., 0., 0.),
+            Some(intersection) => {
+                let settings = self.ambient_occlusion.unwrap_or_default();
+                let computation = ray.prepare_computation(intersection);
+                let unoccluded = self.unoccluded_fraction(&computation, &settings);
+                (Color::color(unoccluded, unoccluded, unoccluded), 1.0)
+            }
+            None => (Color::color(0., 0., 0.), 0.0),
         }
     }
 }
@@ -39,6 +260,9 @@ impl World {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::canvas::Canvas;
+    use crate::environment::CubeMap;
+    use crate::lights::{AreaLight, Attenuation, DirectionalLight, PointLight, SpotLight};
     use crate::rays::Intersection;
     use crate::shapes::Material;
     use crate::Matrix;
@@ -58,6 +282,7 @@ mod tests {
                 diffuse: 0.7,
                 specular: 0.2,
                 shininess: 200.0,
+                ..Material::new()
             };
             let s1 = Sphere {
                 pos: Tuple::point(0.0, 0.0, 0.0),
@@ -68,7 +293,7 @@ mod tests {
             let mut s2 = Sphere::new();
             s2.transformation = Matrix::new_identity().scale(0.5, 0.5, 0.5);
 
-            w.lights.push(light);
+            w.lights.push(light.into());
             w.objects.push(s1);
             w.objects.push(s2);
             return w;
@@ -79,8 +304,8 @@ mod tests {
     fn create_world() {
         let world = World::new();
 
-        assert!(world.objects.len() == 0);
-        assert!(world.lights.len() == 0);
+        assert!(world.objects.is_empty());
+        assert!(world.lights.is_empty());
     }
 
     #[test]
@@ -95,6 +320,7 @@ mod tests {
             diffuse: 0.7,
             specular: 0.2,
             shininess: 200.0,
+            ..Material::new()
         };
         let s1 = Sphere {
             pos: Tuple::point(0.0, 0.0, 0.0),
@@ -106,7 +332,7 @@ mod tests {
         s2.transformation = Matrix::new_identity().scale(0.5, 0.5, 0.5);
         let world = World::default_world();
 
-        assert!(world.lights.contains(&light));
+        assert!(world.lights.contains(&light.into()));
         assert!(world.objects.contains(&s1));
         assert!(world.objects.contains(&s2));
     }
@@ -124,7 +350,8 @@ mod tests {
     #[test]
     fn shade_an_intersection_from_inside() {
         let mut world = World::default_world();
-        world.lights[0] = PointLight::new(Tuple::point(0., 0.25, 0.), Color::color(1.0, 1.0, 1.0));
+        world.lights[0] =
+            PointLight::new(Tuple::point(0., 0.25, 0.), Color::color(1.0, 1.0, 1.0)).into();
         let ray = Ray::new(Tuple::point(0., 0., 0.), Tuple::vector(0., 0., 1.));
         // Check the second object in the default world
         assert_eq!(
@@ -156,4 +383,327 @@ mod tests {
         let color = world.color_at(&ray);
         assert_eq!(color, world.objects[1].material.color);
     }
+
+    #[test]
+    fn no_shadow_when_nothing_is_between_the_point_and_the_light() {
+        let world = World::default_world();
+        let light_position = Tuple::point(-10., 10., -10.);
+        for point in [
+            Tuple::point(0., 10., 0.),
+            Tuple::point(-20., 20., -20.),
+            Tuple::point(-5., 5., -5.),
+        ] {
+            assert!(!world.is_shadowed(&light_position, &point));
+        }
+    }
+
+    #[test]
+    fn shadow_when_an_object_is_between_the_point_and_the_light() {
+        let world = World::default_world();
+        assert!(world.is_shadowed(
+            &Tuple::point(-10., 10., -10.),
+            &Tuple::point(10., -10., 10.)
+        ));
+    }
+
+    #[test]
+    fn shade_hit_is_given_an_intersection_in_shadow() {
+        let mut world = World::new();
+        world
+            .lights
+            .push(PointLight::new(Tuple::point(0., 0., -10.), Color::color(1., 1., 1.)).into());
+        world.objects.push(Sphere::new());
+        let mut sphere = Sphere::new();
+        sphere.transformation = Matrix::new_identity().translate(0., 0., 10.);
+        world.objects.push(sphere);
+        let ray = Ray::new(Tuple::point(0., 0., 5.), Tuple::vector(0., 0., 1.));
+        let computation = ray.prepare_computation(&Intersection::new(4., &world.objects[1]));
+        assert_eq!(world.shade_hit(&computation), Color::color(0.1, 0.1, 0.1));
+    }
+
+    #[test]
+    fn area_light_intensity_is_the_visible_fraction() {
+        let world = World::default_world();
+        let mut light = AreaLight::new(
+            Tuple::point(-0.5, -0.5, -5.),
+            Tuple::vector(1., 0., 0.),
+            2,
+            Tuple::vector(0., 1., 0.),
+            2,
+            Color::color(1., 1., 1.),
+        )
+        .unwrap();
+        light.jitter = false;
+        let light = Light::from(light);
+        for (point, expected) in [
+            (Tuple::point(0., 0., 2.), 0.0),
+            (Tuple::point(1., -1., 2.), 0.25),
+            (Tuple::point(1.5, 0., 2.), 0.5),
+            (Tuple::point(1.25, 1.25, 3.), 0.75),
+            (Tuple::point(0., 0., -2.), 1.0),
+        ] {
+            assert_eq!(world.intensity_at(&light, &point), expected);
+        }
+    }
+
+    #[test]
+    fn all_lights_are_added() {
+        let mut world = World::default_world();
+        let ray = Ray::new(Tuple::point(0., 0., -5.), Tuple::vector(0., 0., 1.));
+        let one_light = world.color_at(&ray);
+        let light = world.lights[0].clone();
+        world.lights.push(light);
+        let two_lights = world.color_at(&ray);
+        assert_eq!(two_lights, one_light + one_light);
+    }
+
+    #[test]
+    fn directional_light_casts_shadows_from_far_away() {
+        let world = World::default_world();
+        let sun = DirectionalLight::new(Tuple::vector(0., -1., 0.), Color::color(1., 1., 1.));
+        assert_eq!(world.intensity_at(&sun, &Tuple::point(0., -100., 0.)), 0.0);
+        assert_eq!(world.intensity_at(&sun, &Tuple::point(3., -100., 0.)), 1.0);
+    }
+
+    #[test]
+    fn spot_light_only_lights_its_cone() {
+        let mut world = World::default_world();
+        world.lights[0] = SpotLight::new(
+            Tuple::point(0., 0., -10.),
+            Tuple::vector(0., 1., 1.),
+            0.1,
+            0.2,
+            Color::color(1., 1., 1.),
+        )
+        .into();
+        world.objects[0].material.ambient = 0.;
+        let ray = Ray::new(Tuple::point(0., 0., -5.), Tuple::vector(0., 0., 1.));
+        assert_eq!(world.color_at(&ray), Color::color(0., 0., 0.));
+    }
+
+    #[test]
+    fn attenuated_light_is_dimmer_and_still_casts_shadows() {
+        let mut world = World::default_world();
+        let ray = Ray::new(Tuple::point(0., 0., -5.), Tuple::vector(0., 0., 1.));
+        let full = world.color_at(&ray);
+        let mut light = PointLight::new(Tuple::point(-10., 10., -10.), Color::color(1., 1., 1.));
+        light.attenuation = Attenuation::Linear;
+        world.lights[0] = light.into();
+        let attenuated = world.color_at(&ray);
+        assert!(attenuated.red < full.red && attenuated.green < full.green);
+
+        // The shadow test uses the same samples
+        assert_eq!(
+            world.intensity_at(&world.lights[0], &Tuple::point(10., -10., 10.)),
+            0.0
+        );
+        assert_eq!(
+            world.intensity_at(&world.lights[0], &Tuple::point(-5., 5., -5.)),
+            1.0
+        );
+    }
+
+    #[test]
+    fn emission_is_added_without_light() {
+        let mut world = World::new();
+        let mut sphere = Sphere::new();
+        sphere.material.emission = Color::color(0.5, 0.25, 0.);
+        world.objects.push(sphere);
+        let ray = Ray::new(Tuple::point(0., 0., -5.), Tuple::vector(0., 0., 1.));
+        assert_eq!(world.color_at(&ray), Color::color(0.5, 0.25, 0.));
+    }
+
+    #[test]
+    fn emissive_object_lights_other_objects() {
+        let mut world = World::new();
+        let mut floor = Sphere::new();
+        floor.transformation = Matrix::new_identity().scale(10., 0.01, 10.);
+        floor.material.ambient = 0.;
+        floor.material.specular = 0.;
+        world.objects.push(floor);
+        let ray = Ray::new(
+            Tuple::point(0., 5., -10.),
+            Tuple::vector(0., -1., 2.).normalize(),
+        );
+        assert_eq!(world.color_at(&ray), Color::color(0., 0., 0.));
+
+        // A glowing ball above the floor, away from the ray
+        let mut lamp = Sphere::new();
+        lamp.transformation = Matrix::new_identity()
+            .scale(0.5, 0.5, 0.5)
+            .translate(0., 2., 0.);
+        lamp.material.emission = Color::color(4., 4., 4.);
+        world.objects.push(lamp);
+        let lit = world.color_at(&ray);
+        assert!(lit.red > 0.1, "{:?}", lit);
+        assert_eq!(lit.red, lit.blue);
+        assert_eq!(world.color_at(&ray), lit);
+
+        // A blocker between the lamp and the floor casts a shadow. The ray passes
+        // outside of it.
+        let mut blocker = Sphere::new();
+        blocker.transformation = Matrix::new_identity()
+            .scale(1.5, 0.1, 1.5)
+            .translate(0., 1., 0.);
+        world.objects.push(blocker);
+        assert_eq!(world.color_at(&ray), Color::color(0., 0., 0.));
+    }
+
+    // A floor lit from above, seen from above at the origin
+    fn floor_world() -> (World, Ray) {
+        let mut world = World::new();
+        world
+            .lights
+            .push(PointLight::new(Tuple::point(0., 10., 0.), Color::color(1., 1., 1.)).into());
+        let mut floor = Sphere::new();
+        floor.transformation = Matrix::new_identity().scale(20., 0.01, 20.);
+        floor.material.specular = 0.;
+        world.objects.push(floor);
+        let ray = Ray::new(Tuple::point(0., 1., 0.), Tuple::vector(0., -1., 0.));
+        return (world, ray);
+    }
+
+    #[test]
+    fn nearby_objects_occlude_the_ambient_light() {
+        let (mut world, ray) = floor_world();
+        let settings = AmbientOcclusion::default();
+        let intersections = ray.intersections_in_world(&world);
+        let computation = ray.prepare_computation(hit(&intersections).unwrap());
+        assert_eq!(world.unoccluded_fraction(&computation, &settings), 1.0);
+        let open = world.color_at(&ray);
+
+        // A ball next to the point, which does not block the light
+        let mut ball = Sphere::new();
+        ball.transformation = Matrix::new_identity()
+            .scale(0.5, 0.5, 0.5)
+            .translate(0.6, 0.6, 0.);
+        world.objects.push(ball);
+        let intersections = ray.intersections_in_world(&world);
+        let computation = ray.prepare_computation(hit(&intersections).unwrap());
+        let unoccluded = world.unoccluded_fraction(&computation, &settings);
+        assert!(unoccluded > 0.5 && unoccluded < 1.0, "{}", unoccluded);
+        let near = AmbientOcclusion {
+            distance: 0.2,
+            ..settings
+        };
+        assert_eq!(world.unoccluded_fraction(&computation, &near), 1.0);
+
+        // Only the ambient light is darker
+        assert_eq!(world.color_at(&ray), open);
+        world.ambient_occlusion = Some(settings);
+        let ambient = 0.1 * (1. - unoccluded);
+        assert_eq!(
+            world.color_at(&ray),
+            open - Color::color(ambient, ambient, ambient)
+        );
+    }
+
+    #[test]
+    fn reflective_material_reflects_the_skybox() {
+        // A skybox that is only lit behind the camera, in the -z direction
+        let sky = Color::color(0.4, 0.6, 1.0);
+        let face = |color: Color| {
+            let mut canvas = Canvas::new(1, 1);
+            canvas.write_pixel(0, 0, color);
+            return canvas;
+        };
+        let black = face(Color::color(0., 0., 0.));
+        let faces = [
+            black.clone(),
+            black.clone(),
+            black.clone(),
+            black.clone(),
+            black,
+            face(sky),
+        ];
+        let mut world = World::new();
+        world.environment = Some(Environment::CubeMap(CubeMap::new(faces).unwrap()));
+        let mut mirror = Sphere::new();
+        mirror.material.reflective = 1.0;
+        world.objects.push(mirror);
+
+        // Without lights only the reflection of the sky is seen
+        let ray = Ray::new(Tuple::point(0., 0., -5.), Tuple::vector(0., 0., 1.));
+        assert_eq!(world.color_at(&ray), sky);
+        let computation = ray.prepare_computation(&Intersection::new(4., &world.objects[0]));
+        assert_eq!(world.reflected_color(&computation, 1), sky);
+        assert_eq!(
+            world.reflected_color(&computation, 0),
+            Color::color(0., 0., 0.)
+        );
+
+        world.objects[0].material.reflective = 0.5;
+        assert_eq!(world.color_at(&ray), sky * 0.5);
+        world.objects[0].material.reflective = 0.0;
+        assert_eq!(world.color_at(&ray), Color::color(0., 0., 0.));
+    }
+
+    #[test]
+    fn transparent_material_refracts_the_skybox() {
+        // A skybox that is only lit in front of the camera, in the +z direction
+        let sky = Color::color(0.4, 0.6, 1.0);
+        let mut faces = [(); 6].map(|_| Canvas::new(1, 1));
+        faces[4].write_pixel(0, 0, sky);
+        let mut world = World::new();
+        world.environment = Some(Environment::CubeMap(CubeMap::new(faces).unwrap()));
+        let mut glass = Sphere::new();
+        glass.material.transparency = 1.0;
+        glass.material.refractive_index = 1.5;
+        world.objects.push(glass);
+
+        // Straight through the middle the sky behind the sphere is seen
+        let ray = Ray::new(Tuple::point(0., 0., -5.), Tuple::vector(0., 0., 1.));
+        assert_eq!(world.color_at(&ray), sky);
+        let computation = ray.prepare_computation(&Intersection::new(4., &world.objects[0]));
+        assert_eq!(
+            world.refracted_color(&computation, 0),
+            Color::color(0., 0., 0.)
+        );
+        // Half the light is lost going in and half of the rest going out
+        world.objects[0].material.transparency = 0.5;
+        assert_eq!(world.color_at(&ray), sky * 0.25);
+
+        // Inside the glass at a grazing angle all the light is reflected
+        let half = 2f64.sqrt() / 2.;
+        let ray = Ray::new(Tuple::point(0., 0., half), Tuple::vector(0., 1., 0.));
+        let intersections = vec![
+            Intersection::new(-half, &world.objects[0]),
+            Intersection::new(half, &world.objects[0]),
+        ];
+        let computation = ray.prepare_computation_in(&intersections[1], &intersections);
+        assert_eq!(
+            world.refracted_color(&computation, 5),
+            Color::color(0., 0., 0.)
+        );
+    }
+
+    #[test]
+    fn reflections_between_mirrors_end() {
+        // Inside a mirror sphere every reflected ray hits the sphere again
+        let mut world = World::new();
+        let mut mirror = Sphere::new();
+        mirror.material.reflective = 1.0;
+        mirror.material.emission = Color::color(0.1, 0.1, 0.1);
+        world.objects.push(mirror);
+        let ray = Ray::new(Tuple::point(0., 0., 0.), Tuple::vector(0., 1., 0.));
+        let expected = 0.1 * (World::MAX_REFLECTIONS + 1) as f64;
+        assert_eq!(
+            world.color_at(&ray),
+            Color::color(expected, expected, expected)
+        );
+    }
+
+    #[test]
+    fn ambient_occlusion_pass() {
+        let (world, ray) = floor_world();
+        assert_eq!(
+            world.occlusion_with_alpha_at(&ray),
+            (Color::color(1., 1., 1.), 1.)
+        );
+        let miss = Ray::new(Tuple::point(0., 1., 0.), Tuple::vector(0., 1., 0.));
+        assert_eq!(
+            world.occlusion_with_alpha_at(&miss),
+            (Color::color(0., 0., 0.), 0.)
+        );
+    }
 }
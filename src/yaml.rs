@@ -0,0 +1,594 @@
+use std::{error, fmt};
+
+// Module with a parser for the subset of YAML that is used by the scene files.
+//
+// Supported are block sequences ("- item"), block mappings ("key: value"), flow
+// sequences ("[1, 2, 3]"), flow mappings ("{ key: value }"), plain and quoted scalars,
+// and comments starting with '#'. Anchors, tags and multi-line scalars are not
+// supported. Every value remembers where it starts in the document, so errors found
+// while interpreting the values can point to the right line and column.
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Node {
+    Null,
+    Scalar(String),
+    Sequence(Vec<Value>),
+    // The entries are kept in the order of the document. The keys are scalars.
+    Mapping(Vec<(Value, Value)>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Value {
+    pub node: Node,
+    // The position of the value, both counted from 1
+    pub line: usize,
+    pub column: usize,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseError {
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "line {}, column {}: {}",
+            self.line, self.column, self.message
+        )
+    }
+}
+
+impl error::Error for ParseError {}
+
+impl Value {
+    fn new(node: Node, line: usize, column: usize) -> Value {
+        Value { node, line, column }
+    }
+
+    // An error located at this value
+    pub fn error(&self, message: String) -> ParseError {
+        return ParseError {
+            line: self.line,
+            column: self.column,
+            message,
+        };
+    }
+
+    pub fn as_str(&self) -> Result<&str, ParseError> {
+        match &self.node {
+            Node::Scalar(text) => Ok(text),
+            _ => Err(self.error("expected a string".to_string())),
+        }
+    }
+
+    pub fn as_f64(&self) -> Result<f64, ParseError> {
+        return self
+            .as_str()
+            .ok()
+            .and_then(|text| text.parse::<f64>().ok())
+            .filter(|number| number.is_finite())
+            .ok_or_else(|| self.error("expected a number".to_string()));
+    }
+
+    pub fn as_usize(&self) -> Result<usize, ParseError> {
+        return self
+            .as_str()
+            .ok()
+            .and_then(|text| text.parse::<usize>().ok())
+            .ok_or_else(|| self.error("expected a non-negative integer".to_string()));
+    }
+
+    pub fn as_bool(&self) -> Result<bool, ParseError> {
+        match self.as_str() {
+            Ok("true") => Ok(true),
+            Ok("false") => Ok(false),
+            _ => Err(self.error("expected true or false".to_string())),
+        }
+    }
+
+    pub fn as_sequence(&self) -> Result<&[Value], ParseError> {
+        match &self.node {
+            Node::Sequence(items) => Ok(items),
+            _ => Err(self.error("expected a sequence".to_string())),
+        }
+    }
+
+    pub fn as_mapping(&self) -> Result<&[(Value, Value)], ParseError> {
+        match &self.node {
+            Node::Mapping(entries) => Ok(entries),
+            _ => Err(self.error("expected a mapping".to_string())),
+        }
+    }
+
+    // Look up a key in a mapping. Returns None for other kinds of values.
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        match &self.node {
+            Node::Mapping(entries) => entries
+                .iter()
+                .find(|(k, _)| k.node == Node::Scalar(key.to_string()))
+                .map(|(_, value)| value),
+            _ => None,
+        }
+    }
+}
+
+// A line with content, without the indentation and a trailing comment
+#[derive(Debug, Clone, Copy)]
+struct Line<'a> {
+    number: usize,
+    indent: usize,
+    text: &'a str,
+}
+
+impl Line<'_> {
+    fn error(&self, offset: usize, message: String) -> ParseError {
+        return ParseError {
+            line: self.number,
+            column: self.indent + offset + 1,
+            message,
+        };
+    }
+
+    fn is_sequence_item(&self) -> bool {
+        return self.text == "-" || self.text.starts_with("- ");
+    }
+}
+
+// Parse a document into a value
+pub fn parse(text: &str) -> Result<Value, ParseError> {
+    let mut parser = Parser {
+        lines: split_lines(text)?,
+        pos: 0,
+    };
+    let Some(first) = parser.lines.first().copied() else {
+        return Ok(Value::new(Node::Null, 1, 1));
+    };
+    let value = parser.block(first.indent)?;
+    if let Some(line) = parser.lines.get(parser.pos) {
+        return Err(line.error(0, "expected the end of the document".to_string()));
+    }
+    return Ok(value);
+}
+
+fn split_lines(text: &str) -> Result<Vec<Line<'_>>, ParseError> {
+    let mut lines = Vec::new();
+    for (index, line) in text.lines().enumerate() {
+        let number = index + 1;
+        let content = line.trim_start_matches(' ');
+        let indent = line.len() - content.len();
+        if content.starts_with('\t') {
+            return Err(ParseError {
+                line: number,
+                column: indent + 1,
+                message: "tabs are not allowed in the indentation".to_string(),
+            });
+        }
+        let content = strip_comment(content).trim_end();
+        match content {
+            "" | "---" => continue,
+            // End of the document
+            "..." => break,
+            _ => lines.push(Line {
+                number,
+                indent,
+                text: content,
+            }),
+        }
+    }
+    return Ok(lines);
+}
+
+// A comment starts with '#' at the start of the line or after a space, outside of
+// quoted strings
+fn strip_comment(text: &str) -> &str {
+    let mut quote = None;
+    let mut previous = ' ';
+    for (i, c) in text.char_indices() {
+        match quote {
+            Some(q) if c == q => quote = None,
+            Some(_) => {}
+            None if c == '"' || c == '\'' => quote = Some(c),
+            None if c == '#' && previous.is_whitespace() => return &text[..i],
+            None => {}
+        }
+        previous = c;
+    }
+    return text;
+}
+
+struct Parser<'a> {
+    lines: Vec<Line<'a>>,
+    pos: usize,
+}
+
+impl Parser<'_> {
+    // A block is a sequence, a mapping or a single scalar where all lines at the top
+    // level have the same indentation
+    fn block(&mut self, indent: usize) -> Result<Value, ParseError> {
+        let line = self.lines[self.pos];
+        if line.is_sequence_item() {
+            return self.sequence(indent);
+        }
+        if split_key(line.text).is_some() {
+            return self.mapping(indent);
+        }
+        self.pos += 1;
+        return flow(line.text, line.number, line.indent + 1);
+    }
+
+    fn sequence(&mut self, indent: usize) -> Result<Value, ParseError> {
+        let start = self.lines[self.pos];
+        let mut items = Vec::new();
+        while let Some(line) = self.lines.get(self.pos).copied() {
+            if line.indent != indent || !line.is_sequence_item() {
+                if line.indent > indent {
+                    return Err(line.error(0, "unexpected indentation".to_string()));
+                }
+                break;
+            }
+            let rest = line.text[1..].trim_start();
+            if rest.is_empty() {
+                // The item is on the following, more indented lines
+                self.pos += 1;
+                items.push(match self.lines.get(self.pos) {
+                    Some(next) if next.indent > indent => self.block(next.indent)?,
+                    _ => Value::new(Node::Null, line.number, line.indent + 1),
+                });
+            } else {
+                // The item starts on the same line as the dash. Treat the rest of the
+                // line as a line of its own, so a mapping can continue on the next
+                // lines with the same indentation.
+                let offset = line.text.len() - rest.len();
+                self.lines[self.pos] = Line {
+                    number: line.number,
+                    indent: line.indent + offset,
+                    text: rest,
+                };
+                items.push(self.block(line.indent + offset)?);
+            }
+        }
+        return Ok(Value::new(
+            Node::Sequence(items),
+            start.number,
+            start.indent + 1,
+        ));
+    }
+
+    fn mapping(&mut self, indent: usize) -> Result<Value, ParseError> {
+        let start = self.lines[self.pos];
+        let mut entries: Vec<(Value, Value)> = Vec::new();
+        while let Some(line) = self.lines.get(self.pos).copied() {
+            if line.indent != indent || line.is_sequence_item() {
+                if line.indent > indent {
+                    return Err(line.error(0, "unexpected indentation".to_string()));
+                }
+                break;
+            }
+            let Some((key, rest)) = split_key(line.text) else {
+                return Err(line.error(0, "expected 'key: value'".to_string()));
+            };
+            let key = flow(key, line.number, line.indent + 1)?;
+            if !matches!(key.node, Node::Scalar(_)) {
+                return Err(key.error("a key must be a scalar".to_string()));
+            }
+            if entries.iter().any(|(k, _)| k.node == key.node) {
+                return Err(key.error(format!("duplicate key '{}'", key.as_str()?)));
+            }
+            self.pos += 1;
+
+            let value = if rest.is_empty() {
+                match self.lines.get(self.pos).copied() {
+                    Some(next) if next.indent > indent => self.block(next.indent)?,
+                    // A sequence may have the same indentation as its key
+                    Some(next) if next.indent == indent && next.is_sequence_item() => {
+                        self.sequence(indent)?
+                    }
+                    _ => Value::new(Node::Null, line.number, line.indent + 1),
+                }
+            } else {
+                let column = line.indent + line.text.len() - rest.len() + 1;
+                flow(rest, line.number, column)?
+            };
+            entries.push((key, value));
+        }
+        return Ok(Value::new(
+            Node::Mapping(entries),
+            start.number,
+            start.indent + 1,
+        ));
+    }
+}
+
+// Split "key: value" into the key and the value. The colon must be followed by a
+// space or end the line, and must not be inside quotes or brackets.
+fn split_key(text: &str) -> Option<(&str, &str)> {
+    let mut quote = None;
+    let mut depth = 0;
+    let bytes = text.as_bytes();
+    for (i, c) in text.char_indices() {
+        match quote {
+            Some(q) if c == q => quote = None,
+            Some(_) => {}
+            None => match c {
+                '"' | '\'' => quote = Some(c),
+                '[' | '{' => depth += 1,
+                ']' | '}' => depth -= 1,
+                ':' if depth == 0 && (i + 1 == bytes.len() || bytes[i + 1] == b' ') => {
+                    return Some((text[..i].trim_end(), text[i + 1..].trim_start()));
+                }
+                _ => {}
+            },
+        }
+    }
+    return None;
+}
+
+// Parse a value written on a single line, which may be a flow sequence or mapping
+fn flow(text: &str, line: usize, column: usize) -> Result<Value, ParseError> {
+    let mut parser = FlowParser {
+        text,
+        pos: 0,
+        line,
+        column,
+    };
+    let value = parser.value(false)?;
+    parser.skip_spaces();
+    if parser.pos < text.len() {
+        return Err(parser.error("unexpected characters after the value".to_string()));
+    }
+    return Ok(value);
+}
+
+struct FlowParser<'a> {
+    text: &'a str,
+    pos: usize,
+    line: usize,
+    // The column of the first character of the text
+    column: usize,
+}
+
+impl FlowParser<'_> {
+    fn error(&self, message: String) -> ParseError {
+        return ParseError {
+            line: self.line,
+            column: self.column + self.pos,
+            message,
+        };
+    }
+
+    fn peek(&self) -> Option<char> {
+        return self.text[self.pos..].chars().next();
+    }
+
+    fn skip_spaces(&mut self) {
+        while self.peek() == Some(' ') {
+            self.pos += 1;
+        }
+    }
+
+    // Inside brackets a plain scalar ends at ',', ']', '}' and ': '
+    fn value(&mut self, nested: bool) -> Result<Value, ParseError> {
+        self.skip_spaces();
+        let (line, column) = (self.line, self.column + self.pos);
+        match self.peek() {
+            Some('[') => {
+                self.pos += 1;
+                let items = self.items(']', |parser| parser.value(true))?;
+                return Ok(Value::new(Node::Sequence(items), line, column));
+            }
+            Some('{') => {
+                self.pos += 1;
+                let entries = self.items('}', |parser| {
+                    let key = parser.value(true)?;
+                    parser.skip_spaces();
+                    if parser.peek() != Some(':') {
+                        return Err(parser.error("expected ':'".to_string()));
+                    }
+                    parser.pos += 1;
+                    return Ok((key, parser.value(true)?));
+                })?;
+                return Ok(Value::new(Node::Mapping(entries), line, column));
+            }
+            Some(quote @ ('"' | '\'')) => {
+                let text = self.quoted(quote)?;
+                return Ok(Value::new(Node::Scalar(text), line, column));
+            }
+            _ => {}
+        }
+
+        let start = self.pos;
+        while let Some(c) = self.peek() {
+            if nested && matches!(c, ',' | ']' | '}') {
+                break;
+            }
+            let after = &self.text[self.pos + c.len_utf8()..];
+            if nested && c == ':' && (after.is_empty() || after.starts_with([' ', ',', '}'])) {
+                break;
+            }
+            self.pos += c.len_utf8();
+        }
+        let text = self.text[start..self.pos].trim_end();
+        if text.is_empty() {
+            return Ok(Value::new(Node::Null, line, column));
+        }
+        if !nested && matches!(text.chars().next(), Some(']' | '}')) {
+            return Err(self.error(format!("unexpected '{}'", &text[..1])));
+        }
+        return Ok(Value::new(Node::Scalar(text.to_string()), line, column));
+    }
+
+    // Comma separated items up to the closing bracket
+    fn items<T, F>(&mut self, close: char, mut item: F) -> Result<Vec<T>, ParseError>
+    where
+        F: FnMut(&mut Self) -> Result<T, ParseError>,
+    {
+        let mut items = Vec::new();
+        loop {
+            self.skip_spaces();
+            if self.peek() == Some(close) {
+                self.pos += 1;
+                return Ok(items);
+            }
+            items.push(item(self)?);
+            self.skip_spaces();
+            match self.peek() {
+                Some(',') => self.pos += 1,
+                Some(c) if c == close => {}
+                Some(c) => {
+                    return Err(self.error(format!("expected ',' or '{}', found '{}'", close, c)))
+                }
+                None => return Err(self.error(format!("missing '{}'", close))),
+            }
+        }
+    }
+
+    // A quoted scalar. Double quoted strings support backslash escapes, in single
+    // quoted strings a quote is written as two quotes.
+    fn quoted(&mut self, quote: char) -> Result<String, ParseError> {
+        let start = self.pos;
+        self.pos += 1;
+        let mut text = String::new();
+        let mut chars = self.text[self.pos..].char_indices();
+        while let Some((i, c)) = chars.next() {
+            if c == quote {
+                if quote == '\'' && self.text[self.pos + i + 1..].starts_with('\'') {
+                    chars.next();
+                    text.push('\'');
+                    continue;
+                }
+                self.pos += i + 1;
+                return Ok(text);
+            }
+            if c == '\\' && quote == '"' {
+                match chars.next() {
+                    Some((_, 'n')) => text.push('\n'),
+                    Some((_, 't')) => text.push('\t'),
+                    Some((_, escaped @ ('"' | '\\' | '/'))) => text.push(escaped),
+                    Some((j, other)) => {
+                        self.pos += j;
+                        return Err(self.error(format!("unknown escape '\\{}'", other)));
+                    }
+                    None => break,
+                }
+                continue;
+            }
+            text.push(c);
+        }
+        self.pos = start;
+        return Err(self.error("unterminated string".to_string()));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scalar(value: &Value) -> &str {
+        return value.as_str().unwrap();
+    }
+
+    #[test]
+    fn parse_scalars() {
+        assert_eq!(scalar(&parse("hello world").unwrap()), "hello world");
+        assert_eq!(parse("42").unwrap().as_f64(), Ok(42.0));
+        assert_eq!(scalar(&parse("\"a # b\\n\"").unwrap()), "a # b\n");
+        assert_eq!(scalar(&parse("'it''s'").unwrap()), "it's");
+        assert_eq!(parse("").unwrap().node, Node::Null);
+        assert_eq!(parse("# only a comment\n").unwrap().node, Node::Null);
+        // Plain scalars with characters of more than one byte
+        assert_eq!(scalar(&parse("café").unwrap()), "café");
+        let value = parse("name: café\nitems: [naïve, {ü: 1}]\n").unwrap();
+        assert_eq!(scalar(value.get("name").unwrap()), "café");
+        let items = value.get("items").unwrap().as_sequence().unwrap();
+        assert_eq!(scalar(&items[0]), "naïve");
+        assert_eq!(scalar(items[1].get("ü").unwrap()), "1");
+    }
+
+    #[test]
+    fn parse_block_mapping() {
+        let value = parse("width: 100\nheight: 50 # pixels\nname: 'test'\n").unwrap();
+        assert_eq!(value.get("width").unwrap().as_usize(), Ok(100));
+        assert_eq!(value.get("height").unwrap().as_f64(), Ok(50.0));
+        assert_eq!(scalar(value.get("name").unwrap()), "test");
+        assert!(value.get("depth").is_none());
+        let height = value.get("height").unwrap();
+        assert_eq!((height.line, height.column), (2, 9));
+    }
+
+    #[test]
+    fn parse_flow_collections() {
+        let value = parse("[1, [2, 3], {a: 4, b: x y}, ]").unwrap();
+        let items = value.as_sequence().unwrap();
+        assert_eq!(items.len(), 3);
+        assert_eq!(scalar(&items[0]), "1");
+        assert_eq!(scalar(&items[1].as_sequence().unwrap()[1]), "3");
+        assert_eq!(scalar(items[2].get("b").unwrap()), "x y");
+        assert_eq!(items[2].get("a").unwrap().column, 17);
+    }
+
+    #[test]
+    fn parse_nested_blocks() {
+        let text = "
+- add: camera
+  from: [ 0, 1.5, -5 ]
+- add: sphere
+  material:
+    color: [1, 0, 0]
+  transform:
+  - [ scale, 2, 2, 2 ]
+  - [ translate, 0, 1, 0 ]
+-
+  add: light
+";
+        let value = parse(text).unwrap();
+        let items = value.as_sequence().unwrap();
+        assert_eq!(items.len(), 3);
+        assert_eq!(scalar(items[0].get("add").unwrap()), "camera");
+        assert_eq!(
+            items[0].get("from").unwrap().as_sequence().unwrap().len(),
+            3
+        );
+        let sphere = &items[1];
+        let color = sphere.get("material").unwrap().get("color").unwrap();
+        assert_eq!((color.line, color.column), (6, 12));
+        let transform = sphere.get("transform").unwrap().as_sequence().unwrap();
+        assert_eq!(transform.len(), 2);
+        assert_eq!(scalar(&transform[1].as_sequence().unwrap()[0]), "translate");
+        assert_eq!(scalar(items[2].get("add").unwrap()), "light");
+        assert_eq!((items[1].line, items[1].column), (4, 3));
+    }
+
+    #[test]
+    fn errors_have_line_and_column() {
+        let error = parse("a: 1\n  b: 2\n").unwrap_err();
+        assert_eq!((error.line, error.column), (2, 3));
+
+        let error = parse("a: [1, 2\n").unwrap_err();
+        assert_eq!((error.line, error.column), (1, 9));
+        assert_eq!(error.message, "missing ']'");
+
+        let error = parse("- a\nb: 1\n").unwrap_err();
+        assert_eq!((error.line, error.column), (2, 1));
+
+        let error = parse("a: 1\na: 2\n").unwrap_err();
+        assert_eq!(error.to_string(), "line 2, column 1: duplicate key 'a'");
+
+        let error = parse("a:\n\t- 1\n").unwrap_err();
+        assert_eq!((error.line, error.column), (2, 1));
+
+        let error = parse("a: \"abc\n").unwrap_err();
+        assert_eq!((error.line, error.column), (1, 4));
+    }
+
+    #[test]
+    fn conversion_errors_point_at_the_value() {
+        let value = parse("width: wide\n").unwrap();
+        let error = value.get("width").unwrap().as_usize().unwrap_err();
+        assert_eq!((error.line, error.column), (1, 8));
+        assert!(value.as_sequence().is_err());
+    }
+}
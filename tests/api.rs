@@ -0,0 +1,78 @@
+#![allow(clippy::needless_return)]
+
+// The types that appear in public fields and signatures can be named outside the crate
+use ray_tracer::{
+    hit, parse_scene, Attenuation, Canvas, Color, ColorU8, Computation, CubeMap, Environment,
+    EnvironmentMap, Face, LightSample, LightSource, OutOfBounds, ParseError, PointLight, Ray,
+    SceneError, ShapeLight, Sphere, Tuple, World,
+};
+
+fn shade(world: &World, computation: &Computation) -> Color {
+    return world.shade_hit(computation);
+}
+
+#[test]
+fn configure_light_attenuation() {
+    let mut light = PointLight::new(Tuple::point(0., 0., -2.), Color::color(1., 1., 1.));
+    light.attenuation = Attenuation::InverseSquare;
+    let samples: Vec<LightSample> = light.samples(&Tuple::point(0., 0., 0.));
+    assert_eq!(samples.len(), 1);
+    assert_eq!(samples[0].intensity, Color::color(0.25, 0.25, 0.25));
+
+    let mut world = World::new();
+    world.lights.push(light.into());
+    world.objects.push(Sphere::new());
+    let ray = Ray::new(Tuple::point(0., 0., -5.), Tuple::vector(0., 0., 1.));
+    let intersections = ray.intersections_in_world(&world);
+    let computation = ray.prepare_computation(hit(&intersections).unwrap());
+    assert!(shade(&world, &computation).red > 0.0);
+}
+
+#[test]
+fn build_environments() {
+    let mut image = Canvas::new(2, 1);
+    image.write_pixel(0, 0, Color::color(1., 0., 0.));
+    let map = EnvironmentMap::new(image.clone());
+    assert!(matches!(Environment::Map(map), Environment::Map(_)));
+
+    let faces = [(); 6].map(|_| image.clone());
+    let cube = CubeMap::new(faces).unwrap();
+    let face: Face = Face::ALL[0];
+    assert_eq!(face, Face::Right);
+    assert!(matches!(
+        Environment::CubeMap(cube),
+        Environment::CubeMap(_)
+    ));
+}
+
+#[test]
+fn emissive_shapes_are_lights() {
+    let mut lamp = Sphere::new();
+    lamp.material.emission = Color::color(1., 1., 1.);
+    let light = ShapeLight {
+        shape: &lamp,
+        samples: 4,
+        seed: 1,
+    };
+    assert_eq!(light.samples(&Tuple::point(0., 0., -5.)).len(), 4);
+}
+
+#[test]
+fn canvas_and_color_types() {
+    let canvas = Canvas::new(2, 2);
+    let error: OutOfBounds = canvas.pixel(2, 0).unwrap_err();
+    assert_eq!((error.x, error.y), (2, 0));
+    let color: ColorU8 = Color::color(1., 0.5, 0.).normalize_u8();
+    assert_eq!(color.red, 255);
+}
+
+#[test]
+fn match_on_parse_errors() {
+    let error: ParseError = parse_scene("- add: cube\n").unwrap_err();
+    assert_eq!(error.line, 1);
+    let error = SceneError::Parse(error);
+    assert!(matches!(
+        error,
+        SceneError::Parse(ParseError { line: 1, .. })
+    ));
+}
@@ -0,0 +1,62 @@
+#![allow(clippy::needless_return)]
+
+// Save and load images using only the public API of the library
+use ray_tracer::output::{self, Format, Operator, OutputTransform};
+use ray_tracer::{Canvas, Color};
+use std::fs;
+use std::path::PathBuf;
+
+fn temp_path(name: &str) -> PathBuf {
+    return std::env::temp_dir().join(format!("ray-tracer-{}-{}", std::process::id(), name));
+}
+
+fn test_canvas() -> Canvas {
+    let mut canvas = Canvas::new(4, 3);
+    canvas.write_pixel(0, 0, Color::color(1.0, 0.0, 0.0));
+    canvas.write_pixel(3, 2, Color::color(0.0, 0.5, 4.0));
+    return canvas;
+}
+
+#[test]
+fn floating_point_formats_round_trip() {
+    for name in ["image.pfm", "image.hdr"] {
+        let path = temp_path(name);
+        output::save(&test_canvas(), &path).unwrap();
+        let loaded = output::load(&path);
+        fs::remove_file(&path).unwrap();
+
+        let loaded = loaded.unwrap();
+        assert_eq!((loaded.width, loaded.height), (4, 3));
+        let color = loaded.read_pixel(3, 2).unwrap();
+        assert!((color.blue - 4.0).abs() < 0.05, "{}: {:?}", name, color);
+    }
+}
+
+#[test]
+fn ppm_round_trip_clamps_colors() {
+    let path = temp_path("image.ppm");
+    output::save(&test_canvas(), &path).unwrap();
+    let loaded = output::load(&path);
+    fs::remove_file(&path).unwrap();
+
+    let loaded = loaded.unwrap();
+    assert_eq!(loaded.read_pixel(0, 0), Some(Color::color(1.0, 0.0, 0.0)));
+    let color = loaded.read_pixel(3, 2).unwrap();
+    assert_eq!(color.blue, 1.0);
+}
+
+#[test]
+fn save_png_with_a_format_and_transform() {
+    let path = temp_path("image.out");
+    let transform = OutputTransform {
+        exposure: -1.0,
+        operator: Operator::Reinhard,
+        srgb: true,
+    };
+    let format = Format::from_name("png").unwrap();
+    output::save_with(&test_canvas(), &path, format, &transform).unwrap();
+    let data = fs::read(&path);
+    fs::remove_file(&path).unwrap();
+
+    assert!(data.unwrap().starts_with(b"\x89PNG\r\n\x1a\n"));
+}
@@ -0,0 +1,80 @@
+#![allow(clippy::needless_return)]
+
+// Render worlds using only the public API of the library
+use ray_tracer::{Camera, Color, Material, Matrix, PointLight, Ray, Sphere, Tuple, World};
+use std::f64;
+
+fn approx_eq(lhs: Color, rhs: Color) -> bool {
+    return (lhs.red - rhs.red).abs() < 0.0001
+        && (lhs.green - rhs.green).abs() < 0.0001
+        && (lhs.blue - rhs.blue).abs() < 0.0001;
+}
+
+// The default world of the book: a light and two concentric spheres
+fn default_world() -> World {
+    let mut world = World::new();
+    world
+        .lights
+        .push(PointLight::new(Tuple::point(-10., 10., -10.), Color::color(1., 1., 1.)).into());
+
+    let mut outer = Sphere::new();
+    outer.material = Material {
+        color: Color::color(0.8, 1.0, 0.6),
+        diffuse: 0.7,
+        specular: 0.2,
+        ..Material::new()
+    };
+    world.objects.push(outer);
+
+    let mut inner = Sphere::new();
+    inner.transformation = Matrix::new_identity().scale(0.5, 0.5, 0.5);
+    world.objects.push(inner);
+    return world;
+}
+
+fn camera(size: usize) -> Camera {
+    let mut camera = Camera::new(size, size, f64::consts::PI / 2.);
+    camera.set_view_transformation(
+        &Tuple::point(0., 0., -5.),
+        &Tuple::point(0., 0., 0.),
+        &Tuple::vector(0., 1., 0.),
+    );
+    return camera;
+}
+
+#[test]
+fn color_where_a_ray_hits() {
+    let world = default_world();
+    let ray = Ray::new(Tuple::point(0., 0., -5.), Tuple::vector(0., 0., 1.));
+    assert!(approx_eq(
+        world.color_at(&ray),
+        Color::color(0.38066, 0.47583, 0.2855)
+    ));
+}
+
+#[test]
+fn render_a_world() {
+    let image = camera(11).render(&default_world());
+    assert_eq!(image.width, 11);
+    assert_eq!(image.height, 11);
+    assert!(approx_eq(
+        image.read_pixel(5, 5).unwrap(),
+        Color::color(0.38066, 0.47583, 0.2855)
+    ));
+    // The corners are outside the spheres
+    assert_eq!(image.read_alpha(0, 0), Some(0.0));
+}
+
+#[test]
+fn threads_and_samples() {
+    let world = default_world();
+    let mut camera = camera(15);
+    camera.threads = 3;
+    let image = camera.render(&world);
+    camera.threads = 1;
+    assert!(image.pixels().eq(camera.render(&world).pixels()));
+
+    camera.samples = 4;
+    let smooth = camera.render(&world);
+    assert_eq!(smooth.read_alpha(7, 7), Some(1.0));
+}
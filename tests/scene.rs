@@ -0,0 +1,59 @@
+#![allow(clippy::needless_return)]
+
+// Build scenes from scene descriptions using only the public API of the library
+use ray_tracer::{load_scene, parse_scene, Color, PointLight, SceneError, Tuple};
+
+const SCENE: &str = "
+- add: camera
+  width: 40
+  height: 20
+  field-of-view: 1.0
+  from: [0, 0, -5]
+  to: [0, 0, 0]
+
+- add: light
+  at: [-10, 10, -10]
+  intensity: [1, 1, 1]
+
+- define: red
+  value:
+    color: [1, 0, 0]
+
+- add: sphere
+  material:
+    extend: red
+    specular: 0
+";
+
+#[test]
+fn parse_and_render_a_scene() {
+    let scene = parse_scene(SCENE).unwrap();
+    assert_eq!(
+        scene.world.lights,
+        vec![PointLight::new(Tuple::point(-10., 10., -10.), Color::color(1., 1., 1.)).into()]
+    );
+    let material = &scene.world.objects[0].material;
+    assert_eq!(material.color, Color::color(1., 0., 0.));
+    assert_eq!(material.specular, 0.);
+
+    let image = scene.camera.render(&scene.world);
+    assert_eq!((image.width, image.height), (40, 20));
+    let center = image.read_pixel(20, 10).unwrap();
+    assert!(center.red > 0.5 && center.green == 0. && center.blue == 0.);
+}
+
+#[test]
+fn scene_errors() {
+    let error = parse_scene("- add: camera\n  width: ten\n").unwrap_err();
+    assert_eq!((error.line, error.column), (2, 10));
+    assert!(matches!(
+        load_scene("does-not-exist.yml"),
+        Err(SceneError::Io(_))
+    ));
+}
+
+#[test]
+fn example_scenes_load() {
+    let scene = load_scene("scenes/world.yml").unwrap();
+    assert_eq!(scene.world.objects.len(), 6);
+}